@@ -1,8 +1,10 @@
 //! A module for compiling Cardinal IR to functioning C code.
 
-use cardinal_codegen::entities::{AbiType, Named, NamedProperty, Type, Value, ValueInfo};
-use cardinal_codegen::function::{Function};
-use cardinal_codegen::instruction::{InstructionInfo, InstBlock, Opcode};
+use cardinal_codegen::data::{Constant, GlobalData};
+use cardinal_codegen::entities::{Block, Linkage, Named, NamedProperty, StringEncoding, Type, Value, ValueInfo};
+use cardinal_codegen::error::CodegenError;
+use cardinal_codegen::function::{Function, ValueTable};
+use cardinal_codegen::instruction::{BlockType, InstructionInfo, InstBlock, Opcode};
 use cardinal_codegen::module::Module;
 
 /// Cardinal's C backend for the code generator.
@@ -14,6 +16,10 @@ pub struct CBackend {
     /// A list of C header files to include at compile time.
     imports: Vec<String>,
 
+    /// Whether the generated code may use GNU C extensions, which GCC and Clang support.  This
+    /// allows dense `switch` instructions to be compiled into tables of label addresses.
+    pub gnu_extensions: bool,
+
 }
 
 impl CBackend {
@@ -23,253 +29,1566 @@ impl CBackend {
         Self {
             module,
             imports: vec![],
+            gnu_extensions: false,
         }
     }
     
-    /// Displays an instruction.
-    fn display_instruction(&self, inst: &InstructionInfo, block: &InstBlock) -> String {
-        match inst.opcode {
-            Opcode::Add => {
-                self.display_value(inst.arguments[0], block) + " + " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::Sub => {
-                self.display_value(inst.arguments[0], block) + " - " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::Mul => {
-                self.display_value(inst.arguments[0], block) + " * " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::Div => {
-                self.display_value(inst.arguments[0], block) + " / " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::Mod => {
-                self.display_value(inst.arguments[0], block) + " % " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::BitAnd => {
-                self.display_value(inst.arguments[0], block) + " & " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::BitOr => {
-                self.display_value(inst.arguments[0], block) + " | " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::BitXor => {
-                self.display_value(inst.arguments[0], block) + " ^ " + &self.display_value(inst.arguments[1], block)
-            },
+    /// Displays an instruction, adding parentheses around operands where C's precedence rules
+    /// need them.
+    fn display_instruction(&self, inst: &InstructionInfo, scope: &Scope) -> Result<String, CodegenError> {
+        let prec = precedence(inst.opcode);
+        let operand = |i: usize, strict: bool| {
+            self.display_operand(scope.argument(inst, i)?, scope, prec, strict)
+        };
+
+        // Binary operators are left associative, so the right operand also needs parentheses
+        // when it has the same precedence, as in `a - (b - c)`.
+        let binary = |op: &str| -> Result<String, CodegenError> {
+            Ok(format!("{} {} {}", operand(0, false)?, op, operand(1, true)?))
+        };
+
+        let inst = match inst.opcode {
+            Opcode::Add => binary("+")?,
+            Opcode::Sub => binary("-")?,
+            Opcode::Mul => binary("*")?,
+            Opcode::Div => binary("/")?,
+            Opcode::Mod => binary("%")?,
+            Opcode::BitAnd => binary("&")?,
+            Opcode::BitOr => binary("|")?,
+            Opcode::BitXor => binary("^")?,
             Opcode::BitNot => {
-                "~".to_string() + &self.display_value(inst.arguments[0], block)
-            },
-            Opcode::BitLeft => {
-                self.display_value(inst.arguments[0], block) + " << " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::BitRight => {
-                self.display_value(inst.arguments[0], block) + " >> " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::TestEq => {
-                self.display_value(inst.arguments[0], block) + " == " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::TestNeq => {
-                self.display_value(inst.arguments[0], block) + " != " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::TestGt => {
-                self.display_value(inst.arguments[0], block) + " > " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::TestGtEq => {
-                self.display_value(inst.arguments[0], block) + " >= " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::TestLt => {
-                self.display_value(inst.arguments[0], block) + " < " + &self.display_value(inst.arguments[1], block)
-            },
-            Opcode::TestLtEq => {
-                self.display_value(inst.arguments[0], block) + " <= " + &self.display_value(inst.arguments[1], block)
+                "~".to_string() + &operand(0, false)?
             },
+            Opcode::BitLeft => binary("<<")?,
+            Opcode::BitRight => binary(">>")?,
+            Opcode::TestEq => binary("==")?,
+            Opcode::TestNeq => binary("!=")?,
+            Opcode::TestGt => binary(">")?,
+            Opcode::TestGtEq => binary(">=")?,
+            Opcode::TestLt => binary("<")?,
+            Opcode::TestLtEq => binary("<=")?,
             Opcode::Not => {
-                "!".to_string() + &self.display_value(inst.arguments[0], block)
+                "!".to_string() + &operand(0, false)?
             },
-            Opcode::Or => {
-                self.display_value(inst.arguments[0], block) + " || " + &self.display_value(inst.arguments[1], block)
+            Opcode::Or => binary("||")?,
+            Opcode::And => binary("&&")?,
+            Opcode::Jmp => {
+                "goto ".to_string() + &self.display_value(scope.argument(inst, 0)?, scope)?
             },
-            Opcode::And => {
-                self.display_value(inst.arguments[0], block) + " && " + &self.display_value(inst.arguments[1], block)
+            Opcode::Switch => {
+                // A `switch` is lowered by `compile_switch`, which needs to check its cases.
+                return Err(CodegenError::NotAnExpression {
+                    function: scope.function.to_string(),
+                    block: scope.path.to_string(),
+                    opcode: inst.opcode,
+                });
             },
-            Opcode::Jmp => {
-                "goto".to_string() + &self.display_value(inst.arguments[0], block)
+            Opcode::Brif => {
+                format!(
+                    "if ({}) goto {}; else goto {}",
+                    self.display_value(scope.argument(inst, 0)?, scope)?,
+                    self.display_value(scope.argument(inst, 1)?, scope)?,
+                    self.display_value(scope.argument(inst, 2)?, scope)?
+                )
             },
             Opcode::Set => {
-                self.display_value(inst.arguments[0], block) + " = " + &self.display_value(inst.arguments[1], block)
+                // Assignment is right associative, so `a = b = c` needs no parentheses.
+                operand(0, true)? + " = " + &operand(1, false)?
             },
             Opcode::Call => {
                 let mut args = vec![];
 
-                let mut i = 1;
-                while i < inst.arguments.len() {
-                    args.push(self.display_value(inst.arguments[i], block));
-                    i += 1;
+                for arg in inst.arguments.iter().skip(1) {
+                    args.push(self.display_value(*arg, scope)?);
                 }
 
-                self.display_value(inst.arguments[0], block) + "(" + &args.join(", ") + ")"
+                operand(0, false)? + "(" + &args.join(", ") + ")"
+            },
+            Opcode::Ret => match inst.arguments.first() {
+                Some(v) => "return ".to_string() + &self.display_value(*v, scope)?,
+                None => "return".to_string(),
             },
-            Opcode::Ret => {
-                "return ".to_string() + &self.display_value(inst.arguments[0], block)
+            Opcode::Break => {
+                "break".to_string()
             },
+            Opcode::Continue => {
+                "continue".to_string()
+            },
+            Opcode::Enter => {
+                self.display_value(scope.argument(inst, 0)?, scope)?
+            },
+            Opcode::Unreachable => {
+                // Reaching the end of the block is a bug in the program, so stop it rather
+                // than leaving the behaviour undefined.
+                "abort()".to_string()
+            },
+        };
+
+        Ok(inst)
+    }
+
+    /// Displays a value as an operand of an operator with the given precedence, wrapping it in
+    /// parentheses if it binds more loosely than the operator.  A `strict` operand is also
+    /// wrapped when it has the same precedence.
+    fn display_operand(&self, val: Value, scope: &Scope, prec: u8, strict: bool) -> Result<String, CodegenError> {
+        let expr = self.display_value(val, scope)?;
+
+        let inner = match scope.value(val)? {
+            ValueInfo::Instruction(inst) if scope.temps[val.0 as usize].is_none() => {
+                precedence(inst.opcode)
+            },
+            _ => PRIMARY,
+        };
+
+        if inner < prec || (strict && inner == prec) {
+            Ok(format!("({})", expr))
+        } else {
+            Ok(expr)
         }
     }
 
-    /// Displays a value from a block.
-    fn display_value(&self, val: Value, block: &InstBlock) -> String {
-        let v = &block.values[val.0 as usize];
+    /// Displays a value from a block, or the temporary that it was stored in.
+    fn display_value(&self, val: Value, scope: &Scope) -> Result<String, CodegenError> {
+        if let Some(Some(temp)) = scope.temps.get(val.0 as usize) {
+            return Ok(temp.to_string());
+        }
 
-        match v {
+        let value = match scope.value(val)? {
             ValueInfo::Block(b) => {
                 format!("block{}", b.0)
             },
+            ValueInfo::BlockCall(call) => {
+                format!("block{}", (call.0).0)
+            },
+            ValueInfo::Param(n) => {
+                let block = scope.values.block(val).map_or(0, |path| path.block.0 as usize);
+                param_name(block, *n as usize)
+            },
             ValueInfo::BooleanConstant(b) => {
                 b.to_string()
             },
             ValueInfo::DoubleConstant(b) => {
-                b.to_string()
+                display_float(*b, false)
             },
             ValueInfo::IntegerConstant(b) => {
                 b.to_string()
             },
             ValueInfo::FloatConstant(b) => {
-                b.to_string()
+                display_float(*b, true)
             },
             ValueInfo::Instruction(b) => {
-                self.display_instruction(b, block)
+                self.display_instruction(b, scope)?
             },
             ValueInfo::Named(b) => {
-                self.display_named(b, block)
+                self.display_named(b, val, scope)?
             },
-            ValueInfo::StringConstant(b) => {
-                "\"".to_string() + &b + "\""
+            ValueInfo::StringConstant(b, encoding) => {
+                display_string(b, *encoding, &"    ".repeat(scope.depth + 1))
+            },
+            ValueInfo::BytesConstant(b) => {
+                display_literal("", '"', b.iter().map(|b| *b as u32), false, &"    ".repeat(scope.depth + 1))
             },
             ValueInfo::CharConstant(b) => {
-                "'".to_string() + &b + "'"
+                display_literal("", '\'', b.bytes().map(u32::from), false, "")
             },
-        }
+        };
+
+        Ok(value)
     }
 
-    fn display_named(&self, named: &Named, block: &InstBlock) -> String {
+    /// Displays a named value.  Static properties have no equivalent in C, so they produce
+    /// an error.
+    fn display_named(&self, named: &Named, val: Value, scope: &Scope) -> Result<String, CodegenError> {
         let mut name = named.name.to_string();
 
         for item in &named.properties {
             match item {
                 NamedProperty::Basic(n) => {
                     name.push('.');
-                    name.push_str(&n);
+                    name.push_str(n);
                 },
                 NamedProperty::Index(n) => {
                     name.push('[');
-                    name.push_str(&self.display_value(*n, block));
+                    name.push_str(&self.display_value(*n, scope)?);
                     name.push(']');
                 },
                 NamedProperty::Pointer(n) => {
                     name.push_str("->");
-                    name.push_str(&n);
+                    name.push_str(n);
+                },
+                NamedProperty::Static(n) => {
+                    return Err(CodegenError::UnsupportedProperty {
+                        function: scope.function.to_string(),
+                        block: scope.path.to_string(),
+                        value: val,
+                        property: format!("::{}", n),
+                    });
                 },
-                NamedProperty::Static(_) => {
-                    panic!("Static indexing isn't allowed with the C emitter.");
-                    //name.push_str("::");
-                    //name.push_str(&n);
+            }
+        }
+
+        Ok(name)
+    }
+
+    /// Displays a type without a name, such as `int32_t*` or `int32_t (*)(char*)`.
+    fn display_type(&self, ty: &Type) -> String {
+        self.display_declarator(ty, String::new())
+    }
+
+    /// Displays the declaration of a name with the given type, such as `int32_t name[4]`.
+    fn display_declaration(&self, ty: &Type, name: &str) -> String {
+        self.display_declarator(ty, name.to_string())
+    }
+
+    /// Wraps a declarator around `inner` from the inside out, following C's rules: pointers
+    /// are prefixed with `*`, while arrays and functions are suffixed and bind tighter, so a
+    /// pointer declarator is parenthesized before an array or function suffix is added.
+    fn display_declarator(&self, ty: &Type, inner: String) -> String {
+        let group = |inner: String| {
+            if inner.starts_with('*') {
+                format!("({})", inner)
+            } else {
+                inner
+            }
+        };
+
+        let base = match ty {
+            Type::Pointer(to) => {
+                return self.display_declarator(to, format!("*{}", inner));
+            },
+            Type::Array(of, len) => {
+                let len = len.map(|n| n.to_string()).unwrap_or_default();
+                return self.display_declarator(of, format!("{}[{}]", group(inner), len));
+            },
+            Type::Function(func) => {
+                let mut params: Vec<String> = func.params.iter().map(|p| self.display_type(p)).collect();
+
+                if func.variadic {
+                    params.push("...".into());
+                } else if params.is_empty() {
+                    params.push("void".into());
+                }
+
+                return self.display_declarator(&func.returns, format!("{}({})", group(inner), params.join(", ")));
+            },
+            Type::Void => "void",
+            Type::Bool => "bool",
+            Type::Char => "char",
+            Type::I8 => "int8_t",
+            Type::I16 => "int16_t",
+            Type::I32 => "int32_t",
+            Type::I64 => "int64_t",
+            Type::ISize => "intptr_t",
+            Type::U8 => "uint8_t",
+            Type::U16 => "uint16_t",
+            Type::U32 => "uint32_t",
+            Type::U64 => "uint64_t",
+            Type::USize => "uintptr_t",
+            Type::Float => "float",
+            Type::Double => "double",
+            Type::Struct(name) => name,
+        };
+
+        // Leading pointer stars stay next to the base type, as in `char* name`.
+        let stars = inner.len() - inner.trim_start_matches('*').len();
+
+        if inner.is_empty() {
+            base.to_string()
+        } else if stars == inner.len() || inner[stars..].starts_with(' ') {
+            format!("{}{}", base, inner)
+        } else {
+            format!("{}{} {}", base, &inner[..stars], &inner[stars..])
+        }
+    }
+
+    /// Adds the C headers that a type needs to `imports`.
+    fn type_imports(&self, ty: &Type, imports: &mut Vec<String>) {
+        match ty {
+            Type::Bool => imports.push("stdbool.h".into()),
+            Type::Pointer(to) | Type::Array(to, _) => self.type_imports(to, imports),
+            Type::Function(func) => {
+                self.type_imports(&func.returns, imports);
+
+                for param in &func.params {
+                    self.type_imports(param, imports);
+                }
+            },
+            ty if ty.is_integer() => imports.push("stdint.h".into()),
+            _ => {},
+        }
+    }
+
+    /// Adds the struct at `index` to `order`, after the structs that it holds by value.
+    fn order_struct(&self, index: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+        if visited[index] {
+            return;
+        }
+
+        visited[index] = true;
+
+        if let Some(fields) = &self.module.structs[index].fields {
+            for field in fields {
+                // Structs behind pointers only need the forward declaration.
+                let mut ty = &field.1;
+
+                while let Type::Array(of, _) = ty {
+                    ty = of;
+                }
+
+                if let Type::Struct(name) = ty {
+                    if let Some(dep) = self.module.structs.iter().position(|s| &s.name == name) {
+                        self.order_struct(dep, visited, order);
+                    }
                 }
             }
         }
 
-        name.to_string()
+        order.push(index);
     }
 
-    fn display_abitype(&self, abitype: &AbiType) -> String {
-        let t = &abitype.1;
+    /// Displays a constant as a C initializer.
+    fn display_constant(&self, constant: &Constant) -> String {
+        match constant {
+            Constant::Integer(n) => n.to_string(),
+            Constant::Float(n) => display_float(*n, true),
+            Constant::Double(n) => display_float(*n, false),
+            Constant::Boolean(b) => b.to_string(),
+            Constant::String(s, encoding) => display_string(s, *encoding, "    "),
+            Constant::Bytes(b) => display_literal("", '"', b.iter().map(|b| *b as u32), false, "    "),
+            Constant::Char(s) => display_literal("", '\'', s.bytes().map(u32::from), false, ""),
+            Constant::Array(items) => {
+                let items: Vec<String> = items.iter().map(|c| self.display_constant(c)).collect();
+                format!("{{ {} }}", items.join(", "))
+            },
+            Constant::Struct(fields) => {
+                let fields: Vec<String> = fields.iter()
+                    .map(|(name, c)| format!(".{} = {}", name, self.display_constant(c)))
+                    .collect();
 
-        match t {
-            Type::Plain => {
-                abitype.0.to_string()
+                format!("{{ {} }}", fields.join(", "))
             },
-            Type::Array(n) => {
-                if n > &-1 {
-                    abitype.0.to_string() + "[]".into()
+        }
+    }
+
+    /// Compiles a global variable into a C declaration at file scope, with the given linkage.
+    /// An imported global is declared `extern`, without its initializer.
+    fn compile_global(&self, name: &str, global: &GlobalData, linkage: Linkage) -> String {
+        let mut decl = String::new();
+
+        match linkage {
+            Linkage::Internal => decl.push_str("static "),
+            Linkage::Import => decl.push_str("extern "),
+            Linkage::Export => {},
+        }
+
+        // A read-only pointer is `T* const`, so that the pointer itself can't be changed.  The
+        // same goes for the elements of an array.
+        let mut element = &global.ty;
+
+        while let Type::Array(of, _) = element {
+            element = of;
+        }
+
+        if !global.constant {
+            decl.push_str(&self.display_declaration(&global.ty, name));
+        } else if let Type::Pointer(_) = element {
+            decl.push_str(&self.display_declarator(&global.ty, format!(" const {}", name)));
+        } else {
+            decl.push_str("const ");
+            decl.push_str(&self.display_declaration(&global.ty, name));
+        }
+
+        if let (Some(init), false) = (&global.init, linkage == Linkage::Import) {
+            decl.push_str(" = ");
+            decl.push_str(&self.display_constant(init));
+        }
+
+        decl + ";"
+    }
+
+    /// Compiles the structs of the module into `typedef` declarations, followed by the
+    /// definitions of the structs that aren't opaque in dependency order.
+    fn compile_structs(&self) -> String {
+        let mut lines = vec![];
+
+        for def in &self.module.structs {
+            lines.push(format!("typedef struct {0} {0};", def.name));
+        }
+
+        let mut visited = vec![false; self.module.structs.len()];
+        let mut order = vec![];
+
+        for i in 0..self.module.structs.len() {
+            self.order_struct(i, &mut visited, &mut order);
+        }
+
+        for i in order {
+            let def = &self.module.structs[i];
+
+            if let Some(fields) = &def.fields {
+                lines.push(String::new());
+                lines.push(format!("struct {} {{", def.name));
+
+                for field in fields {
+                    lines.push(format!("    {};", self.display_declaration(&field.1, &field.0)));
+                }
+
+                lines.push("};".to_string());
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    /// Returns the values of a block that are stored in temporaries, along with their
+    /// positions, in the order that they are defined.  A value is stored in a temporary if it
+    /// has side effects or is used more than once, so that it is evaluated exactly once and in
+    /// instruction order, like an SSA value.  A value that reads a name is also stored if it is
+    /// defined before a write to memory in the block, so that it isn't read after the write.
+    /// Values that loop conditions are built from are left inline, since the loop evaluates
+    /// them again on each iteration.  Shared values are always stored, as they are used by
+    /// other blocks of the function.  `enclosing` holds the temporaries of the enclosing blocks.
+    fn find_temps(&self, block: &InstBlock, values: &ValueTable, uses: &[usize], shared: &[bool], enclosing: &[Option<String>]) -> Vec<(Value, usize)> {
+        let mut inline = vec![false; values.len()];
+        let mut loops: Vec<Value> = block.blocks.iter()
+            .filter(|b| b.block_type.is_loop())
+            .flat_map(|b| b.block_type.values())
+            .collect();
+
+        while let Some(v) = loops.pop() {
+            if let (Some(false), Some(info)) = (inline.get(v.0 as usize), values.get(v)) {
+                inline[v.0 as usize] = true;
+                loops.extend(operands(info));
+            }
+        }
+
+        let writes = find_writes(block, values);
+        let mut stored: Vec<bool> = enclosing.iter().map(Option::is_some).collect();
+        let mut temps = vec![];
+
+        for (v, position) in block.values.iter().copied().zip(block.positions.iter().copied()) {
+            let i = v.0 as usize;
+
+            if let Some(ValueInfo::Instruction(inst)) = values.get(v) {
+                // The instruction that a value is passed to reads it before writing.
+                let written = writes.iter().any(|(k, write)| {
+                    *k > position || (*k == position && write.is_none_or(|write| !write.arguments.contains(&v)))
+                });
+
+                let read = written && inst.opcode != Opcode::Call && reads_name(v, values, &stored);
+
+                if shared[i] || (!inline[i] && (uses[i] > 1 || has_side_effects(inst) || read)) {
+                    stored[i] = true;
+                    temps.push((v, position));
+                }
+            }
+        }
+
+        temps.sort_by_key(|(_, position)| *position);
+
+        // A value with side effects that is only used by the instruction straight after it
+        // can stay inline, as long as nothing else is stored in a temporary in between.
+        let temps: Vec<(Value, usize)> = temps.iter().enumerate()
+            .filter(|(n, (v, position))| {
+                let next = block.insts.get(*position);
+                let direct = next.is_some_and(|inst| inst.arguments.contains(v));
+                let last = temps.get(n + 1).is_none_or(|(_, p)| p != position);
+
+                shared[v.0 as usize] || uses[v.0 as usize] != 1 || !direct || !last
+            })
+            .map(|(_, temp)| *temp)
+            .collect();
+
+        temps
+    }
+
+    /// Stores a value in a temporary, or evaluates it as a statement if it is never used or
+    /// has no type.  Shared values are assigned to the temporaries declared for them at the
+    /// top of the function.
+    fn compile_temp(&self, v: Value, used: bool, scope: &mut Scope, depth: usize, body: &mut FunctionBody) -> Result<(), CodegenError> {
+        let indent = "    ".repeat(depth);
+        let i = v.0 as usize;
+
+        if let Some(name) = scope.temps[i].take() {
+            let expr = self.display_value(v, scope)?;
+            body.lines.push(format!("{}{} = {};", indent, name, expr));
+            scope.temps[i] = Some(name);
+
+            return Ok(());
+        }
+
+        let expr = self.display_value(v, scope)?;
+
+        match scope.values.ty(v) {
+            Some(ty) if used && *ty != Type::Void => {
+                let name = format!("_t{}", body.temps);
+                body.temps += 1;
+
+                self.type_imports(ty, &mut body.imports);
+                body.lines.push(format!("{}{} = {};", indent, self.display_declaration(ty, &name), expr));
+                scope.temps[i] = Some(name);
+            },
+            _ => body.lines.push(indent + &expr + ";"),
+        }
+
+        Ok(())
+    }
+
+    /// Compiles an instruction into a C statement.  A `ret` in a function that returns `void`
+    /// becomes a bare `return;`, after evaluating its value if that has side effects.
+    fn compile_inst(&self, inst: &InstructionInfo, scope: &Scope, depth: usize, body: &mut FunctionBody) -> Result<(), CodegenError> {
+        let indent = "    ".repeat(depth);
+
+        match inst.opcode {
+            Opcode::Ret if *body.returns == Type::Void => {
+                if let Some(v) = inst.arguments.first() {
+                    let evaluate = match scope.value(*v)? {
+                        ValueInfo::Instruction(value) => {
+                            has_side_effects(value) && scope.temps[v.0 as usize].is_none()
+                        },
+                        _ => false,
+                    };
+
+                    if evaluate {
+                        body.lines.push(format!("{}{};", indent, self.display_value(*v, scope)?));
+                    }
+                }
+
+                body.lines.push(indent + "return;");
+            },
+            Opcode::Jmp => {
+                for line in self.compile_jump(scope.argument(inst, 0)?, scope, body)? {
+                    body.lines.push(indent.to_string() + &line);
+                }
+            },
+            Opcode::Brif if has_block_args(inst, scope) => {
+                let cond = self.display_value(scope.argument(inst, 0)?, scope)?;
+                body.lines.push(format!("{}if ({}) {{", indent, cond));
+
+                for line in self.compile_jump(scope.argument(inst, 1)?, scope, body)? {
+                    body.lines.push(format!("{}    {}", indent, line));
+                }
+
+                body.lines.push(indent.to_string() + "} else {");
+
+                for line in self.compile_jump(scope.argument(inst, 2)?, scope, body)? {
+                    body.lines.push(format!("{}    {}", indent, line));
+                }
+
+                body.lines.push(indent + "}");
+            },
+            Opcode::Switch => self.compile_switch(inst, scope, depth, body)?,
+            Opcode::Unreachable => {
+                body.imports.push("stdlib.h".into());
+                body.lines.push(indent + &self.display_instruction(inst, scope)? + ";");
+            },
+            _ => body.lines.push(indent + &self.display_instruction(inst, scope)? + ";"),
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a branch to a target into statements that assign the values passed to the
+    /// parameters of the block, followed by a `goto` to the block.
+    fn compile_jump(&self, target: Value, scope: &Scope, body: &mut FunctionBody) -> Result<Vec<String>, CodegenError> {
+        let (block, args) = match scope.value(target)?.branch_target() {
+            Some(target) => target,
+            None => return Ok(vec![format!("goto {};", self.display_value(target, scope)?)]),
+        };
+
+        let mut lines = vec![];
+        let mut values = vec![];
+
+        // A branch may pass the block's own parameters to it, so every argument is evaluated
+        // before any of the parameters are assigned.
+        let staged = args.len() > 1 && args.iter().any(|arg| reads_param(*arg, block, scope));
+
+        for arg in args {
+            let mut value = self.display_value(*arg, scope)?;
+
+            let constant = !matches!(scope.value(*arg)?, ValueInfo::Instruction(_) | ValueInfo::Named(_) | ValueInfo::Param(_));
+
+            if staged && !constant {
+                let ty = scope.values.ty(*arg).cloned().unwrap_or(Type::Void);
+                let name = format!("_t{}", body.temps);
+                body.temps += 1;
+
+                self.type_imports(&ty, &mut body.imports);
+                lines.push(format!("{} = {};", self.display_declaration(&ty, &name), value));
+                value = name;
+            }
+
+            values.push(value);
+        }
+
+        for (n, value) in values.iter().enumerate() {
+            let param = param_name(block.0 as usize, n);
+
+            if *value != param {
+                lines.push(format!("{} = {};", param, value));
+            }
+        }
+
+        lines.push(format!("goto block{};", block.0));
+        Ok(lines)
+    }
+
+    /// Compiles a `switch` into a C `switch` statement whose cases jump to their blocks.  With
+    /// GNU extensions, dense cases are compiled into a table of label addresses instead, which
+    /// is indexed with a computed `goto`.  Tables are only used when no case passes values to
+    /// the parameters of its block.
+    fn compile_switch(&self, inst: &InstructionInfo, scope: &Scope, depth: usize, body: &mut FunctionBody) -> Result<(), CodegenError> {
+        let indent = "    ".repeat(depth);
+        let v = scope.argument(inst, 0)?;
+        let default = scope.argument(inst, 1)?;
+        let ty = scope.values.ty(v).cloned().unwrap_or(Type::Void);
+
+        // Each case is keyed by its constant, read as a signed number for signed types.
+        let mut cases = vec![];
+
+        for (constant, target) in scope.cases(inst)? {
+            let key = match scope.value(constant)? {
+                ValueInfo::IntegerConstant(n) => ty.integer_value(*n).ok_or_else(|| CodegenError::CaseOutOfRange {
+                    function: scope.function.to_string(),
+                    block: scope.path.to_string(),
+                    value: constant,
+                })?,
+                _ => return Err(CodegenError::NonConstantCase {
+                    function: scope.function.to_string(),
+                    block: scope.path.to_string(),
+                    value: constant,
+                }),
+            };
+
+            cases.push((key, constant, target));
+        }
+
+        cases.sort_by_key(|case| case.0);
+
+        // Two cases for the same key would shift the entries of a jump table.
+        if let Some(case) = cases.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(CodegenError::DuplicateCase {
+                function: scope.function.to_string(),
+                block: scope.path.to_string(),
+                value: case[1].1,
+            });
+        }
+
+        let dense = match (cases.first(), cases.last()) {
+            (Some(min), Some(max)) => {
+                cases.len() >= MIN_TABLE_CASES && max.0 - min.0 < 2 * cases.len() as i128
+            },
+            _ => false,
+        };
+
+        if !(self.gnu_extensions && dense) || has_block_args(inst, scope) {
+            body.lines.push(format!("{}switch ({}) {{", indent, self.display_value(v, scope)?));
+
+            let labels = cases.iter()
+                .map(|(_, constant, target)| Ok((format!("case {}", self.display_value(*constant, scope)?), *target)))
+                .chain(Some(Ok(("default".to_string(), default))))
+                .collect::<Result<Vec<_>, CodegenError>>()?;
+
+            for (label, target) in labels {
+                let jump = self.compile_jump(target, scope, body)?;
+
+                // Declarations can't directly follow a label, so longer jumps get a block.
+                if jump.len() == 1 {
+                    body.lines.push(format!("{}    {}: {}", indent, label, jump[0]));
                 } else {
-                    abitype.0.to_string() + &format!("[{}]", n)
+                    body.lines.push(format!("{}    {}: {{ {} }}", indent, label, jump.join(" ")));
+                }
+            }
+
+            body.lines.push(indent + "}");
+
+            return Ok(());
+        }
+
+        // The value is used more than once, so an expression is stored in a temporary.
+        let mut value = self.display_value(v, scope)?;
+
+        if let ValueInfo::Instruction(_) = scope.value(v)? {
+            if scope.temps[v.0 as usize].is_none() {
+                let name = format!("_t{}", body.temps);
+                body.temps += 1;
+
+                self.type_imports(&ty, &mut body.imports);
+                body.lines.push(format!("{}{} = {};", indent, self.display_declaration(&ty, &name), value));
+                value = name;
+            }
+        }
+
+        let default = self.display_value(default, scope)?;
+        let (min, max) = (&cases[0], &cases[cases.len() - 1]);
+        let mut table = vec![];
+        let mut next = cases.iter().peekable();
+
+        for key in min.0..=max.0 {
+            match next.next_if(|case| case.0 == key) {
+                Some((_, _, target)) => table.push(format!("&&{}", self.display_value(*target, scope)?)),
+                None => table.push(format!("&&{}", default)),
+            }
+        }
+
+        let name = format!("_t{}", body.temps);
+        body.temps += 1;
+
+        // The bounds are written from the keys, so negative cases of signed types keep their sign.
+        let (first, last) = (min.0, max.0);
+        let (cond, index) = match first {
+            0 if ty.is_unsigned() => (format!("{} <= {}", value, last), value.to_string()),
+            0 => (format!("{} >= 0 && {} <= {}", value, value, last), value.to_string()),
+            _ if first < 0 => (format!("{} >= {} && {} <= {}", value, first, value, last), format!("{} + {}", value, -first)),
+            _ => (format!("{} >= {} && {} <= {}", value, first, value, last), format!("{} - {}", value, first)),
+        };
+
+        body.lines.push(format!("{}static void *const {}[] = {{ {} }};", indent, name, table.join(", ")));
+        body.lines.push(format!("{}if ({}) goto *{}[{}];", indent, cond, name, index));
+        body.lines.push(format!("{}goto {};", indent, default));
+
+        Ok(())
+    }
+
+    /// Compiles the instructions of a block into indented C statements, including the blocks
+    /// nested inside of it.  `path` is the path to the block that errors are reported with,
+    /// and `temps` holds the temporaries of the enclosing blocks.
+    fn compile_block(&self, block: &InstBlock, path: String, depth: usize, temps: Vec<Option<String>>, body: &mut FunctionBody) -> Result<(), CodegenError> {
+        let mut entered = vec![false; block.blocks.len()];
+        let mut scope = Scope {
+            function: body.function,
+            path,
+            depth,
+            values: body.values,
+            temps,
+        };
+
+        body.imports.append(&mut block.imports.clone());
+
+        let mut temps = self.find_temps(block, body.values, &body.uses, &body.shared, &scope.temps).into_iter().peekable();
+
+        for (n, inst) in block.insts.iter().enumerate() {
+            while let Some((v, _)) = temps.next_if(|(_, position)| *position <= n) {
+                let used = body.uses[v.0 as usize] > 0;
+                self.compile_temp(v, used, &mut scope, depth, body)?;
+            }
+
+            if let Opcode::Enter = inst.opcode {
+                if let ValueInfo::Block(b) = scope.value(scope.argument(inst, 0)?)? {
+                    let nested = block.blocks.get(b.0 as usize)
+                        .ok_or(CodegenError::MissingBlock { function: None, block: *b })?;
+
+                    entered[b.0 as usize] = true;
+                    self.compile_nested(nested, b.0 as usize, &scope, depth, body)?;
                 }
+
+                continue;
+            }
+
+            self.compile_inst(inst, &scope, depth, body)?;
+        }
+
+        for (v, _) in temps {
+            let used = body.uses[v.0 as usize] > 0;
+            self.compile_temp(v, used, &mut scope, depth, body)?;
+        }
+
+        // Nested blocks that are never entered run after the rest of the block.
+        for (i, nested) in block.blocks.iter().enumerate() {
+            if !entered[i] {
+                self.compile_nested(nested, i, &scope, depth, body)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a nested block as a C compound statement, a loop, or an `if` statement with its
+    /// `else if` and `else` branches.  Conditions are displayed from the parent block, and
+    /// the branches can use the parent's temporaries.
+    fn compile_nested(&self, nested: &InstBlock, index: usize, parent: &Scope, depth: usize, body: &mut FunctionBody) -> Result<(), CodegenError> {
+        let indent = "    ".repeat(depth);
+        let path = format!("{}/block{}", parent.path, index);
+
+        match nested.block_type {
+            BlockType::If(cond) => {
+                body.lines.push(format!("{}if ({}) {{", indent, self.display_value(cond, parent)?));
+            },
+            BlockType::While(cond) => {
+                body.lines.push(format!("{}while ({}) {{", indent, self.display_value(cond, parent)?));
+            },
+            BlockType::DoWhile(_) => {
+                body.lines.push(indent.to_string() + "do {");
             },
-            Type::Pointer => {
-                abitype.0.to_string() + "*".into()
+            BlockType::For { init, cond, step } => {
+                let optional = |v: Option<Value>| match v {
+                    Some(v) => self.display_value(v, parent),
+                    None => Ok(String::new()),
+                };
+
+                body.lines.push(format!(
+                    "{}for ({}; {}; {}) {{",
+                    indent,
+                    optional(init)?,
+                    self.display_value(cond, parent)?,
+                    optional(step)?
+                ));
+            },
+            BlockType::Basic => {
+                body.lines.push(indent.to_string() + "{");
+            },
+        }
+
+        self.compile_block(nested, path.to_string(), depth + 1, parent.temps.clone(), body)?;
+
+        for (i, elif) in nested.elses.iter().enumerate() {
+            match elif.block_type {
+                BlockType::If(cond) => {
+                    body.lines.push(format!("{}}} else if ({}) {{", indent, self.display_value(cond, parent)?));
+                },
+                _ => {
+                    body.lines.push(indent.to_string() + "} else {");
+                },
             }
+
+            self.compile_block(elif, format!("{}/elif{}", path, i), depth + 1, parent.temps.clone(), body)?;
+        }
+
+        if let Some(else_block) = &nested.else_block {
+            body.lines.push(indent.to_string() + "} else {");
+            self.compile_block(else_block, format!("{}/else", path), depth + 1, parent.temps.clone(), body)?;
+        }
+
+        if let BlockType::DoWhile(cond) = nested.block_type {
+            body.lines.push(format!("{}}} while ({});", indent, self.display_value(cond, parent)?));
+        } else {
+            body.lines.push(indent + "}");
+        }
+
+        Ok(())
+    }
+
+    /// Displays the signature of a function, such as `int32_t add(int32_t a, int32_t b)`.  A
+    /// function without parameters takes `void`, as empty parentheses don't declare a
+    /// prototype in C.  Internal functions that are defined in the module are `static`.
+    fn display_signature(&self, func: &Function) -> String {
+        let mut args: Vec<String> = func.signature.arguments.iter()
+            .map(|arg| self.display_declaration(&arg.1, &arg.0))
+            .collect();
+
+        if args.is_empty() {
+            args.push("void".into());
+        }
+
+        let signature = self.display_declarator(&func.signature.returns, format!("{}({})", func.name, args.join(", ")));
+
+        if func.linkage == Linkage::Internal && !func.blocks.is_empty() {
+            format!("static {}", signature)
+        } else {
+            signature
+        }
+    }
+
+    /// Compiles the prototype of a function, which lets it be called before it is defined.  A
+    /// function that is only declared is defined by another module, so it is `extern`.
+    pub fn compile_prototype(&self, func: &Function) -> String {
+        if func.blocks.is_empty() {
+            format!("extern {};", self.display_signature(func))
+        } else {
+            format!("{};", self.display_signature(func))
         }
     }
 
-    /// Compiles a single function into C code.
-    pub fn compile_function(&self, func: &Function) -> (String, Vec<String>) {
-        let mut args = vec![];
+    /// Compiles a single function into C code, returning the code along with the C headers
+    /// that it needs.  A function that is only declared compiles into its prototype.
+    pub fn compile_function(&self, func: &Function) -> Result<(String, Vec<String>), CodegenError> {
+        let mut imports = vec![];
+
+        self.type_imports(&func.signature.returns, &mut imports);
 
         for item in &func.signature.arguments {
-            args.push(format!("{} {}", self.display_abitype(&item.1), item.0));
+            self.type_imports(&item.1, &mut imports);
         }
 
-        let mut header = format!("{} {}({})", self.display_abitype(&func.signature.returns), func.name, args.join(", "));
-        if func.blocks.len() == 0 {
-            return (header, vec![]);
+        if func.blocks.is_empty() {
+            Ok((self.compile_prototype(func), imports))
         } else {
+            let mut header = self.display_signature(func);
             header.push_str(" {\n");
-            let mut imports = vec![];
-            let mut insts = vec![];
+            let mut body = FunctionBody {
+                function: &func.name,
+                returns: &func.signature.returns,
+                values: &func.values,
+                uses: count_uses(func),
+                shared: shared_values(func),
+                lines: vec![],
+                imports,
+                temps: 0,
+            };
+
+            // `true` and `false` come from `stdbool.h`, like the `bool` type.
+            if func.values.info.iter().any(|info| matches!(info, ValueInfo::BooleanConstant(_))) {
+                body.imports.push("stdbool.h".into());
+            }
+
+            for var in &func.variables {
+                self.type_imports(var.1, &mut body.imports);
+                body.lines.push(format!("    {};", self.display_declaration(var.1, var.0)));
+            }
+
+            // Block parameters are declared up front, and assigned by the branches to them.
+            for (i, block) in func.blocks.iter().enumerate() {
+                for (n, ty) in block.params.iter().enumerate() {
+                    self.type_imports(ty, &mut body.imports);
+                    body.lines.push(format!("    {};", self.display_declaration(ty, &param_name(i, n))));
+                }
+            }
+
+            // Each block of the function is a separate C scope, so values that are used by other
+            // blocks are declared up front, and assigned by the block that defines them.
+            let mut temps = vec![None; func.values.len()];
+
+            for (i, ty) in func.values.types.iter().enumerate() {
+                if body.shared[i] {
+                    let name = format!("_t{}", body.temps);
+                    body.temps += 1;
+
+                    self.type_imports(ty, &mut body.imports);
+                    body.lines.push(format!("    {};", self.display_declaration(ty, &name)));
+                    temps[i] = Some(name);
+                }
+            }
+
+            // Only the blocks that are jumped to are labelled, as C compilers warn about
+            // unused labels.
+            let mut targets = vec![false; func.blocks.len()];
+            for block in &func.blocks {
+                for b in func.branch_targets(block) {
+                    if let Some(target) = targets.get_mut(b.0 as usize) {
+                        *target = true;
+                    }
+                }
+            }
 
             for (i, v) in func.blocks.iter().enumerate() {
-                let mut block = vec![];
-                imports.append(&mut v.imports.clone());
-                for inst in &v.insts {
-                    block.push(self.display_instruction(inst, v));
+                if targets[i] {
+                    body.lines.push(format!("    block{}: {{", i));
+                } else {
+                    body.lines.push("    {".to_string());
                 }
-                
-                insts.push(format!("block{}: {{\n", i) + &block.join(";\n") + ";\n}\n");
+
+                self.compile_block(v, format!("block{}", i), 2, temps.clone(), &mut body)?;
+                body.lines.push("    }".to_string());
             }
 
-            let mut vars = vec![];
+            header.push_str(&body.lines.join("\n"));
+            header.push_str("\n}");
 
-            for var in &func.variables {
-                vars.push(self.display_abitype(&var.1) + " " + var.0);
+            Ok((header, body.imports))
+        }
+    }
+
+    /// Returns the C headers that the fields of the module's structs need.
+    fn struct_imports(&self) -> Vec<String> {
+        let mut imports = vec![];
+
+        for def in &self.module.structs {
+            for field in def.fields.iter().flatten() {
+                self.type_imports(&field.1, &mut imports);
             }
+        }
 
-            header.push_str(&(vars.join(";\n")));
+        imports
+    }
+
+    /// Compiles the globals and functions of the module.  The prototypes of exported
+    /// functions are left out unless `exported` is true, for when a header declares them
+    /// instead.
+    fn compile_items(&mut self, exported: bool) -> Result<Items, CodegenError> {
+        let mut f = vec![];
+        let mut globals = vec![];
+
+        let mut imports = vec![];
 
-            if vars.len() > 0 {
-                header.push_str(";\n");
+        for (name, global) in &self.module.data {
+            if global.init.as_ref().is_some_and(uses_bool) {
+                imports.push("stdbool.h".into());
             }
 
-            header.push_str(&(insts.join("\n")));
+            self.type_imports(&global.ty, &mut imports);
+            globals.push(self.compile_global(name, global, global.linkage));
+        }
+
+        self.imports.append(&mut imports);
+
+        // Every function gets a prototype ahead of the definitions, so that functions can call
+        // the ones defined after them.
+        let mut prototypes = vec![];
 
-            header.push('}');
+        for func in self.module.functions.values() {
+            let mut res = self.compile_function(func)?;
 
-            return (header, imports);
+            if func.blocks.is_empty() {
+                prototypes.push(res.0);
+            } else {
+                if exported || func.linkage == Linkage::Internal {
+                    prototypes.push(self.compile_prototype(func));
+                }
+
+                f.push(res.0);
+            }
+
+            self.imports.append(&mut res.1);
         }
 
-        
+        Ok(Items {
+            globals,
+            prototypes,
+            definitions: f,
+        })
     }
 
     /// Compiles the provided module into a `String` of valid C code.
-    pub fn emit(&mut self) -> String {
+    pub fn emit(&mut self) -> Result<String, CodegenError> {
         let mut str = String::new();
 
-        let mut f = vec![];
+        let mut imports = self.struct_imports();
+        self.imports.append(&mut imports);
 
-        for item in &self.module.functions {
-            let x = item.1;
-            let mut res = self.compile_function(x);
-            f.push(res.0);
+        let Items { globals, prototypes, definitions: f } = self.compile_items(true)?;
 
-            self.imports.append(&mut res.1);
-        }
+        str.push_str(&display_includes(&self.imports, &[]).join("\n"));
+        str.push('\n');
 
-        let mut includes = vec![];
+        if !self.module.structs.is_empty() {
+            str.push_str(&self.compile_structs());
+            str.push_str("\n\n");
+        }
 
-        for item in &self.imports {
-            includes.push(format!("#include <{}>", item));
+        if !globals.is_empty() {
+            str.push_str(&globals.join("\n"));
+            str.push_str("\n\n");
         }
 
-        str.push_str(&includes.join("\n"));
-        str.push('\n');
+        if !prototypes.is_empty() {
+            str.push_str(&prototypes.join("\n"));
+
+            if !f.is_empty() {
+                str.push_str("\n\n");
+            }
+        }
 
         str.push_str(&f.join("\n"));
 
-        str
+        Ok(str)
     }
 
-}
\ No newline at end of file
+    /// Compiles the provided module into a C header and source file, for code that is shipped
+    /// as a library.  The header is included as `header`, such as `"math.h"`, and declares the
+    /// structs of the module along with its exported globals and functions, inside of include
+    /// guards.  The source includes the header and holds the definitions, along with the
+    /// internal and imported declarations that stay out of the header.
+    pub fn emit_library(&mut self, header: &str) -> Result<(String, String), CodegenError> {
+        let mut public_imports = self.struct_imports();
+        let mut exports = vec![];
+
+        for (name, global) in &self.module.data {
+            if global.linkage == Linkage::Export {
+                self.type_imports(&global.ty, &mut public_imports);
+                exports.push(self.compile_global(name, global, Linkage::Import));
+            }
+        }
+
+        let mut prototypes = vec![];
+
+        for func in self.module.functions.values() {
+            if !func.blocks.is_empty() && func.linkage != Linkage::Internal {
+                self.type_imports(&func.signature.returns, &mut public_imports);
+
+                for item in &func.signature.arguments {
+                    self.type_imports(&item.1, &mut public_imports);
+                }
+
+                prototypes.push(self.compile_prototype(func));
+            }
+        }
+
+        let Items { globals, prototypes: internals, definitions: f } = self.compile_items(false)?;
+
+        let guard: String = header.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+
+        let guard = if guard.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("H_{}", guard)
+        } else {
+            guard
+        };
+
+        let mut sections = vec![
+            format!("#ifndef {0}\n#define {0}", guard),
+            display_includes(&public_imports, &[]).join("\n"),
+        ];
+
+        if !self.module.structs.is_empty() {
+            sections.push(self.compile_structs());
+        }
+
+        sections.push(exports.join("\n"));
+        sections.push(prototypes.join("\n"));
+        sections.push("#endif".into());
+        sections.retain(|section| !section.is_empty());
+
+        let header_file = sections.join("\n\n");
+
+        let mut includes = vec![format!("#include \"{}\"", header)];
+        includes.append(&mut display_includes(&self.imports, &public_imports));
+
+        let mut sections = vec![
+            includes.join("\n"),
+            globals.join("\n"),
+            internals.join("\n"),
+            f.join("\n"),
+        ];
+
+        sections.retain(|section| !section.is_empty());
+
+        Ok((header_file, sections.join("\n\n")))
+    }
+
+}
+
+/// The globals and functions of a module, compiled into C.
+struct Items {
+
+    /// The declarations of the globals.
+    globals: Vec<String>,
+
+    /// The prototypes of the functions.
+    prototypes: Vec<String>,
+
+    /// The definitions of the functions.
+    definitions: Vec<String>,
+
+}
+
+/// A block that is being compiled, along with the temporaries that values are stored in.
+struct Scope<'a> {
+
+    /// The name of the function that the block is in.
+    function: &'a str,
+
+    /// The path to the block, such as `block0/block1/else`.
+    path: String,
+
+    /// How deeply the statements of the block are indented.
+    depth: usize,
+
+    /// The value table of the function.
+    values: &'a ValueTable,
+
+    /// The name of the temporary holding each value of the function, once it has been stored
+    /// in a temporary that the block can see.
+    temps: Vec<Option<String>>,
+
+}
+
+impl<'a> Scope<'a> {
+
+    /// Returns information about a value of the function, or an error if it doesn't exist.
+    fn value(&self, v: Value) -> Result<&'a ValueInfo, CodegenError> {
+        self.values.get(v).ok_or_else(|| CodegenError::InvalidValue {
+            function: self.function.to_string(),
+            block: self.path.to_string(),
+            value: v,
+        })
+    }
+
+    /// Returns an argument of an instruction, or an error if the instruction has too few
+    /// arguments.
+    fn argument(&self, inst: &InstructionInfo, i: usize) -> Result<Value, CodegenError> {
+        inst.arguments.get(i).copied().ok_or_else(|| self.wrong_argument_count(inst))
+    }
+
+    /// Returns an error for an instruction with the wrong number of arguments.
+    fn wrong_argument_count(&self, inst: &InstructionInfo) -> CodegenError {
+        CodegenError::WrongArgumentCount {
+            function: self.function.to_string(),
+            block: self.path.to_string(),
+            opcode: inst.opcode,
+            found: inst.arguments.len(),
+        }
+    }
+
+    /// Returns the constant and block of each case of a `switch`, or an error if a case is
+    /// missing its block.
+    fn cases(&self, inst: &InstructionInfo) -> Result<Vec<(Value, Value)>, CodegenError> {
+        let cases = inst.arguments.get(2..).unwrap_or(&[]);
+
+        if cases.len() % 2 != 0 {
+            return Err(self.wrong_argument_count(inst));
+        }
+
+        Ok(cases.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+    }
+
+}
+
+/// The C code of a function that is being compiled.
+struct FunctionBody<'a> {
+
+    /// The name of the function.
+    function: &'a str,
+
+    /// The return type of the function.
+    returns: &'a Type,
+
+    /// The value table of the function.
+    values: &'a ValueTable,
+
+    /// The number of times that each value is used, from `count_uses`.
+    uses: Vec<usize>,
+
+    /// Whether each value is used outside of the function block that defines it, from
+    /// `shared_values`.
+    shared: Vec<bool>,
+
+    /// The lines of the function body.
+    lines: Vec<String>,
+
+    /// The C headers that the function needs.
+    imports: Vec<String>,
+
+    /// The number of temporaries declared so far, which gives each one a unique name.
+    temps: usize,
+
+}
+
+/// Returns the values that a value is computed from.
+fn operands(value: &ValueInfo) -> Vec<Value> {
+    match value {
+        ValueInfo::Instruction(inst) => inst.arguments.clone(),
+        ValueInfo::BlockCall(call) => call.1.clone(),
+        ValueInfo::Named(named) => named.properties.iter()
+            .filter_map(|prop| match prop {
+                NamedProperty::Index(v) => Some(*v),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Returns the nested blocks of a block, along with their `elif` and `else` branches.
+fn branches(block: &InstBlock) -> impl Iterator<Item = &InstBlock> {
+    block.blocks.iter().flat_map(|b| b.elses.iter().chain(Some(b)).chain(b.else_block.as_deref()))
+}
+
+/// Counts the number of times that each value of a function is used by its values, its
+/// instructions and the conditions of its nested blocks.
+fn count_uses(func: &Function) -> Vec<usize> {
+    let mut uses = vec![0; func.values.len()];
+    let mut used: Vec<Value> = func.values.info.iter().flat_map(operands).collect();
+    let mut blocks: Vec<&InstBlock> = func.blocks.iter().collect();
+
+    while let Some(block) = blocks.pop() {
+        used.extend(block.insts.iter().flat_map(|inst| inst.arguments.iter().copied()));
+        used.extend(block.blocks.iter().flat_map(|b| b.elses.iter().chain(Some(b))).flat_map(|b| b.block_type.values()));
+        blocks.extend(branches(block));
+    }
+
+    for v in used {
+        if let Some(n) = uses.get_mut(v.0 as usize) {
+            *n += 1;
+        }
+    }
+
+    uses
+}
+
+/// Finds the instruction values of a function that are used outside of the function block
+/// that defines them.  Other values, such as names, are displayed where they are used, so the
+/// values that they are built from are checked instead.
+fn shared_values(func: &Function) -> Vec<bool> {
+    let values = &func.values;
+    let mut shared = vec![false; values.len()];
+
+    // Each use is paired with the function block that it happens in.
+    let mut uses: Vec<(Value, Block)> = vec![];
+
+    for (info, path) in values.info.iter().zip(&values.blocks) {
+        uses.extend(operands(info).into_iter().map(|v| (v, path.block)));
+    }
+
+    for (i, block) in func.blocks.iter().enumerate() {
+        let mut blocks = vec![block];
+
+        while let Some(block) = blocks.pop() {
+            let conds = block.blocks.iter().flat_map(|b| b.elses.iter().chain(Some(b))).flat_map(|b| b.block_type.values());
+            let args = block.insts.iter().flat_map(|inst| inst.arguments.iter().copied());
+
+            uses.extend(args.chain(conds).map(|v| (v, Block(i as u32))));
+            blocks.extend(branches(block));
+        }
+    }
+
+    let mut seen = vec![];
+
+    while let Some((v, root)) = uses.pop() {
+        let (info, path) = match (values.get(v), values.block(v)) {
+            (Some(info), Some(path)) => (info, path),
+            _ => continue,
+        };
+
+        if path.block == root || seen.contains(&(v, root)) {
+            continue;
+        }
+
+        seen.push((v, root));
+
+        match info {
+            ValueInfo::Instruction(_) if values.ty(v) != Some(&Type::Void) => shared[v.0 as usize] = true,
+            _ => uses.extend(operands(info).into_iter().map(|o| (o, root))),
+        }
+    }
+
+    shared
+}
+
+/// Returns the `#include` lines for a list of C headers, leaving out duplicates and the
+/// headers in `exclude`.
+fn display_includes(imports: &[String], exclude: &[String]) -> Vec<String> {
+    let mut includes = vec![];
+
+    for item in imports {
+        let include = format!("#include <{}>", item);
+
+        if !includes.contains(&include) && !exclude.contains(item) {
+            includes.push(include);
+        }
+    }
+
+    includes
+}
+
+/// Returns the name of the variable that holds a parameter of a function block.
+fn param_name(block: usize, n: usize) -> String {
+    format!("_b{}_{}", block, n)
+}
+
+/// Returns true if displaying a value reads a parameter of the given function block, which
+/// a branch to the block may assign.  Values stored in temporaries have already been read.
+fn reads_param(v: Value, block: Block, scope: &Scope) -> bool {
+    if let Some(Some(_)) = scope.temps.get(v.0 as usize) {
+        return false;
+    }
+
+    match scope.values.get(v) {
+        Some(ValueInfo::Param(_)) => scope.values.block(v).is_some_and(|path| path.block == block),
+        Some(info) => operands(info).into_iter().any(|o| reads_param(o, block, scope)),
+        None => false,
+    }
+}
+
+/// Returns true if a branch passes values to the parameters of any of its targets.
+fn has_block_args(inst: &InstructionInfo, scope: &Scope) -> bool {
+    inst.arguments.iter().any(|v| match scope.values.get(*v) {
+        Some(ValueInfo::BlockCall(call)) => !call.1.is_empty(),
+        _ => false,
+    })
+}
+
+/// Returns true if evaluating an instruction changes the state of the program.
+fn has_side_effects(inst: &InstructionInfo) -> bool {
+    matches!(inst.opcode, Opcode::Call | Opcode::Set)
+}
+
+/// Returns true if displaying a value reads a name, rather than only temporaries, constants
+/// and the results of calls.  `stored` holds the values that are in temporaries.
+fn reads_name(v: Value, values: &ValueTable, stored: &[bool]) -> bool {
+    match values.get(v) {
+        Some(ValueInfo::Named(_)) => true,
+        Some(ValueInfo::Instruction(inst)) if inst.opcode != Opcode::Call => {
+            inst.arguments.iter().any(|o| stored.get(o.0 as usize) == Some(&false) && reads_name(*o, values, stored))
+        },
+        _ => false,
+    }
+}
+
+/// Returns true if a block, or a block nested in it, has a `set` or a call.
+fn writes_memory(block: &InstBlock, values: &ValueTable) -> bool {
+    let calls = block.values.iter().any(|v| match values.get(*v) {
+        Some(ValueInfo::Instruction(inst)) => has_side_effects(inst),
+        _ => false,
+    });
+
+    calls || block.insts.iter().any(has_side_effects) || branches(block).any(|b| writes_memory(b, values))
+}
+
+/// Returns the positions in a block where memory may be written, along with the instruction
+/// that writes, if it is a `set`, a call or an `enter`.  Calls stored in temporaries write at
+/// their positions, and nested blocks that are never entered write after the instructions.
+fn find_writes<'a>(block: &'a InstBlock, values: &ValueTable) -> Vec<(usize, Option<&'a InstructionInfo>)> {
+    let mut writes = vec![];
+    let mut entered = vec![false; block.blocks.len()];
+
+    for (v, position) in block.values.iter().zip(&block.positions) {
+        if let Some(ValueInfo::Instruction(inst)) = values.get(*v) {
+            if has_side_effects(inst) {
+                writes.push((*position, None));
+            }
+        }
+    }
+
+    for (k, inst) in block.insts.iter().enumerate() {
+        let nested = match (inst.opcode, inst.arguments.first().and_then(|v| values.get(*v))) {
+            (Opcode::Enter, Some(ValueInfo::Block(b))) => Some(b.0 as usize),
+            _ => None,
+        };
+
+        if let Some(entered) = nested.and_then(|b| entered.get_mut(b)) {
+            *entered = true;
+        }
+
+        if has_side_effects(inst) || nested.and_then(|b| block.blocks.get(b)).is_some_and(|b| writes_memory(b, values)) {
+            writes.push((k, Some(inst)));
+        }
+    }
+
+    if block.blocks.iter().zip(&entered).any(|(b, entered)| !entered && writes_memory(b, values)) {
+        writes.push((block.insts.len(), None));
+    }
+
+    writes
+}
+
+/// The smallest number of cases that a `switch` needs to be compiled into a jump table.
+const MIN_TABLE_CASES: usize = 4;
+
+/// The precedence of values that aren't instructions, such as names, constants and
+/// temporaries, which never need parentheses.
+const PRIMARY: u8 = 16;
+
+/// Returns the precedence of an opcode's C operator, where higher numbers bind more tightly.
+fn precedence(opcode: Opcode) -> u8 {
+    match opcode {
+        Opcode::Call => 15,
+        Opcode::BitNot | Opcode::Not => 14,
+        Opcode::Mul | Opcode::Div | Opcode::Mod => 13,
+        Opcode::Add | Opcode::Sub => 12,
+        Opcode::BitLeft | Opcode::BitRight => 11,
+        Opcode::TestGt | Opcode::TestGtEq | Opcode::TestLt | Opcode::TestLtEq => 10,
+        Opcode::TestEq | Opcode::TestNeq => 9,
+        Opcode::BitAnd => 8,
+        Opcode::BitXor => 7,
+        Opcode::BitOr => 6,
+        Opcode::And => 5,
+        Opcode::Or => 4,
+        Opcode::Set => 2,
+        Opcode::Jmp | Opcode::Brif | Opcode::Switch | Opcode::Ret | Opcode::Break | Opcode::Continue | Opcode::Enter
+        | Opcode::Unreachable => 0,
+    }
+}
+
+/// The longest that the text of a string literal may be before it is split into adjacent
+/// literals, which C joins back together.
+const MAX_LITERAL_LENGTH: usize = 80;
+
+/// Displays a string constant as a C string literal, with the prefix of its encoding.
+/// Narrow and UTF-8 strings are written as UTF-8 bytes, so that they don't depend on the
+/// character set of the C compiler, while the other encodings are written as characters,
+/// which the C compiler encodes.
+fn display_string(text: &str, encoding: StringEncoding, indent: &str) -> String {
+    match encoding {
+        StringEncoding::Narrow => display_literal("", '"', text.bytes().map(u32::from), false, indent),
+        StringEncoding::Utf8 => display_literal("u8", '"', text.bytes().map(u32::from), false, indent),
+        StringEncoding::Utf16 => display_literal("u", '"', text.chars().map(u32::from), true, indent),
+        StringEncoding::Utf32 => display_literal("U", '"', text.chars().map(u32::from), true, indent),
+        StringEncoding::Wide => display_literal("L", '"', text.chars().map(u32::from), true, indent),
+    }
+}
+
+/// Displays a C string or character literal delimited by `quote`, from the code units of the
+/// literal.  Units outside of printable ASCII are escaped, as octal escapes of exactly three
+/// digits so that a following digit can't be read as part of them, or as universal character
+/// names for characters when `unicode` is true.  A literal that is longer than
+/// `MAX_LITERAL_LENGTH` is split after newlines and at the maximum length, with each part
+/// after the first on its own line at `indent`.
+fn display_literal(prefix: &str, quote: char, units: impl Iterator<Item = u32>, unicode: bool, indent: &str) -> String {
+    let mut escaped = vec![];
+    let mut prev = None;
+
+    for unit in units {
+        let text = match std::char::from_u32(unit) {
+            Some(c) if c == quote || c == '\\' => format!("\\{}", c),
+            Some('\n') => "\\n".to_string(),
+            Some('\r') => "\\r".to_string(),
+            Some('\t') => "\\t".to_string(),
+            // `??` starts a trigraph, such as `??/` for `\`.
+            Some('?') if prev == Some('?' as u32) => "\\?".to_string(),
+            Some(c @ ' '..='~') => c.to_string(),
+            Some('\u{a0}'..='\u{ffff}') if unicode => format!("\\u{:04x}", unit),
+            Some(_) if unicode && unit > 0xffff => format!("\\U{:08x}", unit),
+            _ => format!("\\{:03o}", unit),
+        };
+
+        escaped.push(text);
+        prev = Some(unit);
+    }
+
+    let mut parts = vec![String::new()];
+
+    if escaped.iter().map(String::len).sum::<usize>() > MAX_LITERAL_LENGTH {
+        for text in escaped {
+            let part = parts.last_mut().unwrap();
+
+            if !part.is_empty() && part.len() + text.len() > MAX_LITERAL_LENGTH {
+                parts.push(text);
+            } else if text == "\\n" {
+                part.push_str(&text);
+                parts.push(String::new());
+            } else {
+                part.push_str(&text);
+            }
+        }
+
+        if parts.len() > 1 && parts.last().is_some_and(String::is_empty) {
+            parts.pop();
+        }
+    } else {
+        parts[0] = escaped.concat();
+    }
+
+    let parts: Vec<String> = parts.iter()
+        .map(|part| format!("{}{}{}{}", prefix, quote, part, quote))
+        .collect();
+
+    parts.join(&format!("\n{}", indent))
+}
+
+/// Displays a floating point constant as a C literal, which always has a decimal point or an
+/// exponent so that it isn't read as an integer.  `float` constants get an `f` suffix.
+/// Infinities and NaN have no literal, so they are written as divisions by zero.
+fn display_float(n: f64, float: bool) -> String {
+    let suffix = if float { "f" } else { "" };
+
+    if n.is_nan() {
+        format!("(0.0{} / 0.0{})", suffix, suffix)
+    } else if n.is_infinite() {
+        format!("({}1.0{} / 0.0{})", if n < 0.0 { "-" } else { "" }, suffix, suffix)
+    } else if float {
+        format!("{:?}{}", n as f32, suffix)
+    } else {
+        format!("{:?}", n)
+    }
+}
+
+/// Returns true if a constant contains a boolean, which needs `stdbool.h` in C.
+fn uses_bool(constant: &Constant) -> bool {
+    match constant {
+        Constant::Boolean(_) => true,
+        Constant::Array(items) => items.iter().any(uses_bool),
+        Constant::Struct(fields) => fields.iter().any(|(_, c)| uses_bool(c)),
+        _ => false,
+    }
+}
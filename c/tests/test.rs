@@ -2,10 +2,12 @@ extern crate cardinal_c;
 extern crate cardinal_codegen;
 
 use cardinal_c::CBackend;
-use cardinal_codegen::entities::{AbiParam, AbiType, Named, Type};
+use cardinal_codegen::entities::{AbiParam, Block, BlockCall, FunctionType, Named, NamedProperty, StringEncoding, Type, Value, ValueInfo};
 use cardinal_codegen::function::{Function, FunctionSignature};
 use cardinal_codegen::instbuilder::InstBuilder;
-use cardinal_codegen::Module;
+use cardinal_codegen::instruction::{BlockBuilder, BlockType, InstBlock, InstructionInfo, Opcode};
+use cardinal_codegen::ir::parse_module;
+use cardinal_codegen::{CodegenError, Constant, ConstantFolding, DataValue, GlobalData, Interpreter, Linkage, Module, PassManager, StructField};
 
 #[cfg(test)]
 mod tests {
@@ -18,39 +20,1376 @@ mod tests {
         let sig = FunctionSignature::new();
         let mut func = Function::new("main".into(), sig);
 
-        let v = func.declare_var("my_var".into(), AbiType("int".into(), Type::Plain));
+        let v = func.declare_var("my_var".into(), Type::I32);
         
-        let block0;
+        let mut block0;
         {
             let block = func.create_block();
-            block0 = func.use_block(block);
+            block0 = func.use_block(block).unwrap();
         }
 
         block0.require_import("stdio.h".into());
 
         {
-            let tmp0 = block0.iconst_int(21);
-            let tmp1 = block0.iconst_int(21);
-            let tmp2 = block0.iadd(tmp0, tmp1);
+            let tmp0 = block0.iconst_int(21, Type::I32);
+            let tmp1 = block0.iconst_int(21, Type::I32);
+            let tmp2 = block0.iadd(tmp0, tmp1).unwrap();
 
-            let tmp3 = block0.iuse(v.named());
-            block0.set(tmp3, tmp2);
+            let tmp3 = block0.iuse(v.named(), Type::I32);
+            block0.set(tmp3, tmp2).unwrap();
         }
 
         {
+            let printf = Type::Function(FunctionType {
+                params: vec![Type::pointer(Type::Char)],
+                returns: Box::new(Type::I32),
+                variadic: true,
+            });
+
             let tmp0 = Named::new("printf".into());
-            let tmp1 = block0.iuse(tmp0);
-            let tmp2 = block0.iuse(v.named());
+            let tmp1 = block0.iuse(tmp0, printf);
+            let tmp2 = block0.iuse(v.named(), Type::I32);
 
             let str = block0.iconst_str("%d".into());
 
-            block0.call(tmp1, vec![str, tmp2]);
+            block0.call(tmp1, vec![str, tmp2]).unwrap();
+        }
+
+        m.define_function(func);
+
+        let mut gen = CBackend::new(m);
+        println!("{}", gen.emit().unwrap());
+    }
+
+    #[test]
+    pub fn test_if_chain() {
+        let mut m = Module::new();
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("x".into(), Type::I32));
+        sig.returns = Type::I32;
+
+        let mut func = Function::new("sign".into(), sig);
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let x = block0.iconst_named("x".into(), Type::I32);
+        let zero = block0.iconst_int(0, Type::I32);
+        let lt = block0.itest_lt(x, zero).unwrap();
+        let gt = block0.itest_gt(x, zero).unwrap();
+
+        let if_neg = block0.if_block(lt).unwrap();
+        {
+            let mut neg = block0.use_block(if_neg).unwrap();
+            let x = neg.iconst_named("x".into(), Type::I32);
+            let big = neg.iconst_int(100, Type::I32);
+            let cond = neg.itest_lt(x, big).unwrap();
+
+            let nested = neg.if_block(cond).unwrap();
+            let mut inner = neg.use_block(nested).unwrap();
+            let v = inner.iconst_int(2, Type::I32);
+            inner.return_(v).unwrap();
+
+            let one = neg.iconst_int(1, Type::I32);
+            neg.return_(one).unwrap();
+        }
+
+        {
+            let mut pos = block0.elif_block(if_neg, gt).unwrap();
+            let v = pos.iconst_int(3, Type::I32);
+            pos.return_(v).unwrap();
+        }
+
+        {
+            let mut zero = block0.else_block(if_neg).unwrap();
+            let v = zero.iconst_int(0, Type::I32);
+            zero.return_(v).unwrap();
+        }
+
+        let done = block0.iconst_int(4, Type::I32);
+        block0.return_(done).unwrap();
+
+        m.define_function(func);
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        assert!(out.contains(&[
+            "        if (x < 0) {",
+            "            if (x < 100) {",
+            "                return 2;",
+            "            }",
+            "            return 1;",
+            "        } else if (x > 0) {",
+            "            return 3;",
+            "        } else {",
+            "            return 0;",
+            "        }",
+            "        return 4;",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_loops() {
+        let mut m = Module::new();
+
+        let mut func = Function::new("count".into(), FunctionSignature::new());
+        let i = func.declare_var("i".into(), Type::I32);
+
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let iv = block0.iuse(i.named(), Type::I32);
+        let zero = block0.iconst_int(0, Type::I32);
+        let one = block0.iconst_int(1, Type::I32);
+        let ten = block0.iconst_int(10, Type::I32);
+        let init = block0.iset(iv, zero).unwrap();
+        let cond = block0.itest_lt(iv, ten).unwrap();
+        let next = block0.iadd(iv, one).unwrap();
+        let step = block0.iset(iv, next).unwrap();
+
+        let for_loop = block0.for_block(Some(init), cond, Some(step)).unwrap();
+        {
+            let mut body = block0.use_block(for_loop).unwrap();
+            let iv = body.iuse(i.named(), Type::I32);
+            let five = body.iconst_int(5, Type::I32);
+            let is_five = body.itest_eq(iv, five).unwrap();
+
+            let skip = body.if_block(is_five).unwrap();
+            body.use_block(skip).unwrap().continue_().unwrap();
+
+            let t = body.iconst_bool(true);
+            let inner = body.while_block(t).unwrap();
+            body.use_block(inner).unwrap().break_().unwrap();
+        }
+
+        let f = block0.iconst_bool(false);
+        let do_loop = block0.do_while_block(f).unwrap();
+        {
+            let mut body = block0.use_block(do_loop).unwrap();
+            let iv = body.iuse(i.named(), Type::I32);
+            let one = body.iconst_int(1, Type::I32);
+            let prev = body.isub(iv, one).unwrap();
+            body.set(iv, prev).unwrap();
+        }
+
+        block0.jmp(block).unwrap();
+
+        m.define_function(func);
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        assert!(out.contains(&[
+            "        for (i = 0; i < 10; i = i + 1) {",
+            "            if (i == 5) {",
+            "                continue;",
+            "            }",
+            "            while (true) {",
+            "                break;",
+            "            }",
+            "        }",
+            "        do {",
+            "            i = i - 1;",
+            "        } while (false);",
+            "        goto block0;",
+        ].join("\n")));
+
+        // `true` and `false` need `stdbool.h` before C23.
+        assert!(out.starts_with("#include <stdbool.h>\n"));
+    }
+
+    #[test]
+    pub fn test_structs() {
+        let mut m = Module::new();
+        let int = Type::I32;
+
+        m.define_struct("Line".into(), vec![
+            StructField("start".into(), Type::Struct("Point".into())),
+            StructField("end".into(), Type::Struct("Point".into())),
+        ]);
+        m.define_struct("Node".into(), vec![
+            StructField("value".into(), int.clone()),
+            StructField("points".into(), Type::array(Type::Struct("Point".into()), Some(2))),
+            StructField("next".into(), Type::pointer(Type::Struct("Node".into()))),
+            StructField("handle".into(), Type::pointer(Type::Struct("Handle".into()))),
+        ]);
+        m.declare_struct("Handle".into());
+        m.define_struct("Point".into(), vec![
+            StructField("x".into(), int.clone()),
+            StructField("y".into(), int),
+        ]);
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("line".into(), Type::pointer(Type::Struct("Line".into()))));
+
+        let mut func = Function::new("reset".into(), sig);
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let x = block0.iconst_named_props("line".into(), vec![
+            NamedProperty::Pointer("start".into()),
+            NamedProperty::Basic("x".into()),
+        ], Type::I32);
+        let zero = block0.iconst_int(0, Type::I32);
+        block0.set(x, zero).unwrap();
+
+        m.define_function(func);
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        assert!(out.contains(&[
+            "typedef struct Line Line;",
+            "typedef struct Node Node;",
+            "typedef struct Handle Handle;",
+            "typedef struct Point Point;",
+            "",
+            "struct Point {",
+            "    int32_t x;",
+            "    int32_t y;",
+            "};",
+            "",
+            "struct Line {",
+            "    Point start;",
+            "    Point end;",
+            "};",
+            "",
+            "struct Node {",
+            "    int32_t value;",
+            "    Point points[2];",
+            "    Node* next;",
+            "    Handle* handle;",
+            "};",
+            "",
+            "void reset(Line* line);",
+            "",
+            "void reset(Line* line) {",
+            "    {",
+            "        line->start.x = 0;",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_globals() {
+        let mut m = Module::new();
+        let int = Type::I32;
+
+        m.define_struct("Point".into(), vec![
+            StructField("x".into(), int.clone()),
+            StructField("y".into(), int.clone()),
+        ]);
+
+        m.declare_variable("counter".into(), int.clone());
+
+        let mut table = GlobalData::new(Type::array(Type::I32, None));
+        table.linkage = Linkage::Internal;
+        table.constant = true;
+        table.init = Some(Constant::Array(vec![Constant::Integer(1), Constant::Integer(2), Constant::Integer(3)]));
+        m.define_variable("table".into(), table);
+
+        let mut greeting = GlobalData::new(Type::pointer(Type::Char));
+        greeting.constant = true;
+        greeting.init = Some(Constant::String("hello".into(), StringEncoding::Narrow));
+        m.define_variable("greeting".into(), greeting);
+
+        let mut origin = GlobalData::new(Type::Struct("Point".into()));
+        origin.init = Some(Constant::Struct(vec![
+            ("x".into(), Constant::Integer(0)),
+            ("y".into(), Constant::Integer(0)),
+        ]));
+        m.define_variable("origin".into(), origin);
+
+        let mut errno = GlobalData::new(int);
+        errno.linkage = Linkage::Import;
+        m.define_variable("errno".into(), errno);
+
+        let mut flag = GlobalData::new(Type::Bool);
+        flag.linkage = Linkage::Internal;
+        flag.init = Some(Constant::Boolean(false));
+        m.define_variable("flag".into(), flag);
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        assert!(out.starts_with("#include <stdint.h>\n#include <stdbool.h>\n"));
+        assert!(out.contains("\nint32_t counter;\n"));
+        assert!(out.contains("\nstatic const int32_t table[] = { 1, 2, 3 };\n"));
+        assert!(out.contains("\nchar* const greeting = \"hello\";\n"));
+        assert!(out.contains("\nPoint origin = { .x = 0, .y = 0 };\n"));
+        assert!(out.contains("\nextern int32_t errno;\n"));
+        assert!(out.contains("\nstatic bool flag = false;\n"));
+        assert!(out.find("struct Point {").unwrap() < out.find("Point origin").unwrap());
+    }
+
+    #[test]
+    pub fn test_float_constants() {
+        let src = "
+            global scale: float = float 3
+            global limit: double = double 100
+
+            function f() -> double {
+                block0 {
+                    v0 = double 2.5
+                    v1 = add v0, v0
+                    v2 = named printf: fn(char*, ...) -> i32
+                    v3 = str \"%f\"
+                    call v2, v3, v1
+                    v4 = float 0.5
+                    v5 = float 2
+                    v6 = mul v4, v5
+                    call v2, v3, v6
+                    v7 = double 1
+                    v8 = double 0
+                    v9 = div v7, v8
+                    v10 = double 0.0000001
+                    v11 = add v9, v10
+                    ret v11
+                }
+            }
+        ";
+
+        let mut m = parse_module(src).unwrap();
+        let mut manager = PassManager::new();
+        manager.add(ConstantFolding);
+        manager.run(&mut m).unwrap();
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        // Whole numbers keep their decimal point, so they aren't passed as integers, and an
+        // infinite result is left for C to compute.
+        assert!(out.contains("\nfloat scale = 3.0f;\n"));
+        assert!(out.contains("\ndouble limit = 100.0;\n"));
+        assert!(out.contains("printf(\"%f\", 5.0);"));
+        assert!(out.contains("printf(\"%f\", 1.0f);"));
+        assert!(out.contains("return 1.0 / 0.0 + 1e-7;"));
+    }
+
+    #[test]
+    pub fn test_types() {
+        let mut m = Module::new();
+
+        let callback = Type::Function(FunctionType {
+            params: vec![Type::I32],
+            returns: Box::new(Type::I32),
+            variadic: false,
+        });
+
+        m.declare_variable("handler".into(), Type::pointer(callback.clone()));
+        m.declare_variable("handlers".into(), Type::array(Type::pointer(callback), Some(4)));
+        m.declare_variable("rows".into(), Type::pointer(Type::array(Type::U8, Some(16))));
+        m.declare_variable("grid".into(), Type::array(Type::array(Type::Double, Some(3)), Some(2)));
+        m.declare_variable("name".into(), Type::pointer(Type::Char));
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("size".into(), Type::USize));
+        sig.returns = Type::pointer(Type::Void);
+        m.define_function(Function::new("alloc".into(), sig));
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        assert!(out.contains("\nint32_t (*handler)(int32_t);\n"));
+        assert!(out.contains("\nint32_t (*handlers[4])(int32_t);\n"));
+        assert!(out.contains("\nuint8_t (*rows)[16];\n"));
+        assert!(out.contains("\ndouble grid[2][3];\n"));
+        assert!(out.contains("\nchar* name;\n"));
+        assert!(out.contains("void* alloc(uintptr_t size)"));
+    }
+
+    #[test]
+    pub fn test_temporaries() {
+        let mut m = Module::new();
+
+        let mut next_sig = FunctionSignature::new();
+        next_sig.returns = Type::I32;
+        let next_fn = Function::new("next".into(), next_sig);
+        let next_ty = next_fn.signature.function_type();
+        m.define_function(next_fn);
+
+        let mut log_sig = FunctionSignature::new();
+        log_sig.arguments.push(AbiParam("n".into(), Type::I32));
+        let log_fn = Function::new("log".into(), log_sig);
+        let log_ty = log_fn.signature.function_type();
+        m.define_function(log_fn);
+
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let x = func.declare_var("x".into(), Type::I32);
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let next = block0.iconst_named("next".into(), next_ty);
+        let log = block0.iconst_named("log".into(), log_ty);
+        let xv = block0.iuse(x.named(), Type::I32);
+
+        // Used twice, so the call only happens once.
+        let a = block0.icall(next, vec![]).unwrap();
+        let sum = block0.iadd(a, a).unwrap();
+        block0.set(xv, sum).unwrap();
+
+        // Used once by the next instruction, so it stays inline.
+        let b = block0.icall(next, vec![]).unwrap();
+        block0.set(xv, b).unwrap();
+
+        // Two calls in one expression are evaluated in order.
+        let c = block0.icall(next, vec![]).unwrap();
+        let d = block0.icall(next, vec![]).unwrap();
+        let diff = block0.isub(c, d).unwrap();
+        block0.call(log, vec![diff]).unwrap();
+
+        // Unused calls still happen.
+        block0.icall(next, vec![]).unwrap();
+
+        // Pure values used twice are only computed once.
+        let square = block0.imul(xv, xv).unwrap();
+        let twice = block0.iadd(square, square).unwrap();
+        block0.set(xv, twice).unwrap();
+
+        // A call that happens before another instruction stays before it.
+        let e = block0.icall(next, vec![]).unwrap();
+        block0.call(log, vec![xv]).unwrap();
+        block0.set(xv, e).unwrap();
+
+        m.define_function(func);
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        assert!(out.contains(&[
+            "    {",
+            "        int32_t _t0 = next();",
+            "        x = _t0 + _t0;",
+            "        x = next();",
+            "        int32_t _t1 = next();",
+            "        int32_t _t2 = next();",
+            "        log(_t1 - _t2);",
+            "        next();",
+            "        int32_t _t3 = x * x;",
+            "        x = _t3 + _t3;",
+            "        int32_t _t4 = next();",
+            "        log(x);",
+            "        x = _t4;",
+            "    }",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_read_before_write() {
+        let m = parse_module("
+            function f(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = i32 1
+                    v2 = add v0, v1
+                    v3 = i32 5
+                    set v0, v3
+                    ret v2
+                }
+            }
+        ").unwrap();
+
+        assert_eq!(Interpreter::new(&m).call("f", &[DataValue::I32(10)]), Ok(DataValue::I32(11)));
+
+        // `x + 1` is read before `x` is written, so it is stored in a temporary.
+        let out = CBackend::new(m).emit().unwrap();
+
+        assert!(out.contains(&[
+            "        int32_t _t0 = x + 1;",
+            "        x = 5;",
+            "        return _t0;",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_read_in_nested_block() {
+        let m = parse_module("
+            function f(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = bool true
+                    v2 = block block0
+                    enter v2
+                    ret v0
+                    block0 if v1 {
+                        v3 = i32 2
+                        v4 = mul v0, v3
+                        v5 = i32 7
+                        set v0, v5
+                        v6 = add v4, v0
+                        ret v6
+                    }
+                }
+            }
+
+            function g(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = i32 2
+                    v2 = mul v0, v1
+                    v3 = bool true
+                    v4 = block block0
+                    enter v4
+                    ret v0
+                    block0 if v3 {
+                        v5 = i32 7
+                        set v0, v5
+                        v6 = add v2, v0
+                        ret v6
+                    }
+                }
+            }
+        ").unwrap();
+
+        {
+            let mut interpreter = Interpreter::new(&m);
+            assert_eq!(interpreter.call("f", &[DataValue::I32(10)]), Ok(DataValue::I32(27)));
+            assert_eq!(interpreter.call("g", &[DataValue::I32(10)]), Ok(DataValue::I32(27)));
+        }
+
+        // The read is stored whether it happens in the nested block, or before a nested block
+        // that writes.
+        let out = CBackend::new(m).emit().unwrap();
+
+        assert!(out.contains(&[
+            "        if (true) {",
+            "            int32_t _t0 = x * 2;",
+            "            x = 7;",
+            "            return _t0 + x;",
+            "        }",
+        ].join("\n")));
+        assert!(out.contains(&[
+            "        int32_t _t0 = x * 2;",
+            "        if (true) {",
+            "            x = 7;",
+            "            return _t0 + x;",
+            "        }",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_read_after_loop() {
+        let m = parse_module("
+            function f(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = i32 1
+                    v2 = add v0, v1
+                    v3 = i32 20
+                    v4 = test_lt v0, v3
+                    v5 = block block0
+                    enter v5
+                    ret v2
+                    block0 while v4 {
+                        v6 = add v0, v1
+                        set v0, v6
+                    }
+                }
+            }
+        ").unwrap();
+
+        assert_eq!(Interpreter::new(&m).call("f", &[DataValue::I32(10)]), Ok(DataValue::I32(11)));
+
+        // The loop writes `x`, so `x + 1` is stored before it, while the loop condition is
+        // still read on each iteration.
+        let out = CBackend::new(m).emit().unwrap();
+
+        assert!(out.contains(&[
+            "        int32_t _t0 = x + 1;",
+            "        while (x < 20) {",
+            "            x = x + 1;",
+            "        }",
+            "        return _t0;",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_precedence() {
+        // The binary operators of C, along with their precedence from the C standard, where
+        // higher numbers bind more tightly.
+        let binary = [
+            (Opcode::Mul, "*", 13),
+            (Opcode::Div, "/", 13),
+            (Opcode::Mod, "%", 13),
+            (Opcode::Add, "+", 12),
+            (Opcode::Sub, "-", 12),
+            (Opcode::BitLeft, "<<", 11),
+            (Opcode::BitRight, ">>", 11),
+            (Opcode::TestGt, ">", 10),
+            (Opcode::TestGtEq, ">=", 10),
+            (Opcode::TestLt, "<", 10),
+            (Opcode::TestLtEq, "<=", 10),
+            (Opcode::TestEq, "==", 9),
+            (Opcode::TestNeq, "!=", 9),
+            (Opcode::BitAnd, "&", 8),
+            (Opcode::BitXor, "^", 7),
+            (Opcode::BitOr, "|", 6),
+            (Opcode::And, "&&", 5),
+            (Opcode::Or, "||", 4),
+        ];
+        let unary = [(Opcode::BitNot, "~"), (Opcode::Not, "!")];
+        let unary_prec = 14;
+
+        // Every other opcode either has no expression operands, or is checked separately.
+        for opcode in Opcode::ALL.iter() {
+            let covered = binary.iter().any(|(op, _, _)| op == opcode)
+                || unary.iter().any(|(op, _)| op == opcode);
+
+            assert!(covered || matches!(opcode, Opcode::Set | Opcode::Call | Opcode::Ret
+                | Opcode::Jmp | Opcode::Brif | Opcode::Switch | Opcode::Break | Opcode::Continue
+                | Opcode::Enter | Opcode::Unreachable),
+                "{:?}", opcode);
+        }
+
+        let mut m = Module::new();
+        let mut sig = FunctionSignature::new();
+        sig.returns = Type::I32;
+        let mut func = Function::new("exprs".into(), sig);
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let a = block0.iconst_named("a".into(), Type::I32);
+        let b = block0.iconst_named("b".into(), Type::I32);
+        let c = block0.iconst_named("c".into(), Type::I32);
+
+        // Instructions are created without type checks, so that every combination of opcodes
+        // can be printed.
+        fn value(block: &mut BlockBuilder, opcode: Opcode, arguments: Vec<Value>) -> Value {
+            block.create_value(ValueInfo::Instruction(InstructionInfo { opcode, arguments }), Type::I32)
+        }
+
+        fn inst(block: &mut BlockBuilder, opcode: Opcode, arguments: Vec<Value>) {
+            block.create_inst(InstructionInfo { opcode, arguments });
+        }
+
+        // Each expression gets a block of its own, so that no value is defined before a write
+        // that would store it in a temporary.
+        fn next_block(func: &mut Function) -> BlockBuilder<'_> {
+            let block = func.create_block();
+            func.use_block(block).unwrap()
+        }
+
+        let wrap = |expr: &str, parens: bool| if parens { format!("({})", expr) } else { expr.to_string() };
+
+        let mut inners: Vec<(Opcode, String, u8)> = binary.iter()
+            .map(|(op, sym, prec)| (*op, format!("a {} b", sym), *prec))
+            .collect();
+        inners.extend(unary.iter().map(|(op, sym)| (*op, format!("{}a", sym), unary_prec)));
+
+        let mut expected = vec![];
+
+        for (inner_op, inner, inner_prec) in &inners {
+            let build = |block0: &mut BlockBuilder| {
+                if *inner_prec == unary_prec {
+                    value(block0, *inner_op, vec![a])
+                } else {
+                    value(block0, *inner_op, vec![a, b])
+                }
+            };
+
+            for (op, sym, prec) in &binary {
+                let mut block0 = next_block(&mut func);
+                let left = build(&mut block0);
+                let v = value(&mut block0, *op, vec![left, c]);
+                inst(&mut block0, Opcode::Ret, vec![v]);
+                expected.push(format!("return {} {} c;", wrap(inner, inner_prec < prec), sym));
+
+                let mut block0 = next_block(&mut func);
+                let right = build(&mut block0);
+                let v = value(&mut block0, *op, vec![c, right]);
+                inst(&mut block0, Opcode::Ret, vec![v]);
+                expected.push(format!("return c {} {};", sym, wrap(inner, inner_prec <= prec)));
+            }
+
+            for (op, sym) in &unary {
+                let mut block0 = next_block(&mut func);
+                let operand = build(&mut block0);
+                let v = value(&mut block0, *op, vec![operand]);
+                inst(&mut block0, Opcode::Ret, vec![v]);
+                expected.push(format!("return {}{};", sym, wrap(inner, *inner_prec < unary_prec)));
+            }
+
+            let mut block0 = next_block(&mut func);
+            let v = build(&mut block0);
+            inst(&mut block0, Opcode::Set, vec![c, v]);
+            expected.push(format!("c = {};", inner));
+
+            let mut block0 = next_block(&mut func);
+            let callee = build(&mut block0);
+            inst(&mut block0, Opcode::Call, vec![callee, a]);
+            expected.push(format!("({})(a);", inner));
+
+            let mut block0 = next_block(&mut func);
+            let arg = build(&mut block0);
+            inst(&mut block0, Opcode::Call, vec![c, arg, b]);
+            expected.push(format!("c({}, b);", inner));
+
+            let mut block0 = next_block(&mut func);
+            let v = build(&mut block0);
+            inst(&mut block0, Opcode::Ret, vec![v]);
+            expected.push(format!("return {};", inner));
         }
 
         m.define_function(func);
 
         let mut gen = CBackend::new(m);
-        println!("{}", gen.emit());
+        let out = gen.emit().unwrap();
+
+        let lines: Vec<&str> = out.lines()
+            .map(|line| line.trim())
+            .skip_while(|line| *line != "{")
+            .filter(|line| *line != "{" && *line != "}")
+            .take(expected.len())
+            .collect();
+
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    pub fn test_parentheses() {
+        let mut m = Module::new();
+
+        let mut next_sig = FunctionSignature::new();
+        next_sig.returns = Type::I32;
+        let next_ty = next_sig.function_type();
+        m.define_function(Function::new("next".into(), next_sig));
+
+        let mut sig = FunctionSignature::new();
+        sig.returns = Type::I32;
+
+        let mut func = Function::new("f".into(), sig);
+        let x = func.declare_var("x".into(), Type::I32);
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let a = block0.iconst_named("a".into(), Type::I32);
+        let b = block0.iconst_named("b".into(), Type::I32);
+        let c = block0.iconst_named("c".into(), Type::I32);
+        let xv = block0.iuse(x.named(), Type::I32);
+        let next = block0.iconst_named("next".into(), next_ty);
+        let zero = block0.iconst_int(0, Type::I32);
+
+        // Values used by a loop condition are evaluated on every iteration, so assignments
+        // stay inline and are wrapped in parentheses.
+        let call = block0.icall(next, vec![]).unwrap();
+        let assign = block0.iset(xv, call).unwrap();
+        let cond = block0.itest_neq(assign, zero).unwrap();
+        block0.while_block(cond).unwrap();
+
+        let sum = block0.iadd(a, b).unwrap();
+        let product = block0.imul(sum, c).unwrap();
+        let diff = block0.isub(b, c).unwrap();
+        let nested = block0.isub(product, diff).unwrap();
+        let lt = block0.itest_lt(a, b).unwrap();
+        let gt = block0.itest_gt(b, c).unwrap();
+        let both = block0.iand(lt, gt).unwrap();
+        let not = block0.inot(both).unwrap();
+        let flag = block0.iconst_named("flag".into(), Type::Bool);
+        let either = block0.ior(not, flag).unwrap();
+        block0.if_block(either).unwrap();
+        block0.return_(nested).unwrap();
+
+        m.define_function(func);
+
+        let mut gen = CBackend::new(m);
+        let out = gen.emit().unwrap();
+
+        assert!(out.contains("        while ((x = next()) != 0) {\n"));
+        assert!(out.contains("        if (!(a < b && b > c) || flag) {\n"));
+        assert!(out.contains("        return (a + b) * c - (b - c);\n"));
+    }
+
+    #[test]
+    pub fn test_terminators() {
+        let mut m = Module::new();
+        let log_ty = Type::Function(FunctionType {
+            params: vec![],
+            returns: Box::new(Type::Void),
+            variadic: false,
+        });
+
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let log = block0.iconst_named("log".into(), log_ty);
+        let call = block0.icall(log, vec![]).unwrap();
+        let flag = block0.iconst_named("flag".into(), Type::Bool);
+        let if_flag = block0.if_block(flag).unwrap();
+        block0.use_block(if_flag).unwrap().unreachable().unwrap();
+        block0.return_(call).unwrap();
+        m.define_function(func);
+
+        let out = CBackend::new(m).emit().unwrap();
+
+        assert!(out.contains("#include <stdlib.h>\n"));
+        assert!(out.contains("        if (flag) {\n            abort();\n        }\n"));
+        assert!(out.contains("        log();\n        return;\n"));
+    }
+
+    #[test]
+    pub fn test_branches() {
+        let mut m = Module::new();
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("x".into(), Type::I32));
+        sig.returns = Type::I32;
+
+        let mut func = Function::new("sign".into(), sig);
+        let entry = func.create_block();
+        let negative = func.create_block();
+        let positive = func.create_block();
+
+        let mut block0 = func.use_block(entry).unwrap();
+        let x = block0.iconst_named("x".into(), Type::I32);
+        let zero = block0.iconst_int(0, Type::I32);
+        let cond = block0.itest_lt(x, zero).unwrap();
+        block0.brif(cond, negative, positive).unwrap();
+
+        let mut block1 = func.use_block(negative).unwrap();
+        let one = block1.iconst_int(1, Type::I32);
+        block1.return_(one).unwrap();
+
+        let mut block2 = func.use_block(positive).unwrap();
+        let zero = block2.iconst_int(0, Type::I32);
+        block2.return_(zero).unwrap();
+
+        m.define_function(func);
+
+        let out = CBackend::new(m).emit().unwrap();
+
+        assert!(out.contains(&[
+            "int32_t sign(int32_t x) {",
+            "    {",
+            "        if (x < 0) goto block1; else goto block2;",
+            "    }",
+            "    block1: {",
+            "        return 1;",
+            "    }",
+            "    block2: {",
+            "        return 0;",
+            "    }",
+            "}",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_switch() {
+        let module = |cases: Vec<u64>| {
+            let mut m = Module::new();
+
+            let mut sig = FunctionSignature::new();
+            sig.arguments.push(AbiParam("op".into(), Type::I32));
+            sig.returns = Type::I32;
+
+            let mut func = Function::new("run".into(), sig);
+            let entry = func.create_block();
+            let blocks: Vec<_> = cases.iter().map(|_| func.create_block()).collect();
+            let default = func.create_block();
+
+            let mut block0 = func.use_block(entry).unwrap();
+            let op = block0.iconst_named("op".into(), Type::I32);
+            let one = block0.iconst_int(1, Type::I32);
+            let next = block0.iadd(op, one).unwrap();
+            block0.switch(next, cases.iter().copied().zip(blocks.iter().copied()).collect(), default).unwrap();
+
+            for (n, block) in cases.iter().zip(blocks) {
+                let mut b = func.use_block(block).unwrap();
+                let n = b.iconst_int(*n, Type::I32);
+                b.return_(n).unwrap();
+            }
+
+            let mut b = func.use_block(default).unwrap();
+            let zero = b.iconst_int(0, Type::I32);
+            b.return_(zero).unwrap();
+
+            m.define_function(func);
+            m
+        };
+
+        let out = CBackend::new(module(vec![7, 2])).emit().unwrap();
+
+        assert!(out.contains(&[
+            "        switch (op + 1) {",
+            "            case 2: goto block2;",
+            "            case 7: goto block1;",
+            "            default: goto block3;",
+            "        }",
+        ].join("\n")));
+
+        // Without GNU extensions, dense cases still use a native `switch`.
+        let dense = vec![3, 4, 6, 2];
+        let out = CBackend::new(module(dense.clone())).emit().unwrap();
+        assert!(out.contains("        switch (op + 1) {\n"));
+
+        let mut gen = CBackend::new(module(dense));
+        gen.gnu_extensions = true;
+        let out = gen.emit().unwrap();
+
+        assert!(out.contains(&[
+            "        int32_t _t0 = op + 1;",
+            "        static void *const _t1[] = { &&block4, &&block1, &&block2, &&block5, &&block3 };",
+            "        if (_t0 >= 2 && _t0 <= 6) goto *_t1[_t0 - 2];",
+            "        goto block5;",
+        ].join("\n")));
+
+        // A repeated key would shift the table, and a key that doesn't fit can't be matched.
+        let parsed = |first: &str, second: &str| parse_module(&format!("
+            function run(op: i8) -> i32 {{
+                block0 {{
+                    v0 = named op: i8
+                    v1 = block block1
+                    v2 = i8 {}
+                    v3 = i8 {}
+                    v4 = i8 4
+                    v5 = i8 5
+                    switch v0, v1, v2, v1, v3, v1, v4, v1, v5, v1
+                }}
+                block1 {{
+                    v6 = i32 0
+                    ret v6
+                }}
+            }}
+        ", first, second)).unwrap();
+
+        let mut gen = CBackend::new(parsed("2", "2"));
+        gen.gnu_extensions = true;
+        assert_eq!(gen.emit().unwrap_err().to_string(), "in function `run`, block0: switch case v3 is given twice");
+
+        let mut gen = CBackend::new(parsed("3", "200"));
+        gen.gnu_extensions = true;
+        assert_eq!(
+            gen.emit().unwrap_err().to_string(),
+            "in function `run`, block0: switch case v3 doesn't fit in the scrutinee's type",
+        );
+
+        let mut gen = CBackend::new(parsed("3", "18446744073709551614"));
+        gen.gnu_extensions = true;
+        assert!(gen.emit().unwrap().contains("        if (op >= -2 && op <= 5) goto *_t0[op + 2];\n"));
+    }
+
+    #[test]
+    pub fn test_block_params() {
+        let mut m = Module::new();
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("n".into(), Type::I32));
+        sig.returns = Type::I32;
+
+        let mut func = Function::new("fib".into(), sig);
+        let entry = func.create_block();
+        let body = func.create_block();
+        let exit = func.create_block();
+
+        let mut block2 = func.use_block(exit).unwrap();
+        let result = block2.append_param(Type::I32);
+        block2.return_(result).unwrap();
+
+        let mut block1 = func.use_block(body).unwrap();
+        let a = block1.append_param(Type::I32);
+        let b = block1.append_param(Type::I32);
+        let n = block1.append_param(Type::I32);
+        let zero = block1.iconst_int(0, Type::I32);
+        let one = block1.iconst_int(1, Type::I32);
+        let more = block1.itest_gt(n, zero).unwrap();
+        let sum = block1.iadd(a, b).unwrap();
+        let next = block1.isub(n, one).unwrap();
+        block1.brif(more, BlockCall(body, vec![b, sum, next]), BlockCall(exit, vec![a])).unwrap();
+
+        let mut block0 = func.use_block(entry).unwrap();
+        let zero = block0.iconst_int(0, Type::I32);
+        let one = block0.iconst_int(1, Type::I32);
+        let n = block0.iconst_named("n".into(), Type::I32);
+        block0.jmp(BlockCall(body, vec![zero, one, n])).unwrap();
+
+        m.define_function(func);
+
+        let out = CBackend::new(m).emit().unwrap();
+
+        // Arguments of a block that branches to itself are evaluated before the parameters
+        // are assigned, as they may use the parameters.
+        assert!(out.contains(&[
+            "int32_t fib(int32_t n) {",
+            "    int32_t _b1_0;",
+            "    int32_t _b1_1;",
+            "    int32_t _b1_2;",
+            "    int32_t _b2_0;",
+            "    {",
+            "        _b1_0 = 0;",
+            "        _b1_1 = 1;",
+            "        _b1_2 = n;",
+            "        goto block1;",
+            "    }",
+            "    block1: {",
+            "        if (_b1_2 > 0) {",
+            "            int32_t _t0 = _b1_1;",
+            "            int32_t _t1 = _b1_0 + _b1_1;",
+            "            int32_t _t2 = _b1_2 - 1;",
+            "            _b1_0 = _t0;",
+            "            _b1_1 = _t1;",
+            "            _b1_2 = _t2;",
+            "            goto block1;",
+            "        } else {",
+            "            _b2_0 = _b1_0;",
+            "            goto block2;",
+            "        }",
+            "    }",
+            "    block2: {",
+            "        return _b2_0;",
+            "    }",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_shared_values() {
+        let mut m = Module::new();
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("x".into(), Type::I32));
+        sig.returns = Type::I32;
+
+        let mut func = Function::new("grow".into(), sig);
+        let entry = func.create_block();
+        let small = func.create_block();
+        let large = func.create_block();
+
+        let mut block0 = func.use_block(entry).unwrap();
+        let x = block0.iconst_named("x".into(), Type::I32);
+        let one = block0.iconst_int(1, Type::I32);
+        let ten = block0.iconst_int(10, Type::I32);
+        let sum = block0.iadd(x, one).unwrap();
+        let big = block0.itest_gt(sum, ten).unwrap();
+        block0.brif(big, large, small).unwrap();
+
+        let mut block1 = func.use_block(small).unwrap();
+        block1.return_(sum).unwrap();
+
+        let mut block2 = func.use_block(large).unwrap();
+        let two = block2.iconst_int(2, Type::I32);
+        let product = block2.imul(sum, two).unwrap();
+        block2.return_(product).unwrap();
+
+        m.define_function(func);
+
+        // Each block is a separate C scope, so a value used by other blocks is declared at
+        // the top of the function, while constants are still written where they are used.
+        assert_eq!(CBackend::new(m).emit().unwrap(), [
+            "#include <stdint.h>",
+            "int32_t grow(int32_t x);",
+            "",
+            "int32_t grow(int32_t x) {",
+            "    int32_t _t0;",
+            "    {",
+            "        _t0 = x + 1;",
+            "        if (_t0 > 10) goto block2; else goto block1;",
+            "    }",
+            "    block1: {",
+            "        return _t0;",
+            "    }",
+            "    block2: {",
+            "        return _t0 * 2;",
+            "    }",
+            "}",
+        ].join("\n"));
+    }
+
+    #[test]
+    pub fn test_output_order() {
+        let build = || {
+            let mut m = Module::new();
+
+            for name in &["zeta", "alpha", "mid", "beta"] {
+                m.declare_variable(name.to_string(), Type::I32);
+
+                let mut func = Function::new(name.to_string(), FunctionSignature::new());
+                func.declare_var("z".into(), Type::I32);
+                func.declare_var("a".into(), Type::Bool);
+                func.declare_var("m".into(), Type::Char);
+
+                let block = func.create_block();
+                func.use_block(block).unwrap().return_none().unwrap();
+                m.define_function(func);
+            }
+
+            CBackend::new(m).emit().unwrap()
+        };
+
+        let out = build();
+
+        // The same module always produces the same code, in the order that it was declared.
+        for _ in 0..8 {
+            assert_eq!(build(), out);
+        }
+
+        assert!(out.contains("int32_t zeta;\nint32_t alpha;\nint32_t mid;\nint32_t beta;\n"));
+        assert!(out.contains("void zeta(void);\nvoid alpha(void);\nvoid mid(void);\nvoid beta(void);\n\n"));
+        assert!(out.contains("void zeta(void) {\n    int32_t z;\n    bool a;\n    char m;\n"));
+
+        let order: Vec<_> = ["void zeta(void) {", "void alpha(void) {", "void mid(void) {", "void beta(void) {"].iter()
+            .map(|f| out.find(f).unwrap())
+            .collect();
+
+        assert!(order.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    pub fn test_prototypes() {
+        let mut m = Module::new();
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("s".into(), Type::pointer(Type::Char)));
+        sig.returns = Type::I32;
+        m.declare_function("puts".into(), sig);
+
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+        let greet = Type::Function(FunctionType {
+            params: vec![],
+            returns: Box::new(Type::Void),
+            variadic: false,
+        });
+        let callee = block0.iuse(Named::new("greet".into()), greet);
+        block0.call(callee, vec![]).unwrap();
+        block0.return_none().unwrap();
+        m.define_function(func);
+
+        let mut func = Function::new("greet".into(), FunctionSignature::new());
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+        let puts = Type::Function(FunctionType {
+            params: vec![Type::pointer(Type::Char)],
+            returns: Box::new(Type::I32),
+            variadic: false,
+        });
+        let callee = block0.iuse(Named::new("puts".into()), puts);
+        let str = block0.iconst_str("hi".into());
+        block0.call(callee, vec![str]).unwrap();
+        block0.return_none().unwrap();
+        m.define_function(func);
+
+        // Every function is declared before the definitions, so `main` can call `greet`, and
+        // `puts` is only declared.
+        assert!(CBackend::new(m).emit().unwrap().ends_with(&[
+            "extern int32_t puts(char* s);",
+            "void main(void);",
+            "void greet(void);",
+            "",
+            "void main(void) {",
+            "    {",
+            "        greet();",
+            "        return;",
+            "    }",
+            "}",
+            "void greet(void) {",
+            "    {",
+            "        puts(\"hi\");",
+            "        return;",
+            "    }",
+            "}",
+        ].join("\n")));
+    }
+
+    #[test]
+    pub fn test_library() {
+        let mut m = Module::new();
+
+        m.define_struct("Point".into(), vec![
+            StructField("x".into(), Type::I32),
+            StructField("y".into(), Type::I32),
+        ]);
+
+        let mut counter = GlobalData::new(Type::I64);
+        counter.init = Some(Constant::Integer(0));
+        m.define_variable("counter".into(), counter);
+
+        let mut cache = GlobalData::new(Type::Bool);
+        cache.linkage = Linkage::Internal;
+        cache.init = Some(Constant::Boolean(false));
+        m.define_variable("cache".into(), cache);
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("p".into(), Type::pointer(Type::Struct("Point".into()))));
+        sig.returns = Type::I32;
+
+        let mut func = Function::new("helper".into(), sig);
+        func.linkage = Linkage::Internal;
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+        let x = block0.iconst_named_props("p".into(), vec![NamedProperty::Pointer("x".into())], Type::I32);
+        block0.return_(x).unwrap();
+        m.define_function(func);
+
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("p".into(), Type::pointer(Type::Struct("Point".into()))));
+        sig.returns = Type::I32;
+
+        let mut func = Function::new("norm".into(), sig);
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+        let helper = block0.iuse(Named::new("helper".into()), Type::Function(FunctionType {
+            params: vec![Type::pointer(Type::Struct("Point".into()))],
+            returns: Box::new(Type::I32),
+            variadic: false,
+        }));
+        let p = block0.iuse(Named::new("p".into()), Type::pointer(Type::Struct("Point".into())));
+        let res = block0.icall(helper, vec![p]).unwrap();
+        block0.return_(res).unwrap();
+        m.define_function(func);
+
+        let (header, source) = CBackend::new(m).emit_library("geometry/point.h").unwrap();
+
+        assert_eq!(header, [
+            "#ifndef GEOMETRY_POINT_H",
+            "#define GEOMETRY_POINT_H",
+            "",
+            "#include <stdint.h>",
+            "",
+            "typedef struct Point Point;",
+            "",
+            "struct Point {",
+            "    int32_t x;",
+            "    int32_t y;",
+            "};",
+            "",
+            "extern int64_t counter;",
+            "",
+            "int32_t norm(Point* p);",
+            "",
+            "#endif",
+        ].join("\n"));
+
+        // Internal functions and globals are `static`, and stay out of the header.
+        assert_eq!(source, [
+            "#include \"geometry/point.h\"",
+            "#include <stdbool.h>",
+            "",
+            "int64_t counter = 0;",
+            "static bool cache = false;",
+            "",
+            "static int32_t helper(Point* p);",
+            "",
+            "static int32_t helper(Point* p) {",
+            "    {",
+            "        return p->x;",
+            "    }",
+            "}",
+            "int32_t norm(Point* p) {",
+            "    {",
+            "        return helper(p);",
+            "    }",
+            "}",
+        ].join("\n"));
+    }
+
+    #[test]
+    pub fn test_strings() {
+        let mut m = Module::new();
+
+        let mut raw = GlobalData::new(Type::pointer(Type::Char));
+        raw.init = Some(Constant::Bytes(vec![0, b'1', 0xff, b'"']));
+        m.define_variable("raw".into(), raw);
+
+        let mut wide = GlobalData::new(Type::pointer(Type::I32));
+        wide.init = Some(Constant::String("caf\u{e9} \u{1f600}\u{85}".into(), StringEncoding::Wide));
+        m.define_variable("wide".into(), wide);
+
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let units = func.declare_var("units".into(), Type::pointer(Type::U16));
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+        let puts = block0.iuse(Named::new("puts".into()), Type::Function(FunctionType {
+            params: vec![Type::pointer(Type::Char)],
+            returns: Box::new(Type::I32),
+            variadic: false,
+        }));
+
+        for text in &["say \"hi\"\\n\t??=\0\u{7}1", "caf\u{e9}", &"abc ".repeat(25), "line one\nline two"] {
+            let str = block0.iconst_str(text.to_string());
+            block0.call(puts, vec![str]).unwrap();
+        }
+
+        let str = block0.iconst_encoded_str("caf\u{e9}".into(), StringEncoding::Utf8);
+        block0.call(puts, vec![str]).unwrap();
+        let str = block0.iconst_encoded_str("\u{1f600}".into(), StringEncoding::Utf16);
+        let var = block0.iuse(units.named(), Type::pointer(Type::U16));
+        block0.set(var, str).unwrap();
+        let str = block0.iconst_bytes(vec![b'?', b'?', b'/', 0x80]);
+        block0.call(puts, vec![str]).unwrap();
+        block0.return_none().unwrap();
+        m.define_function(func);
+
+        let out = CBackend::new(m).emit().unwrap();
+
+        // Escapes are always three octal digits, so that a following digit isn't part of them,
+        // and `??` is broken up so that it can't start a trigraph.
+        assert!(out.contains("\nchar* raw = \"\\0001\\377\\\"\";\n"));
+        assert!(out.contains("\nint32_t* wide = L\"caf\\u00e9 \\U0001f600\\205\";\n"));
+        assert!(out.contains("        puts(\"say \\\"hi\\\"\\\\n\\t?\\?=\\000\\0071\");\n"));
+        assert!(out.contains("        puts(\"caf\\303\\251\");\n"));
+        assert!(out.contains(&format!("        puts(\"{}\"\n            \"{}\");\n", "abc ".repeat(20), "abc ".repeat(5))));
+        assert!(out.contains("        puts(\"line one\\nline two\");\n"));
+        assert!(out.contains("        puts(u8\"caf\\303\\251\");\n"));
+        assert!(out.contains("        units = u\"\\U0001f600\";\n"));
+        assert!(out.contains("        puts(\"?\\?/\\200\");\n"));
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let mut m = Module::new();
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let block = func.create_block();
+
+        assert_eq!(func.use_block(Block(5)).unwrap_err(), CodegenError::MissingBlock {
+            function: Some("main".into()),
+            block: Block(5),
+        });
+
+        let mut block0 = func.use_block(block).unwrap();
+        block0.return_none().unwrap();
+        m.define_function(func);
+
+        let mut gen = CBackend::new(m);
+        assert!(gen.emit().unwrap().contains("        return;\n"));
+
+        let mut m = Module::new();
+        let mut sig = FunctionSignature::new();
+        sig.returns = Type::I32;
+        let mut func = Function::new("main".into(), sig);
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let prop = block0.iconst_named_static("count".into());
+        let count = block0.iconst_named_props("Counter".into(), vec![prop], Type::I32);
+        block0.return_(count).unwrap();
+        m.define_function(func);
+
+        let err = CBackend::new(m).emit().unwrap_err();
+
+        assert_eq!(err, CodegenError::UnsupportedProperty {
+            function: "main".into(),
+            block: "block0".into(),
+            value: Value(0),
+            property: "::count".into(),
+        });
+        assert_eq!(err.to_string(), "in function `main`, block0: v0 uses the unsupported property `::count`");
+
+        let mut m = Module::new();
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+        let nested = block0.create_block(InstBlock::new(BlockType::Basic));
+        block0.use_block(nested).unwrap().create_inst(InstructionInfo {
+            opcode: Opcode::Jmp,
+            arguments: vec![Value(3)],
+        });
+        m.define_function(func);
+
+        assert_eq!(CBackend::new(m).emit().unwrap_err(), CodegenError::InvalidValue {
+            function: "main".into(),
+            block: "block0/block0".into(),
+            value: Value(3),
+        });
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,69 @@
+//! Exposes types for global data declarations and their initializers.
+
+use crate::entities::{Linkage, StringEncoding, Type};
+
+/// A constant that can be used to initialize global data.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Constant {
+
+    /// An integer constant.
+    Integer(u64),
+
+    /// A floating point number constant.
+    Float(f64),
+
+    /// A double constant.
+    Double(f64),
+
+    /// A boolean constant.
+    Boolean(bool),
+
+    /// A string constant, in the given encoding.
+    String(String, StringEncoding),
+
+    /// A string of raw bytes.
+    Bytes(Vec<u8>),
+
+    /// A character constant.
+    Char(String),
+
+    /// An array of constants, in order.
+    Array(Vec<Constant>),
+
+    /// A struct aggregate, with a constant for each named field.
+    Struct(Vec<(String, Constant)>),
+
+}
+
+/// A global variable declared in a module.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GlobalData {
+
+    /// The type of the global variable.
+    pub ty: Type,
+
+    /// The value that the global variable starts with, if any.
+    pub init: Option<Constant>,
+
+    /// Where the global variable is visible from.  Imported globals can't have an
+    /// initializer, since they are defined somewhere else.
+    pub linkage: Linkage,
+
+    /// Whether the global variable is read-only.
+    pub constant: bool,
+
+}
+
+impl GlobalData {
+
+    /// Creates an exported, mutable global variable with no initializer.
+    pub fn new(ty: Type) -> Self {
+        Self {
+            ty,
+            init: None,
+            linkage: Linkage::Export,
+            constant: false,
+        }
+    }
+
+}
@@ -0,0 +1,526 @@
+//! Removes code that can't affect the result of a function.
+//!
+//! Blocks of a function that can't be reached from its first block are removed, along with
+//! the values defined in them.  Values are removed when nothing uses them and they have no
+//! side effects, which includes calls to functions that are `Pure` or `Const`.  Variables
+//! are removed when nothing reads them, along with the `set` instructions that write to them.
+//! The values and blocks that are left are renumbered in order, so the function stays valid.
+
+use crate::entities::{Block, NamedProperty, Value, ValueInfo};
+use crate::function::{Function, ValueTable};
+use crate::instruction::{BlockType, InstBlock, InstructionInfo, Opcode};
+use crate::module::Module;
+use crate::pass::{Analyses, Pass};
+use std::collections::HashSet;
+use std::fmt;
+
+/// What dead code elimination removed from a module.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Removed {
+
+    /// The number of values that were removed, including the instructions that defined them.
+    pub values: usize,
+
+    /// The number of instructions that were removed without being values, such as `set`
+    /// instructions that wrote to removed variables.
+    pub instructions: usize,
+
+    /// The blocks that were removed, by function, numbered as they were before the blocks
+    /// after them were renumbered.
+    pub blocks: Vec<(String, Block)>,
+
+    /// The variables that were removed, by function.
+    pub variables: Vec<(String, String)>,
+
+}
+
+impl Removed {
+
+    /// Returns true if nothing was removed.
+    pub fn is_empty(&self) -> bool {
+        self.values == 0 && self.instructions == 0 && self.blocks.is_empty() && self.variables.is_empty()
+    }
+
+}
+
+impl fmt::Display for Removed {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |n: usize, name: &str| format!("{} {}{}", n, name, if n == 1 { "" } else { "s" });
+
+        write!(f, "removed {}, {}, {} and {}",
+            count(self.values, "value"),
+            count(self.instructions, "instruction"),
+            count(self.blocks.len(), "block"),
+            count(self.variables.len(), "variable"),
+        )
+    }
+
+}
+
+/// A pass that removes unreachable blocks, unused values without side effects, and variables
+/// that are never read.  What it removed the last time that it ran is kept in `removed`.
+#[derive(Clone, Debug, Default)]
+pub struct DeadCodeElimination {
+
+    /// What the pass removed the last time that it ran.
+    pub removed: Removed,
+
+    /// The functions of the module whose calls have no side effects, by name.
+    pure: HashSet<String>,
+
+}
+
+impl DeadCodeElimination {
+
+    /// Creates the pass.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+}
+
+impl Pass for DeadCodeElimination {
+
+    fn name(&self) -> &str {
+        "dead-code-elimination"
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some(self.removed.to_string())
+    }
+
+    fn run_module(&mut self, module: &mut Module, analyses: &mut Analyses) -> bool {
+        self.removed = Removed::default();
+        self.pure = module.functions.iter()
+            .filter(|(name, func)| func.is_pure() && !module.data.contains_key(name))
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        for func in module.functions.values_mut() {
+            if !func.blocks.is_empty() {
+                self.run_function(func, analyses);
+            }
+        }
+
+        !self.removed.is_empty()
+    }
+
+    fn run_function(&mut self, func: &mut Function, _analyses: &mut Analyses) -> bool {
+        let before = self.removed.clone();
+
+        remove_unreachable_blocks(func, &mut self.removed);
+
+        // Removing a variable's `set` instructions can leave the values that they assigned
+        // unused, so the function is swept until nothing changes.
+        loop {
+            let dead = dead_variables(func);
+            let instructions = remove_instructions(func, &dead, &self.pure);
+
+            let live = live_values(func, &dead, &self.pure);
+            let values = live.iter().filter(|live| !**live).count();
+            retain_values(func, &live);
+
+            let mut variables = 0;
+
+            for name in dead {
+                if !is_referenced(func, &name) {
+                    func.variables.remove(&name);
+                    self.removed.variables.push((func.name.to_string(), name));
+                    variables += 1;
+                }
+            }
+
+            self.removed.instructions += instructions;
+            self.removed.values += values;
+
+            if instructions == 0 && values == 0 && variables == 0 {
+                break;
+            }
+        }
+
+        self.removed != before
+    }
+
+}
+
+/// Returns true if an instruction has no effect other than producing its result.  Calls only
+/// have no effects when the value that they call is marked in `pure_callees`.
+fn is_pure(inst: &InstructionInfo, pure_callees: &[bool]) -> bool {
+    match inst.opcode {
+        Opcode::Call => inst.arguments.first()
+            .is_some_and(|callee| pure_callees.get(callee.0 as usize) == Some(&true)),
+        Opcode::Set => false,
+        opcode => !opcode.is_control_flow(),
+    }
+}
+
+/// Returns whether each value of a function refers to one of the given functions by name.
+fn pure_callees(func: &Function, pure: &HashSet<String>) -> Vec<bool> {
+    (0..func.values.len())
+        .map(|v| func.callee(Value(v as u32)).is_some_and(|name| pure.contains(name)))
+        .collect()
+}
+
+/// Returns the values that an instruction of a block branches to, which are the blocks of
+/// the function rather than nested blocks.
+fn branch_arguments(inst: &InstructionInfo) -> &[Value] {
+    match inst.opcode {
+        Opcode::Jmp => &inst.arguments[..],
+        Opcode::Brif | Opcode::Switch => inst.arguments.get(1..).unwrap_or(&[]),
+        _ => &[],
+    }
+}
+
+/// Returns the blocks of a function and every block nested in them, along with their `elif`
+/// and `else` branches.
+fn all_blocks(blocks: &[InstBlock]) -> Vec<&InstBlock> {
+    let mut all = vec![];
+    let mut pending: Vec<&InstBlock> = blocks.iter().collect();
+
+    while let Some(block) = pending.pop() {
+        pending.extend(block.blocks.iter());
+        pending.extend(block.elses.iter());
+        pending.extend(block.else_block.as_deref());
+        all.push(block);
+    }
+
+    all
+}
+
+/// Calls `f` with each block of a function and every block nested in them, along with their
+/// `elif` and `else` branches.
+fn for_each_block(blocks: &mut [InstBlock], mut f: impl FnMut(&mut InstBlock)) {
+    let mut pending: Vec<&mut InstBlock> = blocks.iter_mut().collect();
+
+    while let Some(block) = pending.pop() {
+        f(block);
+
+        pending.extend(block.blocks.iter_mut());
+        pending.extend(block.elses.iter_mut());
+        pending.extend(block.else_block.as_deref_mut());
+    }
+}
+
+/// Removes the blocks of a function that can't be reached from its first block, along with
+/// the values defined in them, and renumbers the blocks after them.
+fn remove_unreachable_blocks(func: &mut Function, removed: &mut Removed) {
+    let n = func.blocks.len();
+    let mut reachable = vec![false; n];
+    let mut pending = vec![0];
+
+    while let Some(i) = pending.pop() {
+        if reachable[i] {
+            continue;
+        }
+
+        reachable[i] = true;
+
+        let block = &func.blocks[i];
+        let mut succs: Vec<usize> = func.branch_targets(block).iter().map(|b| b.0 as usize).collect();
+
+        // A block that doesn't end with a terminator falls through to the block after it.
+        if block.terminator().is_none() && i + 1 < n {
+            succs.push(i + 1);
+        }
+
+        pending.extend(succs.into_iter().filter(|succ| *succ < n));
+    }
+
+    if reachable.iter().all(|r| *r) {
+        return;
+    }
+
+    let mut numbers = vec![None; n];
+    let mut next = 0;
+
+    for (i, reachable) in reachable.iter().enumerate() {
+        if *reachable {
+            numbers[i] = Some(Block(next));
+            next += 1;
+        } else {
+            removed.blocks.push((func.name.to_string(), Block(i as u32)));
+        }
+    }
+
+    // The values that branch to blocks of the function, rather than entering nested blocks,
+    // are renumbered along with the blocks.
+    let mut targets = HashSet::new();
+
+    for (block, _) in func.blocks.iter().zip(&reachable).filter(|(_, reachable)| **reachable) {
+        for inner in all_blocks(std::slice::from_ref(block)) {
+            for inst in &inner.insts {
+                targets.extend(branch_arguments(inst).iter().map(|v| v.0));
+            }
+        }
+    }
+
+    let mut i = 0;
+    func.blocks.retain(|_| {
+        i += 1;
+        reachable[i - 1]
+    });
+
+    let mut live = vec![true; func.values.len()];
+
+    for (v, info) in func.values.info.iter_mut().enumerate() {
+        let path = &mut func.values.blocks[v];
+
+        match numbers.get(path.block.0 as usize).copied().flatten() {
+            Some(block) => path.block = block,
+            None => {
+                live[v] = false;
+                continue;
+            },
+        }
+
+        match info {
+            ValueInfo::BlockCall(call) => {
+                if let Some(Some(block)) = numbers.get(call.0 .0 as usize) {
+                    call.0 = *block;
+                }
+            },
+            ValueInfo::Block(b) if targets.contains(&(v as u32)) => {
+                if let Some(Some(block)) = numbers.get(b.0 as usize) {
+                    *b = *block;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    removed.values += live.iter().filter(|live| !**live).count();
+    retain_values(func, &live);
+}
+
+/// Returns the variables of a function that are never read.  A variable is only written to
+/// when it is the target of a `set`, without any properties.
+fn dead_variables(func: &Function) -> Vec<String> {
+    let mut read = HashSet::new();
+
+    let mut uses = |user: Option<&InstructionInfo>, args: &[Value]| {
+        for (i, arg) in args.iter().enumerate() {
+            if let Some(ValueInfo::Named(named)) = func.values.get(*arg) {
+                let write = i == 0 && named.properties.is_empty() && user.is_some_and(|inst| inst.opcode == Opcode::Set);
+
+                if !write {
+                    read.insert(named.name.as_str());
+                }
+            }
+        }
+    };
+
+    for info in &func.values.info {
+        match info {
+            ValueInfo::Instruction(inst) => uses(Some(inst), &inst.arguments),
+            ValueInfo::BlockCall(call) => uses(None, &call.1),
+            ValueInfo::Named(named) => {
+                for prop in &named.properties {
+                    if let NamedProperty::Index(index) = prop {
+                        uses(None, std::slice::from_ref(index));
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    for block in all_blocks(&func.blocks) {
+        for inst in &block.insts {
+            uses(Some(inst), &inst.arguments);
+        }
+
+        uses(None, &block.block_type.values());
+    }
+
+    func.variables.keys()
+        .filter(|name| !read.contains(name.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Returns true if an instruction is a `set` that assigns to one of the given variables.
+fn sets_variable(values: &ValueTable, inst: &InstructionInfo, variables: &[String]) -> bool {
+    if inst.opcode != Opcode::Set {
+        return false;
+    }
+
+    match inst.arguments.first().and_then(|target| values.get(*target)) {
+        Some(ValueInfo::Named(named)) => named.properties.is_empty() && variables.contains(&named.name),
+        _ => false,
+    }
+}
+
+/// Removes the instructions that aren't values and have no side effects, along with the
+/// `set` instructions that assign to dead variables, returning how many were removed.
+fn remove_instructions(func: &mut Function, dead: &[String], pure: &HashSet<String>) -> usize {
+    let pure_callees = pure_callees(func, pure);
+    let mut count = 0;
+    let values = &func.values;
+
+    for_each_block(&mut func.blocks, |block| {
+        let mut i = 0;
+
+        while i < block.insts.len() {
+            let inst = &block.insts[i];
+
+            if is_pure(inst, &pure_callees) || sets_variable(values, inst, dead) {
+                block.insts.remove(i);
+                count += 1;
+
+                // Values after the instruction move back by one.
+                for position in &mut block.positions {
+                    if *position > i {
+                        *position -= 1;
+                    }
+                }
+            } else {
+                i += 1;
+            }
+        }
+    });
+
+    count
+}
+
+/// Returns whether each value of a function is live.  Values are live when they are used by
+/// an instruction or a block, or by another live value, or when they have side effects.
+/// Parameters are always live, as they are part of their block.  `set` values that assign to
+/// dead variables only live on if something uses them.
+fn live_values(func: &Function, dead: &[String], pure: &HashSet<String>) -> Vec<bool> {
+    let pure_callees = pure_callees(func, pure);
+    let mut live = vec![false; func.values.len()];
+    let mut pending = vec![];
+
+    for (v, info) in func.values.info.iter().enumerate() {
+        let root = match info {
+            ValueInfo::Param(_) => true,
+            ValueInfo::Instruction(inst) => !is_pure(inst, &pure_callees) && !sets_variable(&func.values, inst, dead),
+            _ => false,
+        };
+
+        if root {
+            pending.push(Value(v as u32));
+        }
+    }
+
+    for block in all_blocks(&func.blocks) {
+        for inst in &block.insts {
+            pending.extend(inst.arguments.iter().copied());
+        }
+
+        pending.extend(block.block_type.values());
+    }
+
+    while let Some(v) = pending.pop() {
+        match live.get_mut(v.0 as usize) {
+            Some(live) if !*live => *live = true,
+            _ => continue,
+        }
+
+        match func.values.get(v) {
+            Some(ValueInfo::Instruction(inst)) => pending.extend(inst.arguments.iter().copied()),
+            Some(ValueInfo::BlockCall(call)) => pending.extend(call.1.iter().copied()),
+            Some(ValueInfo::Named(named)) => {
+                for prop in &named.properties {
+                    if let NamedProperty::Index(index) = prop {
+                        pending.push(*index);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    live
+}
+
+/// Returns true if any value of a function is a named reference to the given name.
+fn is_referenced(func: &Function, name: &str) -> bool {
+    func.values.info.iter().any(|info| matches!(info, ValueInfo::Named(named) if named.name == name))
+}
+
+/// Removes the values of a function that aren't live, renumbering the values after them.
+fn retain_values(func: &mut Function, live: &[bool]) {
+    if live.iter().all(|live| *live) {
+        return;
+    }
+
+    let mut numbers = vec![None; live.len()];
+    let mut next = 0;
+
+    for (v, live) in live.iter().enumerate() {
+        if *live {
+            numbers[v] = Some(Value(next));
+            next += 1;
+        }
+    }
+
+    let renumber = |v: &mut Value| {
+        if let Some(Some(n)) = numbers.get(v.0 as usize) {
+            *v = *n;
+        }
+    };
+
+    let table = &mut func.values;
+    let mut i = 0;
+
+    table.info.retain(|_| {
+        i += 1;
+        live[i - 1]
+    });
+
+    let mut i = 0;
+    table.types.retain(|_| {
+        i += 1;
+        live[i - 1]
+    });
+
+    let mut i = 0;
+    table.blocks.retain(|_| {
+        i += 1;
+        live[i - 1]
+    });
+
+    for info in &mut table.info {
+        match info {
+            ValueInfo::Instruction(inst) => inst.arguments.iter_mut().for_each(renumber),
+            ValueInfo::BlockCall(call) => call.1.iter_mut().for_each(renumber),
+            ValueInfo::Named(named) => {
+                for prop in &mut named.properties {
+                    if let NamedProperty::Index(index) = prop {
+                        renumber(index);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    for_each_block(&mut func.blocks, |block| {
+        let mut kept = vec![];
+        let mut positions = vec![];
+
+        for (v, position) in block.values.iter().zip(&block.positions) {
+            if let Some(Some(n)) = numbers.get(v.0 as usize) {
+                kept.push(*n);
+                positions.push(*position);
+            }
+        }
+
+        block.values = kept;
+        block.positions = positions;
+
+        for inst in &mut block.insts {
+            inst.arguments.iter_mut().for_each(renumber);
+        }
+
+        match &mut block.block_type {
+            BlockType::If(v) | BlockType::While(v) | BlockType::DoWhile(v) => renumber(v),
+            BlockType::For { init, cond, step } => {
+                init.iter_mut().chain(Some(cond)).chain(step.iter_mut()).for_each(renumber);
+            },
+            BlockType::Basic => {},
+        }
+    });
+}
@@ -0,0 +1,91 @@
+//! Finds which blocks of a function dominate each other, which decides where values can be
+//! used.
+
+use crate::entities::{Block, BlockPath};
+use crate::function::Function;
+
+/// The dominators of each block of a function.  A block dominates another if every path from
+/// the first block of the function to the other block goes through it, so the values that it
+/// defines have been evaluated by the time that the other block runs.
+#[derive(Clone, Debug)]
+pub struct Dominators {
+
+    /// For each block, whether each block of the function dominates it.
+    sets: Vec<Vec<bool>>,
+
+}
+
+impl Dominators {
+
+    /// Finds the dominators of the blocks of a function.  A block that doesn't end with a
+    /// terminator falls through to the block after it.  Blocks that can't be reached are
+    /// dominated by every block.
+    pub fn new(func: &Function) -> Self {
+        let n = func.blocks.len();
+        let mut preds = vec![vec![]; n];
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            let mut succs = func.branch_targets(block);
+
+            let terminated = block.insts.last().is_some_and(|inst| inst.opcode.is_terminator());
+            if !terminated && i + 1 < n {
+                succs.push(Block(i as u32 + 1));
+            }
+
+            for succ in succs {
+                if let Some(p) = preds.get_mut(succ.0 as usize) {
+                    p.push(i);
+                }
+            }
+        }
+
+        let mut sets = vec![vec![true; n]; n];
+
+        if let Some(entry) = sets.first_mut() {
+            *entry = vec![false; n];
+            entry[0] = true;
+        }
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for b in 1..n {
+                let mut set = vec![true; n];
+
+                for p in &preds[b] {
+                    for (d, dominates) in set.iter_mut().enumerate() {
+                        *dominates &= sets[*p][d];
+                    }
+                }
+
+                set[b] = true;
+
+                if set != sets[b] {
+                    sets[b] = set;
+                    changed = true;
+                }
+            }
+        }
+
+        Self { sets }
+    }
+
+    /// Returns true if block `a` dominates block `b`.  Every block dominates itself.
+    pub fn dominates(&self, a: Block, b: Block) -> bool {
+        self.sets.get(b.0 as usize)
+            .and_then(|set| set.get(a.0 as usize))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Returns true if a value defined in the block at `def` can be used in the block at `at`.
+    /// That is the case when `at` is nested in the body of `def`, or when `def` is a block of
+    /// the function that dominates the block that `at` is in.  Values defined in nested
+    /// blocks can't be used outside of them, as nested blocks may run any number of times.
+    pub fn is_available(&self, def: &BlockPath, at: &BlockPath) -> bool {
+        def.encloses(at) || (def.steps.is_empty() && self.dominates(def.block, at.block))
+    }
+
+}
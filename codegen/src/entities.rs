@@ -1,18 +1,110 @@
 //! Entities that the code generator use.
 
 use crate::instruction::InstructionInfo;
-
-/// An opaque reference to a Cardinal SSA value.  These can be used as instruction parameters,
-/// if a value is not used, it will not be included in the generated code.
-#[derive(Clone, Copy, PartialEq)]
+use std::fmt;
+
+/// An opaque reference to a Cardinal SSA value.  These can be used as instruction parameters.
+/// If a value is not used and has no side effects, `DeadCodeElimination` removes it.
+///
+/// Values are numbered across the whole function, so a value defined in one block can be
+/// used in any block that its definition dominates.
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Value(pub u32);
 
 /// An opaque reference to a Cardinal IR block.
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Block(pub u32);
 
+/// A step from a block into one of the blocks nested inside of it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlockStep {
+
+    /// The body of a nested block, by its index in the enclosing block.
+    Nested(Block),
+
+    /// An `elif` branch of the nested block that the previous step entered, by its index.
+    Elif(usize),
+
+    /// The `else` branch of the nested block that the previous step entered.
+    Else,
+
+}
+
+/// The location of a block in a function: a block of the function, followed by the steps
+/// into the nested blocks that lead to it.  It is written as a path such as
+/// `block0/block1/elif0`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BlockPath {
+
+    /// The block of the function that the block is, or is nested in.
+    pub block: Block,
+
+    /// The steps from the function block to the block.
+    pub steps: Vec<BlockStep>,
+
+}
+
+impl BlockPath {
+
+    /// Creates a path to a block of the function.
+    pub fn new(block: Block) -> Self {
+        Self {
+            block,
+            steps: vec![],
+        }
+    }
+
+    /// Returns the path to a block nested inside of this one.
+    pub fn join(&self, step: BlockStep) -> Self {
+        let mut path = self.clone();
+        path.steps.push(step);
+
+        path
+    }
+
+    /// Returns true if `other` is this block, or is nested inside of its body.  The `elif`
+    /// and `else` branches of a block are not part of its body.
+    pub fn encloses(&self, other: &BlockPath) -> bool {
+        self.block == other.block
+            && other.steps.starts_with(&self.steps)
+            && !matches!(other.steps.get(self.steps.len()), Some(BlockStep::Elif(_) | BlockStep::Else))
+    }
+
+}
+
+impl fmt::Display for BlockPath {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "block{}", self.block.0)?;
+
+        for step in &self.steps {
+            match step {
+                BlockStep::Nested(b) => write!(f, "/block{}", b.0)?,
+                BlockStep::Elif(i) => write!(f, "/elif{}", i)?,
+                BlockStep::Else => write!(f, "/else")?,
+            }
+        }
+
+        Ok(())
+    }
+
+}
+
+/// The target of a branch: a block of the function, along with the values passed to its
+/// parameters.  A `Block` converts into a call without any arguments.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BlockCall(pub Block, pub Vec<Value>);
+
+impl From<Block> for BlockCall {
+
+    fn from(block: Block) -> Self {
+        BlockCall(block, vec![])
+    }
+
+}
+
 /// An opaque reference to a Cardinal variable.
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct Variable(pub String);
 
 impl Variable {
@@ -24,7 +116,7 @@ impl Variable {
 }
 
 /// An opaque reference to a Cardinal global variable.
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct GlobalVariable(pub String);
 
 impl GlobalVariable {
@@ -35,33 +127,217 @@ impl GlobalVariable {
 
 }
 
-/// Different types of types that can be declared.
-#[derive(Clone, PartialEq)]
+/// The type of a value, variable, argument or field.  Types are recursive, so that pointers,
+/// arrays and functions can be built from any other type.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Type {
 
-    /// A plain type, such as `int` or `double`.
-    Plain,
+    /// The absence of a value, used as a return type.
+    Void,
+
+    /// A boolean.
+    Bool,
+
+    /// A character, used for text.
+    Char,
+
+    /// A signed 8 bit integer.
+    I8,
+
+    /// A signed 16 bit integer.
+    I16,
 
-    /// An array type, such as `char[]` or `int[]`. `-1` should be used as the size argument if
-    /// a size should be declared implicitly.
-    Array(isize),
+    /// A signed 32 bit integer.
+    I32,
 
-    /// A pointer type, such as `char*` or `int*`.
-    Pointer,
+    /// A signed 64 bit integer.
+    I64,
+
+    /// A signed integer with the size of a pointer on the target.
+    ISize,
+
+    /// An unsigned 8 bit integer.
+    U8,
+
+    /// An unsigned 16 bit integer.
+    U16,
+
+    /// An unsigned 32 bit integer.
+    U32,
+
+    /// An unsigned 64 bit integer.
+    U64,
+
+    /// An unsigned integer with the size of a pointer on the target.
+    USize,
+
+    /// A 32 bit floating point number.
+    Float,
+
+    /// A 64 bit floating point number.
+    Double,
+
+    /// A pointer to a value of the inner type.
+    Pointer(Box<Type>),
+
+    /// An array of the inner type.  Arrays without a length have their size declared
+    /// implicitly, for example by an initializer.
+    Array(Box<Type>, Option<usize>),
+
+    /// A function type, which is usually used behind a pointer.
+    Function(FunctionType),
+
+    /// A struct declared in the module, referred to by name.
+    Struct(String),
 
 }
 
-/// An ABI type.
-#[derive(Clone, PartialEq)]
-pub struct AbiType(pub String, pub Type);
+impl Type {
+
+    /// Creates a pointer to the given type.
+    pub fn pointer(to: Type) -> Self {
+        Type::Pointer(Box::new(to))
+    }
+
+    /// Creates an array of the given type, with an optional length.
+    pub fn array(of: Type, len: Option<usize>) -> Self {
+        Type::Array(Box::new(of), len)
+    }
+
+    /// Returns true if the type is a signed or unsigned integer.
+    pub fn is_integer(&self) -> bool {
+        self.is_signed() || self.is_unsigned()
+    }
+
+    /// Returns true if the type is a signed integer.
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::ISize)
+    }
+
+    /// Returns true if the type is an unsigned integer.
+    pub fn is_unsigned(&self) -> bool {
+        matches!(self, Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::USize)
+    }
+
+    /// Returns true if the type is a floating point number.
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::Float | Type::Double)
+    }
+
+    /// Reads an integer constant as a value of this type, sign extending it if the type is
+    /// signed.  Returns None if the type isn't an integer or a character, or if the value
+    /// doesn't fit in it.  Characters are signed, as in the interpreter.
+    pub fn integer_value(&self, n: u64) -> Option<i128> {
+        let bits = match self {
+            Type::Char | Type::I8 | Type::U8 => 8,
+            Type::I16 | Type::U16 => 16,
+            Type::I32 | Type::U32 => 32,
+            Type::I64 | Type::U64 | Type::ISize | Type::USize => 64,
+            _ => return None,
+        };
+
+        if self.is_unsigned() {
+            Some(n as i128).filter(|n| *n < 1 << bits)
+        } else {
+            let n = n as i64 as i128;
+            Some(n).filter(|n| -(1 << (bits - 1)) <= *n && *n < 1 << (bits - 1))
+        }
+    }
+
+}
+
+/// The type of a function, made up of its parameter and return types.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FunctionType {
+
+    /// The types of the parameters of the function.
+    pub params: Vec<Type>,
+
+    /// The return type of the function.
+    pub returns: Box<Type>,
+
+    /// Whether the function takes a variable number of arguments after its parameters, like
+    /// C's `printf`.
+    pub variadic: bool,
+
+}
+
+/// Where a global symbol is visible from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Linkage {
+
+    /// Only visible inside of the module, such as C's `static`.
+    Internal,
+
+    /// Defined outside of the module, such as C's `extern`.
+    Import,
+
+    /// Defined in the module and visible to other modules.
+    Export,
+
+}
+
+/// What a function promises about its calls, which lets passes remove or merge them, such as
+/// GCC's `pure` and `const` attributes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FunctionAttribute {
+
+    /// The function has no side effects, and its result only depends on its arguments and
+    /// on the memory that it reads.  Calls with the same arguments return the same result
+    /// while nothing is written in between.
+    Pure,
+
+    /// The function has no side effects and doesn't read memory, so its result only depends
+    /// on its arguments.
+    Const,
+
+}
+
+/// How the characters of a string constant are encoded, which decides the type of its
+/// characters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StringEncoding {
+
+    /// A string of `char`s, such as C's `"text"`.  Characters outside of ASCII are stored as
+    /// UTF-8.
+    Narrow,
+
+    /// A string of UTF-8 `char`s, such as C's `u8"text"`.
+    Utf8,
+
+    /// A string of UTF-16 `u16`s, such as C's `u"text"`.
+    Utf16,
+
+    /// A string of UTF-32 `u32`s, such as C's `U"text"`.
+    Utf32,
+
+    /// A string of wide characters, such as C's `L"text"`.  Wide characters have the type
+    /// `i32`, like `wchar_t` on most platforms other than Windows.
+    Wide,
+
+}
+
+impl StringEncoding {
+
+    /// Returns the type of the characters of a string with this encoding.
+    pub fn char_type(self) -> Type {
+        match self {
+            StringEncoding::Narrow | StringEncoding::Utf8 => Type::Char,
+            StringEncoding::Utf16 => Type::U16,
+            StringEncoding::Utf32 => Type::U32,
+            StringEncoding::Wide => Type::I32,
+        }
+    }
+
+}
 
 /// An ABI value used for function parameters.
-#[derive(Clone, PartialEq)]
-pub struct AbiParam(pub String, pub AbiType);
+#[derive(Clone, PartialEq, Debug)]
+pub struct AbiParam(pub String, pub Type);
 
 /// Properties of a `Named` struct that may be basic properties, static properties, pointer
 /// properties or index properties.
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum NamedProperty {
 
     /// A basic property, for example, `Named.Basic`.
@@ -81,7 +357,7 @@ pub enum NamedProperty {
 }
 
 /// Used as a named reference to an object.
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct Named {
 
     /// The name of the first object in the reference.
@@ -119,7 +395,7 @@ impl Named {
 
 
 /// Information about a value.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum ValueInfo {
 
     /// An integer constant.
@@ -134,8 +410,13 @@ pub enum ValueInfo {
     /// A boolean constant.
     BooleanConstant(bool),
 
-    /// A string constant.
-    StringConstant(String),
+    /// A string constant, which is a pointer to the characters of the string in the given
+    /// encoding.
+    StringConstant(String, StringEncoding),
+
+    /// A string of raw bytes, which may hold any byte including ones that aren't valid
+    /// UTF-8.  Byte strings have the type `char*`.
+    BytesConstant(Vec<u8>),
 
     /// A character constant.
     CharConstant(String),
@@ -146,7 +427,27 @@ pub enum ValueInfo {
     /// A value reference to a block.
     Block(Block),
 
+    /// A branch target that passes values to the parameters of a block.
+    BlockCall(BlockCall),
+
+    /// A parameter of the block, by index, which takes the value passed by the branch that
+    /// jumped to the block.
+    Param(u32),
+
     /// A pointer to an instruction.
     Instruction(InstructionInfo),
 
+}
+
+impl ValueInfo {
+
+    /// Returns the block and arguments of a branch target, if the value is one.
+    pub fn branch_target(&self) -> Option<(Block, &[Value])> {
+        match self {
+            ValueInfo::Block(b) => Some((*b, &[])),
+            ValueInfo::BlockCall(call) => Some((call.0, &call.1)),
+            _ => None,
+        }
+    }
+
 }
\ No newline at end of file
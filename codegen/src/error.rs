@@ -0,0 +1,140 @@
+//! The errors that the code generator and its backends can produce.
+
+use crate::entities::{Block, Value};
+use crate::instbuilder::BuilderError;
+use crate::instruction::Opcode;
+use crate::verifier::VerifierError;
+use std::error::Error;
+use std::fmt;
+
+/// An error produced while building or compiling Cardinal IR.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CodegenError {
+
+    /// A block that does not exist.  The function is given when the block was looked up in
+    /// a function, rather than nested in another block.
+    MissingBlock {
+        function: Option<String>,
+        block: Block,
+    },
+
+    /// A value that does not exist in the block that uses it.
+    InvalidValue {
+        function: String,
+        block: String,
+        value: Value,
+    },
+
+    /// An instruction with fewer arguments than its opcode needs.
+    WrongArgumentCount {
+        function: String,
+        block: String,
+        opcode: Opcode,
+        found: usize,
+    },
+
+    /// A named value with a property that the backend can't represent, such as a static
+    /// property in C.
+    UnsupportedProperty {
+        function: String,
+        block: String,
+        value: Value,
+        property: String,
+    },
+
+    /// A `switch` case whose value is not an integer constant, which C can't use as a case
+    /// label.
+    NonConstantCase {
+        function: String,
+        block: String,
+        value: Value,
+    },
+
+    /// A `switch` with two cases for the same value, which C rejects.
+    DuplicateCase {
+        function: String,
+        block: String,
+        value: Value,
+    },
+
+    /// A `switch` case whose value doesn't fit in the type of the scrutinee.
+    CaseOutOfRange {
+        function: String,
+        block: String,
+        value: Value,
+    },
+
+    /// An instruction that the backend compiles into statements of its own, such as a
+    /// `switch`, where a single expression is needed.
+    NotAnExpression {
+        function: String,
+        block: String,
+        opcode: Opcode,
+    },
+
+    /// An instruction built with values of the wrong types.
+    Builder(BuilderError),
+
+    /// The errors found by the verifier.
+    Verifier(Vec<VerifierError>),
+
+}
+
+impl fmt::Display for CodegenError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodegenError::MissingBlock { function: Some(function), block } => {
+                write!(f, "block{} does not exist in function `{}`", block.0, function)
+            },
+            CodegenError::MissingBlock { function: None, block } => {
+                write!(f, "nested block{} does not exist", block.0)
+            },
+            CodegenError::InvalidValue { function, block, value } => {
+                write!(f, "in function `{}`, {}: value v{} is not defined", function, block, value.0)
+            },
+            CodegenError::WrongArgumentCount { function, block, opcode, found } => {
+                write!(f, "in function `{}`, {}: `{}` can't take {} arguments", function, block, opcode.name(), found)
+            },
+            CodegenError::UnsupportedProperty { function, block, value, property } => {
+                write!(f, "in function `{}`, {}: v{} uses the unsupported property `{}`", function, block, value.0, property)
+            },
+            CodegenError::NonConstantCase { function, block, value } => {
+                write!(f, "in function `{}`, {}: switch case v{} is not an integer constant", function, block, value.0)
+            },
+            CodegenError::DuplicateCase { function, block, value } => {
+                write!(f, "in function `{}`, {}: switch case v{} is given twice", function, block, value.0)
+            },
+            CodegenError::CaseOutOfRange { function, block, value } => {
+                write!(f, "in function `{}`, {}: switch case v{} doesn't fit in the scrutinee's type", function, block, value.0)
+            },
+            CodegenError::NotAnExpression { function, block, opcode } => {
+                write!(f, "in function `{}`, {}: `{}` can't be compiled as an expression", function, block, opcode.name())
+            },
+            CodegenError::Builder(err) => write!(f, "{}", err),
+            CodegenError::Verifier(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            },
+        }
+    }
+
+}
+
+impl Error for CodegenError {}
+
+impl From<BuilderError> for CodegenError {
+
+    fn from(err: BuilderError) -> Self {
+        CodegenError::Builder(err)
+    }
+
+}
+
+impl From<Vec<VerifierError>> for CodegenError {
+
+    fn from(errors: Vec<VerifierError>) -> Self {
+        CodegenError::Verifier(errors)
+    }
+
+}
@@ -0,0 +1,198 @@
+//! Folds instructions on constants into constants, and simplifies algebraic identities.
+//!
+//! Constants are folded with the same arithmetic as the interpreter, so integers wrap around
+//! to their type.  Divisions by zero and out of range shifts are left alone, so that they
+//! still fail when the program runs, as are floating point results that are infinite or not
+//! a number, which have no constant to stand for them.  Identities such as `x + 0` and `x * 1`
+//! are only used for integers, booleans and pointers, as they don't hold for every floating
+//! point number.
+
+use crate::entities::{Type, Value, ValueInfo};
+use crate::function::Function;
+use crate::instruction::{InstructionInfo, Opcode};
+use crate::interpreter::{operate, DataValue};
+use crate::pass::{Analyses, Analysis, Pass};
+
+/// A pass that replaces instructions with the constants that they evaluate to, and forwards
+/// the operands of instructions that don't change them, such as `x + 0` or `!!x`.
+///
+/// Folded values are rewritten in place, while the uses of a value that is simplified to one
+/// of its operands are replaced with the operand.  The instruction is left behind for dead
+/// code elimination to remove.
+#[derive(Clone, Debug, Default)]
+pub struct ConstantFolding;
+
+/// What a value can be simplified to.
+enum Simplified {
+
+    /// A constant, which replaces the value.
+    Constant(ValueInfo),
+
+    /// Another value, which replaces the uses of the value.
+    Value(Value),
+
+}
+
+impl Pass for ConstantFolding {
+
+    fn name(&self) -> &str {
+        "constant-folding"
+    }
+
+    fn invalidates(&self) -> &[Analysis] {
+        &[]
+    }
+
+    fn run_function(&mut self, func: &mut Function, _analyses: &mut Analyses) -> bool {
+        let inline = func.loop_values();
+        let mut changed = false;
+        let mut progress = true;
+
+        // Folding a value can make the values that use it constant, so the values are
+        // simplified until nothing changes.
+        while progress {
+            progress = false;
+
+            for i in 0..func.values.len() {
+                let v = Value(i as u32);
+
+                match simplify(func, v, &inline) {
+                    Some(Simplified::Constant(info)) => {
+                        func.values.info[i] = info;
+                        progress = true;
+                    },
+                    Some(Simplified::Value(to)) => progress |= func.replace_uses(v, to),
+                    None => {},
+                }
+            }
+
+            changed |= progress;
+        }
+
+        changed
+    }
+
+}
+
+/// Returns what an instruction value can be simplified to, if anything.
+fn simplify(func: &Function, v: Value, inline: &[bool]) -> Option<Simplified> {
+    let inst = match func.values.get(v) {
+        Some(ValueInfo::Instruction(inst)) => inst,
+        _ => return None,
+    };
+
+    let ty = func.values.ty(v)?;
+
+    match inst.opcode {
+        Opcode::Set | Opcode::Call => return None,
+        opcode if opcode.is_terminator() => return None,
+        _ => {},
+    }
+
+    let args: Option<Vec<DataValue>> = inst.arguments.iter().map(|arg| constant(func, *arg)).collect();
+
+    match args {
+        Some(args) => operate(inst.opcode, &args, ty).ok().and_then(constant_info).map(Simplified::Constant),
+        None => identity(func, v, inst, ty, inline),
+    }
+}
+
+/// Returns the value of a numeric or boolean constant.
+fn constant(func: &Function, v: Value) -> Option<DataValue> {
+    let ty = func.values.ty(v)?;
+
+    match func.values.get(v)? {
+        ValueInfo::IntegerConstant(n) if ty.is_integer() => DataValue::from_int(ty, *n as i128),
+        ValueInfo::FloatConstant(n) => Some(DataValue::Float(*n as f32)),
+        ValueInfo::DoubleConstant(n) => Some(DataValue::Double(*n)),
+        ValueInfo::BooleanConstant(b) => Some(DataValue::Bool(*b)),
+        _ => None,
+    }
+}
+
+/// Returns the constant that stands for a folded value, if there is one.
+fn constant_info(value: DataValue) -> Option<ValueInfo> {
+    match value {
+        DataValue::Bool(b) => Some(ValueInfo::BooleanConstant(b)),
+        DataValue::Float(n) if n.is_finite() => Some(ValueInfo::FloatConstant(n as f64)),
+        DataValue::Double(n) if n.is_finite() => Some(ValueInfo::DoubleConstant(n)),
+        DataValue::Float(_) | DataValue::Double(_) => None,
+        value => value.as_int().map(|n| ValueInfo::IntegerConstant(n as u64)),
+    }
+}
+
+/// Applies the algebraic identities of an instruction whose operands aren't all constants.
+fn identity(func: &Function, v: Value, inst: &InstructionInfo, ty: &Type, inline: &[bool]) -> Option<Simplified> {
+    let integral = ty.is_integer();
+
+    let int = |arg: Value| match func.values.get(arg) {
+        Some(ValueInfo::IntegerConstant(n)) => Some(*n),
+        _ => None,
+    };
+
+    let boolean = |arg: Value| match func.values.get(arg) {
+        Some(ValueInfo::BooleanConstant(b)) => Some(*b),
+        _ => None,
+    };
+
+    // An operand replaces the value when it is evaluated at the same time as the value would
+    // have been.  Named references are read when they are used, so they are never forwarded,
+    // and loop values are evaluated each time that they are used, so they can only replace
+    // other loop values.
+    let forward = |arg: Value| {
+        if func.values.ty(arg) != Some(ty) {
+            return None;
+        }
+
+        match func.values.get(arg)? {
+            info @ ValueInfo::IntegerConstant(_) | info @ ValueInfo::BooleanConstant(_) => {
+                Some(Simplified::Constant(info.clone()))
+            },
+            ValueInfo::Param(_) | ValueInfo::Instruction(_) if !inline[arg.0 as usize] || inline[v.0 as usize] => {
+                Some(Simplified::Value(arg))
+            },
+            _ => None,
+        }
+    };
+
+    let zero = || Some(Simplified::Constant(ValueInfo::IntegerConstant(0)));
+    let truth = |b| Some(Simplified::Constant(ValueInfo::BooleanConstant(b)));
+
+    match (inst.opcode, &inst.arguments[..]) {
+        (Opcode::Add, [a, b]) | (Opcode::Sub, [a, b]) if matches!(ty, Type::Pointer(_)) && int(*b) == Some(0) => forward(*a),
+        (Opcode::Add, [a, b]) if matches!(ty, Type::Pointer(_)) && int(*a) == Some(0) => forward(*b),
+        _ if !integral && *ty != Type::Bool => None,
+        (Opcode::Sub, [a, b]) | (Opcode::BitXor, [a, b]) if a == b => zero(),
+        (Opcode::BitAnd, [a, b]) | (Opcode::BitOr, [a, b]) | (Opcode::And, [a, b]) | (Opcode::Or, [a, b]) if a == b => forward(*a),
+        (Opcode::Mul, [a, b]) | (Opcode::BitAnd, [a, b]) if int(*a) == Some(0) || int(*b) == Some(0) => zero(),
+        (Opcode::Mod, [_, b]) if int(*b) == Some(1) => zero(),
+        (Opcode::Add, [a, b]) | (Opcode::Sub, [a, b]) | (Opcode::BitOr, [a, b]) | (Opcode::BitXor, [a, b])
+        | (Opcode::BitLeft, [a, b]) | (Opcode::BitRight, [a, b]) if int(*b) == Some(0) => forward(*a),
+        (Opcode::Add, [a, b]) | (Opcode::BitOr, [a, b]) | (Opcode::BitXor, [a, b]) if int(*a) == Some(0) => forward(*b),
+        (Opcode::Mul, [a, b]) | (Opcode::Div, [a, b]) if int(*b) == Some(1) => forward(*a),
+        (Opcode::Mul, [a, b]) if int(*a) == Some(1) => forward(*b),
+        (Opcode::And, [a, b]) | (Opcode::Or, [a, b]) => {
+            let or = inst.opcode == Opcode::Or;
+
+            match (boolean(*a), boolean(*b)) {
+                (Some(x), _) | (_, Some(x)) if x == or => truth(or),
+                (Some(_), _) => forward(*b),
+                (_, Some(_)) => forward(*a),
+                _ => None,
+            }
+        },
+        (Opcode::Not, [a]) | (Opcode::BitNot, [a]) => match func.values.get(*a)? {
+            ValueInfo::Instruction(inner) if inner.opcode == inst.opcode => forward(*inner.arguments.first()?),
+            _ => None,
+        },
+        (Opcode::TestEq, [a, b]) | (Opcode::TestGtEq, [a, b]) | (Opcode::TestLtEq, [a, b]) if a == b && is_integral(func, *a) => truth(true),
+        (Opcode::TestNeq, [a, b]) | (Opcode::TestGt, [a, b]) | (Opcode::TestLt, [a, b]) if a == b && is_integral(func, *a) => truth(false),
+        _ => None,
+    }
+}
+
+/// Returns true if a value is an integer, a character or a boolean, which always compare
+/// equal to themselves.
+fn is_integral(func: &Function, v: Value) -> bool {
+    func.values.ty(v).is_some_and(|ty| ty.is_integer() || matches!(ty, Type::Char | Type::Bool))
+}
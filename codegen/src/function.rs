@@ -1,27 +1,100 @@
 //! Exposes types for function declarations and definitions.
 
-use crate::entities::{AbiParam, AbiType, Block, Type, Variable};
-use crate::instruction::{InstBlock, BlockType};
-use std::collections::HashMap;
+use crate::entities::{AbiParam, Block, BlockPath, FunctionAttribute, FunctionType, Linkage, NamedProperty, Type, Value, ValueInfo, Variable};
+use crate::error::CodegenError;
+use crate::instruction::{BlockBuilder, InstBlock, BlockType, Opcode};
+use crate::map::OrderedMap;
 
 // A function that allows Cardinal to create instructions, variables and SSA values.
+#[derive(Debug)]
 pub struct Function {
 
-    // A list of variables declared in the function.
-    pub variables: HashMap<String, AbiType>,
+    // A list of variables declared in the function, in the order that they were declared.
+    pub variables: OrderedMap<Type>,
 
     /// A list of that blocks that may store instructions.
     pub blocks: Vec<InstBlock>,
 
+    /// The values of the function, which are shared by all of its blocks.
+    pub values: ValueTable,
+
     /// The name of the Function, used at compile time to generate correct code.
     pub name: String,
 
     /// The signature that the function uses.
     pub signature: FunctionSignature,
 
+    /// Where the function is visible from.  A function without blocks is only declared, so it
+    /// is always treated as imported.
+    pub linkage: Linkage,
+
+    /// What the function promises about its calls.
+    pub attributes: Vec<FunctionAttribute>,
+
+}
+
+/// The values of a function.  Each value is numbered by its index in the table, and records
+/// the block that defined it.
+#[derive(Clone, Debug, Default)]
+pub struct ValueTable {
+
+    /// Information about each value.
+    pub info: Vec<ValueInfo>,
+
+    /// The type of each value.
+    pub types: Vec<Type>,
+
+    /// The block that defined each value.
+    pub blocks: Vec<BlockPath>,
+
+}
+
+impl ValueTable {
+
+    /// Creates an empty value table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a value in the given block, returning the new value.
+    pub fn push(&mut self, value: ValueInfo, ty: Type, block: BlockPath) -> Value {
+        let val = Value(self.info.len() as u32);
+        self.info.push(value);
+        self.types.push(ty);
+        self.blocks.push(block);
+
+        val
+    }
+
+    /// Returns information about a value, if it exists.
+    pub fn get(&self, value: Value) -> Option<&ValueInfo> {
+        self.info.get(value.0 as usize)
+    }
+
+    /// Returns the type of a value, if it exists.
+    pub fn ty(&self, value: Value) -> Option<&Type> {
+        self.types.get(value.0 as usize)
+    }
+
+    /// Returns the block that defined a value, if it exists.
+    pub fn block(&self, value: Value) -> Option<&BlockPath> {
+        self.blocks.get(value.0 as usize)
+    }
+
+    /// Returns the number of values in the table.
+    pub fn len(&self) -> usize {
+        self.info.len()
+    }
+
+    /// Returns true if the table has no values.
+    pub fn is_empty(&self) -> bool {
+        self.info.is_empty()
+    }
+
 }
 
 /// A function signature that allows the code generator to verify function calls and references.
+#[derive(Debug)]
 pub struct FunctionSignature {
 
     /// A list of arguments in the function signature, which are checked at compile time to
@@ -29,7 +102,7 @@ pub struct FunctionSignature {
     pub arguments: Vec<AbiParam>,
 
     /// A return value of the function.  Defaults to `void`.
-    pub returns: AbiType,
+    pub returns: Type,
 
 }
 
@@ -38,10 +111,27 @@ impl FunctionSignature {
     pub fn new() -> Self {
         Self {
             arguments: vec![],
-            returns: AbiType("void".into(), Type::Plain)
+            returns: Type::Void
         }
     }
 
+    /// Returns the type of a function with this signature.
+    pub fn function_type(&self) -> Type {
+        Type::Function(FunctionType {
+            params: self.arguments.iter().map(|arg| arg.1.clone()).collect(),
+            returns: Box::new(self.returns.clone()),
+            variadic: false,
+        })
+    }
+
+}
+
+impl Default for FunctionSignature {
+
+    fn default() -> Self {
+        Self::new()
+    }
+
 }
 
 impl Function {
@@ -51,35 +141,38 @@ impl Function {
         Self {
             name,
             signature: sig,
-            variables: HashMap::new(),
+            linkage: Linkage::Export,
+            attributes: vec![],
+            variables: OrderedMap::new(),
             blocks: vec![],
+            values: ValueTable::new(),
         }
     }
 
     /// Declares a variable at the start of the function.
-    pub fn declare_var(&mut self, name: String, var_type: AbiType) -> Variable {
+    pub fn declare_var(&mut self, name: String, var_type: Type) -> Variable {
         let val = Variable(name.to_string());
         self.variables.insert(name, var_type);
 
         val
     }
 
-    /// Uses a block.
-    pub fn use_block(&mut self, block: Block) -> &mut InstBlock {
-        self.blocks.get_mut(block.0 as usize).unwrap()
+    /// Uses a block, returning a builder for its instructions, or returns an error if it
+    /// doesn't exist in the function.
+    pub fn use_block(&mut self, block: Block) -> Result<BlockBuilder<'_>, CodegenError> {
+        let function = &self.name;
+
+        let inst_block = self.blocks.get_mut(block.0 as usize).ok_or_else(|| CodegenError::MissingBlock {
+            function: Some(function.to_string()),
+            block,
+        })?;
+
+        Ok(BlockBuilder::new(&mut self.values, inst_block, BlockPath::new(block)))
     }
 
     /// Creates a new empty block.
     pub fn create_block(&mut self) -> Block {
-        let block = InstBlock {
-            block_type: BlockType::Basic,
-            blocks: vec![],
-            else_block: None,
-            elses: vec![],
-            imports: vec![],
-            insts: vec![],
-            values: vec![],
-        };
+        let block = InstBlock::new(BlockType::Basic);
 
         let val = Block(self.blocks.len() as u32);
         self.blocks.push(block);
@@ -87,4 +180,161 @@ impl Function {
         val
     }
 
-}
\ No newline at end of file
+    /// Returns true if calls to the function have no side effects, because it is `Pure` or
+    /// `Const`.
+    pub fn is_pure(&self) -> bool {
+        self.attributes.iter().any(|attr| matches!(attr, FunctionAttribute::Pure | FunctionAttribute::Const))
+    }
+
+    /// Returns true if the function is `Const`, so calls to it only depend on their arguments.
+    pub fn is_const(&self) -> bool {
+        self.attributes.contains(&FunctionAttribute::Const)
+    }
+
+    /// Returns the name that a value calls a function by, if it is a named reference without
+    /// properties that isn't a variable or an argument of this function.  The name may still
+    /// refer to a global of the module.
+    pub fn callee(&self, v: Value) -> Option<&str> {
+        match self.values.get(v)? {
+            ValueInfo::Named(named) if named.properties.is_empty() => {
+                let local = self.variables.contains_key(&named.name)
+                    || self.signature.arguments.iter().any(|arg| arg.0 == named.name);
+
+                if local {
+                    None
+                } else {
+                    Some(&named.name)
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the blocks of the function that a block, or any of the blocks nested in it,
+    /// branches to with a `Jmp`, `Brif` or `Switch`.  Each block is only listed once.
+    pub fn branch_targets(&self, block: &InstBlock) -> Vec<Block> {
+        let mut targets = vec![];
+
+        for inst in &block.insts {
+            let args = match inst.opcode {
+                Opcode::Jmp => &inst.arguments[..],
+                Opcode::Brif | Opcode::Switch => inst.arguments.get(1..).unwrap_or(&[]),
+                _ => continue,
+            };
+
+            for arg in args {
+                if let Some((b, _)) = self.values.get(*arg).and_then(ValueInfo::branch_target) {
+                    if !targets.contains(&b) {
+                        targets.push(b);
+                    }
+                }
+            }
+        }
+
+        let nested = block.blocks.iter()
+            .flat_map(|b| b.elses.iter().chain(Some(b)).chain(b.else_block.as_deref()));
+
+        for b in nested {
+            for target in self.branch_targets(b) {
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+        }
+
+        targets
+    }
+
+    /// Returns whether each value of the function is one that the conditions of loops, or the
+    /// initializers and steps of `for` loops, are built from.  These values are evaluated each
+    /// time that they are used, rather than once at their positions.
+    pub fn loop_values(&self) -> Vec<bool> {
+        let mut inline = vec![false; self.values.len()];
+        let mut pending = vec![];
+        let mut blocks: Vec<&InstBlock> = self.blocks.iter().collect();
+
+        while let Some(block) = blocks.pop() {
+            for nested in &block.blocks {
+                if nested.block_type.is_loop() {
+                    pending.extend(nested.block_type.values());
+                }
+            }
+
+            blocks.extend(block.blocks.iter());
+            blocks.extend(block.elses.iter());
+            blocks.extend(block.else_block.as_deref());
+        }
+
+        while let Some(v) = pending.pop() {
+            if let (Some(false), Some(info)) = (inline.get(v.0 as usize), self.values.get(v)) {
+                inline[v.0 as usize] = true;
+
+                match info {
+                    ValueInfo::Instruction(inst) => pending.extend(inst.arguments.iter().copied()),
+                    ValueInfo::Named(named) => {
+                        for prop in &named.properties {
+                            if let NamedProperty::Index(index) = prop {
+                                pending.push(*index);
+                            }
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        inline
+    }
+
+    /// Replaces every use of a value with another value, in the arguments of instructions,
+    /// named references and branches, and in the conditions of blocks.  Returns true if the
+    /// value was used anywhere.
+    pub fn replace_uses(&mut self, from: Value, to: Value) -> bool {
+        let mut replaced = false;
+
+        let mut replace = |v: &mut Value| {
+            if *v == from {
+                *v = to;
+                replaced = true;
+            }
+        };
+
+        for info in &mut self.values.info {
+            match info {
+                ValueInfo::Instruction(inst) => inst.arguments.iter_mut().for_each(&mut replace),
+                ValueInfo::BlockCall(call) => call.1.iter_mut().for_each(&mut replace),
+                ValueInfo::Named(named) => {
+                    for prop in &mut named.properties {
+                        if let NamedProperty::Index(index) = prop {
+                            replace(index);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let mut blocks: Vec<&mut InstBlock> = self.blocks.iter_mut().collect();
+
+        while let Some(block) = blocks.pop() {
+            for inst in &mut block.insts {
+                inst.arguments.iter_mut().for_each(&mut replace);
+            }
+
+            match &mut block.block_type {
+                BlockType::If(v) | BlockType::While(v) | BlockType::DoWhile(v) => replace(v),
+                BlockType::For { init, cond, step } => {
+                    init.iter_mut().chain(Some(cond)).chain(step.iter_mut()).for_each(&mut replace);
+                },
+                BlockType::Basic => {},
+            }
+
+            blocks.extend(block.blocks.iter_mut());
+            blocks.extend(block.elses.iter_mut());
+            blocks.extend(block.else_block.as_deref_mut());
+        }
+
+        replaced
+    }
+
+}
@@ -0,0 +1,402 @@
+//! Finds values that compute the same thing, and replaces them with the first one.
+//!
+//! Every value is given a number, so that values with the same number are equal.  Constants
+//! are numbered by their value and type, and named references by their name and properties.
+//! An instruction is numbered by its opcode, its type and the numbers of its operands, which
+//! are sorted for commutative opcodes, so `add v0, v1` and `add v1, v0` are numbered the same.
+//! When a value is numbered the same as a value that is available where it is defined, its
+//! uses are replaced with that value, and it is left behind for dead code elimination.
+//!
+//! Named references are read when they are used, so an instruction that depends on one only
+//! equals another while nothing may have written to memory in between.  Such instructions are
+//! only merged in the same block, with no `set`, call or nested block between them.  Calls are
+//! only merged when they call a `Const` function, or a `Pure` one, which also depends on
+//! memory.  Values that loop conditions are built from are evaluated every time that they are
+//! used, so they are left alone.
+
+use crate::dominance::Dominators;
+use crate::entities::{Block, BlockPath, BlockStep, NamedProperty, Type, Value, ValueInfo};
+use crate::function::Function;
+use crate::instruction::{InstBlock, InstructionInfo, Opcode};
+use crate::module::Module;
+use crate::pass::{Analyses, Analysis, Pass};
+use std::collections::HashMap;
+
+/// A pass that replaces the uses of values that compute the same thing as an earlier value
+/// with that value.  Lowering field accesses computes the same indexes many times, which this
+/// leaves computed once.
+#[derive(Clone, Debug, Default)]
+pub struct GlobalValueNumbering {
+
+    /// Whether each `Pure` or `Const` function of the module is `Const`, by name.
+    callees: HashMap<String, bool>,
+
+}
+
+impl GlobalValueNumbering {
+
+    /// Creates the pass.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+}
+
+impl Pass for GlobalValueNumbering {
+
+    fn name(&self) -> &str {
+        "global-value-numbering"
+    }
+
+    fn invalidates(&self) -> &[Analysis] {
+        &[]
+    }
+
+    fn run_module(&mut self, module: &mut Module, analyses: &mut Analyses) -> bool {
+        self.callees = module.functions.iter()
+            .filter(|(name, func)| func.is_pure() && !module.data.contains_key(name))
+            .map(|(name, func)| (name.to_string(), func.is_const()))
+            .collect();
+
+        let mut changed = false;
+
+        for func in module.functions.values_mut() {
+            if !func.blocks.is_empty() {
+                changed |= self.run_function(func, analyses);
+            }
+        }
+
+        changed
+    }
+
+    fn run_function(&mut self, func: &mut Function, analyses: &mut Analyses) -> bool {
+        let dominators = analyses.dominators(func);
+        let mut numbering = Numbering::new(func, dominators, &self.callees);
+
+        // Blocks are numbered after the blocks that dominate them, which have fewer
+        // dominators, so the values that they define are already known.
+        let mut order: Vec<usize> = (0..func.blocks.len()).collect();
+        order.sort_by_key(|b| (0..func.blocks.len()).filter(|d| dominators.dominates(Block(*d as u32), Block(*b as u32))).count());
+
+        for b in order {
+            numbering.block(&func.blocks[b], BlockPath::new(Block(b as u32)));
+        }
+
+        let replaced = numbering.replaced;
+        let mut changed = false;
+
+        for (from, to) in replaced {
+            changed |= func.replace_uses(from, to);
+        }
+
+        changed
+    }
+
+}
+
+/// What a value is numbered by.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Key {
+
+    /// An integer constant.
+    Integer(u64, Type),
+
+    /// A floating point constant, by its bits, so that `0.0` and `-0.0` are different.
+    Float(u64, Type),
+
+    /// A boolean constant.
+    Boolean(bool),
+
+    /// A character constant.
+    Char(String, Type),
+
+    /// A named reference, with the numbers of the values that it is indexed by.
+    Named(String, Vec<Property>, Type),
+
+    /// An instruction, by its opcode, type and the numbers of its operands.  Instructions that
+    /// depend on memory also record the state of memory that they were evaluated in.
+    Instruction(Opcode, Type, Vec<usize>, Option<usize>),
+
+}
+
+/// A property of a named reference, in a `Key`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Property {
+
+    Basic(String),
+    Static(String),
+    Pointer(String),
+    Index(usize),
+
+}
+
+/// Numbers the values of a function, in the order that they are evaluated.
+struct Numbering<'a> {
+
+    func: &'a Function,
+
+    dominators: &'a Dominators,
+
+    /// Whether each `Pure` or `Const` function is `Const`, by name.
+    callees: &'a HashMap<String, bool>,
+
+    /// Whether each value is evaluated every time that it is used.
+    inline: Vec<bool>,
+
+    /// The number of each value that has been numbered.
+    numbers: Vec<Option<usize>>,
+
+    /// Whether each value that has been numbered depends on memory.
+    memory: Vec<bool>,
+
+    /// The number given to each key.
+    keys: HashMap<Key, usize>,
+
+    /// The values that later values with the same number may be replaced with, by number.
+    leaders: HashMap<usize, Vec<Value>>,
+
+    /// The number of numbers given out so far.
+    count: usize,
+
+    /// The state of memory, which changes whenever something may write to it.
+    epoch: usize,
+
+    /// The values whose uses are replaced, and the values that replace them.
+    replaced: Vec<(Value, Value)>,
+
+}
+
+impl<'a> Numbering<'a> {
+
+    fn new(func: &'a Function, dominators: &'a Dominators, callees: &'a HashMap<String, bool>) -> Self {
+        Self {
+            func,
+            dominators,
+            callees,
+            inline: func.loop_values(),
+            numbers: vec![None; func.values.len()],
+            memory: vec![false; func.values.len()],
+            keys: HashMap::new(),
+            leaders: HashMap::new(),
+            count: 0,
+            epoch: 0,
+            replaced: vec![],
+        }
+    }
+
+    /// Numbers the values of a block, and the blocks nested in it, in the order that they run.
+    fn block(&mut self, block: &'a InstBlock, path: BlockPath) {
+        self.epoch += 1;
+
+        let mut order = vec![vec![]; block.insts.len() + 1];
+
+        for (v, position) in block.values.iter().zip(&block.positions) {
+            order[(*position).min(block.insts.len())].push(*v);
+        }
+
+        let mut entered = vec![false; block.blocks.len()];
+
+        for (i, values) in order.into_iter().enumerate() {
+            for v in values {
+                self.value(v);
+            }
+
+            let inst = match block.insts.get(i) {
+                Some(inst) => inst,
+                None => break,
+            };
+
+            if inst.opcode == Opcode::Enter {
+                if let Some(ValueInfo::Block(b)) = inst.arguments.first().and_then(|b| self.func.values.get(*b)) {
+                    let index = b.0 as usize;
+
+                    if index < block.blocks.len() && !entered[index] {
+                        entered[index] = true;
+                        self.nested(block, &path, index);
+                    }
+                }
+            } else if self.writes_memory(inst) {
+                self.epoch += 1;
+            }
+        }
+
+        for (index, entered) in entered.into_iter().enumerate() {
+            if !entered {
+                self.nested(block, &path, index);
+            }
+        }
+    }
+
+    /// Numbers a nested block, along with its `elif` and `else` branches.
+    fn nested(&mut self, parent: &'a InstBlock, path: &BlockPath, index: usize) {
+        let nested = &parent.blocks[index];
+        let path = path.join(BlockStep::Nested(Block(index as u32)));
+
+        self.block(nested, path.clone());
+
+        for (i, elif) in nested.elses.iter().enumerate() {
+            self.block(elif, path.join(BlockStep::Elif(i)));
+        }
+
+        if let Some(else_block) = &nested.else_block {
+            self.block(else_block, path.join(BlockStep::Else));
+        }
+
+        // The nested block may have written to memory any number of times.
+        self.epoch += 1;
+    }
+
+    /// Numbers an instruction value where it is evaluated, replacing it with an earlier value
+    /// with the same number if there is one that is available.
+    fn value(&mut self, v: Value) {
+        let i = v.0 as usize;
+
+        let inst = match self.func.values.get(v) {
+            Some(ValueInfo::Instruction(inst)) if !self.inline[i] && self.numbers[i].is_none() => inst,
+            _ => return,
+        };
+
+        let (number, memory) = match self.key(inst, v) {
+            Some((key, memory)) => (self.number_key(key), memory),
+            None => {
+                if self.writes_memory(inst) {
+                    self.epoch += 1;
+                }
+
+                (self.fresh(), false)
+            },
+        };
+
+        self.numbers[i] = Some(number);
+        self.memory[i] = memory;
+
+        let path = match self.func.values.block(v) {
+            Some(path) => path,
+            None => return,
+        };
+
+        let values = &self.func.values;
+        let dominators = self.dominators;
+        let leaders = self.leaders.entry(number).or_default();
+
+        // A value in the same block must come first, so that it is still defined before the
+        // uses that it takes over.
+        let leader = leaders.iter().copied().find(|u| match values.block(*u) {
+            Some(def) if def == path => u.0 < v.0,
+            Some(def) => dominators.is_available(def, path),
+            None => false,
+        });
+
+        match leader {
+            Some(u) => self.replaced.push((v, u)),
+            None => leaders.push(v),
+        }
+    }
+
+    /// Returns the key of an instruction and whether it depends on memory, or `None` if the
+    /// instruction can't be merged with another.
+    fn key(&mut self, inst: &InstructionInfo, v: Value) -> Option<(Key, bool)> {
+        let ty = self.func.values.ty(v)?.clone();
+
+        // A call depends on memory if its callee reads it, and otherwise only on its arguments,
+        // not on the name of the function that it calls.
+        let (reads_memory, operands) = match inst.opcode {
+            Opcode::Call => (!*self.callee(inst)?, inst.arguments.get(1..)?),
+            Opcode::Set | Opcode::Enter | Opcode::Break | Opcode::Continue => return None,
+            opcode if opcode.is_terminator() => return None,
+            _ => (false, &inst.arguments[..]),
+        };
+
+        let mut args: Vec<usize> = inst.arguments.iter().map(|arg| self.number(*arg)).collect();
+
+        if inst.opcode.is_commutative() && args.len() == 2 {
+            args.sort_unstable();
+        }
+
+        let memory = reads_memory || operands.iter().any(|arg| self.memory[arg.0 as usize]);
+        let epoch = if memory { Some(self.epoch) } else { None };
+
+        Some((Key::Instruction(inst.opcode, ty, args, epoch), memory))
+    }
+
+    /// Returns the number of a value, giving a value that isn't an instruction a number the
+    /// first time that it is used.  Instructions that haven't been evaluated yet are only equal
+    /// to themselves.
+    fn number(&mut self, v: Value) -> usize {
+        let i = v.0 as usize;
+
+        if let Some(number) = self.numbers.get(i).copied().flatten() {
+            return number;
+        }
+
+        let ty = self.func.values.ty(v).cloned().unwrap_or(Type::Void);
+
+        let key = match self.func.values.get(v) {
+            Some(ValueInfo::IntegerConstant(n)) => Some(Key::Integer(*n, ty)),
+            Some(ValueInfo::FloatConstant(n)) | Some(ValueInfo::DoubleConstant(n)) => Some(Key::Float(n.to_bits(), ty)),
+            Some(ValueInfo::BooleanConstant(b)) => Some(Key::Boolean(*b)),
+            Some(ValueInfo::CharConstant(s)) => Some(Key::Char(s.to_string(), ty)),
+            Some(ValueInfo::Named(named)) => {
+                let properties = named.properties.iter()
+                    .map(|prop| match prop {
+                        NamedProperty::Basic(name) => Property::Basic(name.to_string()),
+                        NamedProperty::Static(name) => Property::Static(name.to_string()),
+                        NamedProperty::Pointer(name) => Property::Pointer(name.to_string()),
+                        NamedProperty::Index(index) => Property::Index(self.number(*index)),
+                    })
+                    .collect();
+
+                if let Some(memory) = self.memory.get_mut(i) {
+                    *memory = true;
+                }
+
+                Some(Key::Named(named.name.to_string(), properties, ty))
+            },
+            _ => None,
+        };
+
+        let number = match key {
+            Some(key) => self.number_key(key),
+            None => self.fresh(),
+        };
+
+        if let Some(slot) = self.numbers.get_mut(i) {
+            *slot = Some(number);
+        }
+
+        number
+    }
+
+    /// Returns the number of a key, giving it a new one if it hasn't been seen before.
+    fn number_key(&mut self, key: Key) -> usize {
+        let count = &mut self.count;
+
+        *self.keys.entry(key).or_insert_with(|| {
+            *count += 1;
+            *count - 1
+        })
+    }
+
+    /// Returns a number that no other value has.
+    fn fresh(&mut self) -> usize {
+        self.count += 1;
+        self.count - 1
+    }
+
+    /// Returns whether the function that a call calls is `Const`, if it is `Pure` or `Const`.
+    fn callee(&self, inst: &InstructionInfo) -> Option<&'a bool> {
+        let name = self.func.callee(*inst.arguments.first()?)?;
+        self.callees.get(name)
+    }
+
+    /// Returns true if an instruction may write to memory, which is the case for `set` and
+    /// calls to functions that aren't `Pure` or `Const`.
+    fn writes_memory(&self, inst: &InstructionInfo) -> bool {
+        match inst.opcode {
+            Opcode::Set => true,
+            Opcode::Call => self.callee(inst).is_none(),
+            _ => false,
+        }
+    }
+
+}
@@ -1,13 +1,109 @@
 //! Provides a trait for building instructions.
 
-use crate::entities::{AbiType, Block, Named, NamedProperty, Type, Value, ValueInfo};
-use crate::instruction::{InstBlock, InstructionInfo, Opcode};
+use crate::entities::{Block, BlockCall, Named, NamedProperty, StringEncoding, Type, Value, ValueInfo};
+use crate::error::CodegenError;
+use crate::instruction::{BlockBuilder, BlockType, InstBlock, InstructionInfo, Opcode};
+use std::error::Error;
+use std::fmt;
+
+/// An error produced when an instruction is built with values of the wrong types.
+#[derive(Clone, PartialEq, Debug)]
+pub enum BuilderError {
+
+    /// A value that does not exist in the InstBuilder.
+    InvalidValue(Value),
+
+    /// Two values that must have the same type, such as the operands of an `add`, or a
+    /// function parameter and the argument passed to it.
+    MismatchedTypes {
+        opcode: Opcode,
+        left: Type,
+        right: Type,
+    },
+
+    /// A value whose type can't be used with the opcode, such as a float in a bitwise
+    /// operation.
+    InvalidOperand {
+        opcode: Opcode,
+        ty: Type,
+    },
+
+    /// A call to a value that is neither a function nor a pointer to a function.
+    NotAFunction(Type),
+
+    /// A call with a different number of arguments than the function takes.
+    CallArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+
+    /// An instruction with the wrong number of arguments for its opcode.
+    WrongArgumentCount {
+        opcode: Opcode,
+        found: usize,
+    },
+
+    /// An instruction added to a block that already ends with a terminator, such as a `ret`
+    /// or `jmp`.  The terminator's opcode is given.
+    AfterTerminator(Opcode),
+
+    /// A `switch` with two cases for the same value.
+    DuplicateCase(u64),
+
+    /// A `switch` case whose value doesn't fit in the type of the scrutinee.
+    CaseOutOfRange {
+        value: u64,
+        ty: Type,
+    },
+
+}
+
+impl fmt::Display for BuilderError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuilderError::InvalidValue(v) => write!(f, "value v{} is not defined", v.0),
+            BuilderError::MismatchedTypes { opcode, left, right } => {
+                write!(f, "`{}` expects matching types, found `{}` and `{}`", opcode.name(), left, right)
+            },
+            BuilderError::InvalidOperand { opcode, ty } => {
+                write!(f, "`{}` can't be used with `{}`", opcode.name(), ty)
+            },
+            BuilderError::NotAFunction(ty) => write!(f, "`{}` is not a function", ty),
+            BuilderError::CallArgumentCount { expected, found } => {
+                write!(f, "the function takes {} arguments, found {}", expected, found)
+            },
+            BuilderError::WrongArgumentCount { opcode, found } => {
+                write!(f, "`{}` can't take {} arguments", opcode.name(), found)
+            },
+            BuilderError::AfterTerminator(opcode) => {
+                write!(f, "the block already ends with `{}`", opcode.name())
+            },
+            BuilderError::DuplicateCase(value) => write!(f, "switch case {} is given twice", value),
+            BuilderError::CaseOutOfRange { value, ty } => {
+                write!(f, "switch case {} doesn't fit in `{}`", value, ty)
+            },
+        }
+    }
+
+}
+
+impl Error for BuilderError {}
 
 /// A trait for building instructions.
 pub trait InstBuilder {
 
-    /// Defines a value in the InstBuilder's values table, then returning the value.
-    fn create_value(&mut self, value: ValueInfo) -> Value;
+    /// Defines a value with the given type in the values table of the InstBuilder's function,
+    /// then returning the value.
+    fn create_value(&mut self, value: ValueInfo, ty: Type) -> Value;
+
+    /// Returns the type of a value in the values table of the InstBuilder's function, if it
+    /// exists.
+    fn value_type(&self, value: Value) -> Option<&Type>;
+
+    /// Adds a parameter of the given type to the InstBuilder's block, returning the value
+    /// that it takes.  Branches to the block pass a value for each parameter.
+    fn append_param(&mut self, ty: Type) -> Value;
 
     /// Pushes an instruction to the InstBuilder's instruction list.
     fn create_inst(&mut self, inst: InstructionInfo);
@@ -16,45 +112,106 @@ pub trait InstBuilder {
     /// to said block.
     fn create_block(&mut self, block: InstBlock) -> Block;
 
-    /// Returns a pointer to the given block.
-    fn use_block(&mut self, block: Block) -> &mut InstBlock;
+    /// Returns a builder for the given block, or an error if it isn't nested in this
+    /// InstBuilder.
+    fn use_block(&mut self, block: Block) -> Result<BlockBuilder<'_>, CodegenError>;
+
+    /// Adds an `else if` branch to a conditional block, returning a builder for the new
+    /// branch.  The condition is a value from this InstBuilder, like the condition of the
+    /// block itself.
+    fn elif_block(&mut self, block: Block, cond: Value) -> Result<BlockBuilder<'_>, CodegenError>;
+
+    /// Returns a builder for the `else` branch of a conditional block, creating the branch if
+    /// it doesn't exist.
+    fn else_block(&mut self, block: Block) -> Result<BlockBuilder<'_>, CodegenError>;
 
     /// Adds a required import.
     fn require_import(&mut self, name: String);
 
-    /// Creates an unsigned 64-bit integer constant.
-    fn iconst_int(&mut self, value: u64) -> Value {
-        self.create_value(ValueInfo::IntegerConstant(value))
+    /// Returns the opcode of the terminator that ends the InstBuilder's instruction list, if
+    /// it has one.
+    fn terminator(&self) -> Option<Opcode>;
+
+    /// Returns an error if the InstBuilder already ends with a terminator, as no instructions
+    /// can follow one.
+    fn check_terminator(&self) -> Result<(), BuilderError> {
+        match self.terminator() {
+            Some(opcode) => Err(BuilderError::AfterTerminator(opcode)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the types of the given values, or an error if one of them doesn't exist.
+    fn value_types(&self, values: &[Value]) -> Result<Vec<Type>, BuilderError> {
+        values.iter()
+            .map(|v| self.value_type(*v).cloned().ok_or(BuilderError::InvalidValue(*v)))
+            .collect()
+    }
+
+    /// Creates an instruction value after checking the types of its arguments.  The type of
+    /// the value is inferred from the opcode and its arguments.
+    fn build_value(&mut self, opcode: Opcode, arguments: Vec<Value>) -> Result<Value, BuilderError> {
+        self.check_terminator()?;
+        let ty = opcode.result_type(&self.value_types(&arguments)?)?;
+        Ok(self.create_value(ValueInfo::Instruction(InstructionInfo { opcode, arguments }), ty))
+    }
+
+    /// Pushes an instruction to the instruction list after checking the types of its
+    /// arguments, and that the list doesn't already end with a terminator.
+    fn build_inst(&mut self, opcode: Opcode, arguments: Vec<Value>) -> Result<(), BuilderError> {
+        self.check_terminator()?;
+        opcode.result_type(&self.value_types(&arguments)?)?;
+        self.create_inst(InstructionInfo { opcode, arguments });
+
+        Ok(())
+    }
+
+    /// Creates an integer constant of the given integer type, from the bits of an unsigned
+    /// 64-bit integer.
+    fn iconst_int(&mut self, value: u64, ty: Type) -> Value {
+        self.create_value(ValueInfo::IntegerConstant(value), ty)
     }
 
     /// Creates an unsigned 64-bit float constant.
     fn iconst_float(&mut self, value: f64) -> Value {
-        self.create_value(ValueInfo::FloatConstant(value))
+        self.create_value(ValueInfo::FloatConstant(value), Type::Float)
     }
 
     /// Creates an unsigned 64-bit double constant.
     fn iconst_double(&mut self, value: f64) -> Value {
-        self.create_value(ValueInfo::DoubleConstant(value))
+        self.create_value(ValueInfo::DoubleConstant(value), Type::Double)
     }
 
     /// Creates a boolean constant.
     fn iconst_bool(&mut self, value: bool) -> Value {
-        self.create_value(ValueInfo::BooleanConstant(value))
+        self.create_value(ValueInfo::BooleanConstant(value), Type::Bool)
     }
 
-    /// Creates a string constant.
+    /// Creates a string constant, which has the type `char*`.
     fn iconst_str(&mut self, value: String) -> Value {
-        self.create_value(ValueInfo::StringConstant(value))
+        self.iconst_encoded_str(value, StringEncoding::Narrow)
     }
 
-    /// Creates a named reference constant.
-    fn iconst_named(&mut self, name: String) -> Value {
-        self.create_value(ValueInfo::Named(Named::new(name)))
+    /// Creates a string constant in the given encoding, which is a pointer to the character
+    /// type of the encoding.
+    fn iconst_encoded_str(&mut self, value: String, encoding: StringEncoding) -> Value {
+        self.create_value(ValueInfo::StringConstant(value, encoding), Type::pointer(encoding.char_type()))
     }
 
-    /// Creates a named reference constant including the provided properties.
-    fn iconst_named_props(&mut self, name: String, props: Vec<NamedProperty>) -> Value {
-        self.create_value(ValueInfo::Named(Named::new_props(name, props)))
+    /// Creates a string of raw bytes, which has the type `char*`.
+    fn iconst_bytes(&mut self, value: Vec<u8>) -> Value {
+        self.create_value(ValueInfo::BytesConstant(value), Type::pointer(Type::Char))
+    }
+
+    /// Creates a named reference constant, which refers to a value of the given type.
+    fn iconst_named(&mut self, name: String, ty: Type) -> Value {
+        self.create_value(ValueInfo::Named(Named::new(name)), ty)
+    }
+
+    /// Creates a named reference constant including the provided properties.  The type is the
+    /// type of the value that the last property refers to.
+    fn iconst_named_props(&mut self, name: String, props: Vec<NamedProperty>, ty: Type) -> Value {
+        self.create_value(ValueInfo::Named(Named::new_props(name, props)), ty)
     }
 
     /// Returns a new basic NamedProperty.
@@ -79,313 +236,363 @@ pub trait InstBuilder {
 
     /// Creates a C target specific boolean type.  Requires the `stdbool.h` standard library
     /// to be provided by your C compiler.
-    fn ctype_bool(&mut self) -> AbiType {
+    fn ctype_bool(&mut self) -> Type {
         // The C bool type requires `stdbool.h` to be imported.
         self.require_import("stdbool.h".into());
-        AbiType("bool".into(), Type::Plain)
+        Type::Bool
     }
 
     /// Creates a C target specific 8 bit unsigned integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_uint8(&mut self) -> AbiType {
+    fn ctype_uint8(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("uint8_t".into(), Type::Plain)
+        Type::U8
     }
 
     /// Creates a C target specific 16 bit unsigned integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_uint16(&mut self) -> AbiType {
+    fn ctype_uint16(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("uint16_t".into(), Type::Plain)
+        Type::U16
     }
 
     /// Creates a C target specific 32 bit unsigned integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_uint32(&mut self) -> AbiType {
+    fn ctype_uint32(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("uint32_t".into(), Type::Plain)
+        Type::U32
     }
 
     /// Creates a C target specific 64 bit unsigned integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_uint64(&mut self) -> AbiType {
+    fn ctype_uint64(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("uint64_t".into(), Type::Plain)
+        Type::U64
     }
 
     /// Creates a C target specific size that scales to the target's architecture.  For 32-bit
     /// processors, this is the same as a `uint32` and for 64-bit architectures this is the
     /// same as a `uint64`.
-    fn ctype_usize(&mut self) -> AbiType {
+    fn ctype_usize(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("uintptr".into(), Type::Plain)
+        Type::USize
     }
 
     /// Creates a C target specific 8 bit integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_int8(&mut self) -> AbiType {
+    fn ctype_int8(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("int8_t".into(), Type::Plain)
+        Type::I8
     }
 
     /// Creates a C target specific 16 bit integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_int16(&mut self) -> AbiType {
+    fn ctype_int16(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("uint16_t".into(), Type::Plain)
+        Type::I16
     }
 
     /// Creates a C target specific 32 bit integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_int32(&mut self) -> AbiType {
+    fn ctype_int32(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("int32_t".into(), Type::Plain)
+        Type::I32
     }
 
     /// Creates a C target specific 64 bit integer type.  Requires the `stdint.h`
     /// standard library to be provided by your C compiler.
-    fn ctype_int64(&mut self) -> AbiType {
+    fn ctype_int64(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("int64_t".into(), Type::Plain)
+        Type::I64
     }
 
     /// Creates a C target specific size that scales to the target's architecture.  For 32-bit
     /// processors, this is the same as an `int32` and for 64-bit architectures this is the
     /// same as an `int64`.
-    fn ctype_isize(&mut self) -> AbiType {
+    fn ctype_isize(&mut self) -> Type {
         self.require_import("stdint.h".into());
-        AbiType("intptr".into(), Type::Plain)
+        Type::ISize
     }
 
     /// A C-specific character type.
-    fn ctype_char(&mut self) -> AbiType {
-        AbiType("char".into(), Type::Plain)
+    fn ctype_char(&mut self) -> Type {
+        Type::Char
     }
 
     /// Adds two values together and returns the sum of the expression.
-    fn iadd(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Add,
-            arguments: vec![l, r]
-        }))
+    fn iadd(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Add, vec![l, r])
     }
 
     /// Subtracts two values together and returns the sum of the expression.
-    fn isub(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Sub,
-            arguments: vec![l, r]
-        }))
+    fn isub(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Sub, vec![l, r])
     }
 
     /// Multiplies two values together and returns the sum of the expression.
-    fn imul(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Mul,
-            arguments: vec![l, r]
-        }))
+    fn imul(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Mul, vec![l, r])
     }
 
     /// Divides two values together and returns the sum of the expression.
-    fn idiv(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Div,
-            arguments: vec![l, r]
-        }))
+    fn idiv(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Div, vec![l, r])
     }
 
     /// Divides two values together and returns the remainder of the expression.  Equivalent to
     /// the `%` (modulus) operator.
-    fn imod(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Mod,
-            arguments: vec![l, r]
-        }))
+    fn imod(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Mod, vec![l, r])
     }
 
     /// Returns the results of the Bitwise AND operation.
-    fn ibit_and(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::BitAnd,
-            arguments: vec![l, r]
-        }))
+    fn ibit_and(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::BitAnd, vec![l, r])
     }
 
     /// Returns the results of the Bitwise OR operation.
-    fn ibit_or(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::BitOr,
-            arguments: vec![l, r]
-        }))
+    fn ibit_or(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::BitOr, vec![l, r])
     }
 
     /// Returns the results of the Bitwise XOR operation.
-    fn ibit_xor(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::BitXor,
-            arguments: vec![l, r]
-        }))
+    fn ibit_xor(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::BitXor, vec![l, r])
     }
 
     /// Returns the results of the Bitwise NOT operation.
-    fn ibit_not(&mut self, l: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::BitNot,
-            arguments: vec![l]
-        }))
+    fn ibit_not(&mut self, l: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::BitNot, vec![l])
     }
 
     /// Returns the results of the Bitwise left shift operation.
-    fn ibit_left(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::BitLeft,
-            arguments: vec![l, r]
-        }))
+    fn ibit_left(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::BitLeft, vec![l, r])
     }
 
     /// Returns the results of the Bitwise right shift operation.
-    fn ibit_right(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::BitRight,
-            arguments: vec![l, r]
-        }))
+    fn ibit_right(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::BitRight, vec![l, r])
     }
 
     /// Tests if two values are equal to eachother.  Returns a boolean value with the result of
     /// the operation.
-    fn itest_eq(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::TestEq,
-            arguments: vec![l, r]
-        }))
+    fn itest_eq(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::TestEq, vec![l, r])
     }
 
     /// Tests if two values are not equal to eachother.  Returns a boolean value with the result
     /// of the operation.
-    fn itest_neq(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::TestNeq,
-            arguments: vec![l, r]
-        }))
+    fn itest_neq(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::TestNeq, vec![l, r])
     }
 
     /// Tests if the first value is greater than the second.  Returns a boolean value with the
     /// result of the operation.
-    fn itest_gt(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::TestGt,
-            arguments: vec![l, r]
-        }))
+    fn itest_gt(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::TestGt, vec![l, r])
     }
 
     /// Tests if the first value is greater than or equal to the second.  Returns a boolean
     /// value with the result of the operation.
-    fn itest_gt_eq(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::TestGtEq,
-            arguments: vec![l, r]
-        }))
+    fn itest_gt_eq(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::TestGtEq, vec![l, r])
     }
 
     /// Tests if the first value is less than the second.  Returns a boolean value with the
     /// result of the operation.
-    fn itest_lt(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::TestLt,
-            arguments: vec![l, r]
-        }))
+    fn itest_lt(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::TestLt, vec![l, r])
     }
 
     /// Tests if the first value is less than or equal to the second.  Returns a boolean
     /// value with the result of the operation.
-    fn itest_lt_eq(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::TestLtEq,
-            arguments: vec![l, r]
-        }))
+    fn itest_lt_eq(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::TestLtEq, vec![l, r])
     }
 
     /// Negates a boolean value.
-    fn inot(&mut self, l: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Not,
-            arguments: vec![l]
-        }))
+    fn inot(&mut self, l: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Not, vec![l])
     }
 
     /// Returns if either value is equal to true.
-    fn ior(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Or,
-            arguments: vec![l, r]
-        }))
+    fn ior(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Or, vec![l, r])
     }
 
     /// Returns if both values are equal to true.
-    fn iand(&mut self, l: Value, r: Value) -> Value {
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::And,
-            arguments: vec![l, r]
-        }))
+    fn iand(&mut self, l: Value, r: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::And, vec![l, r])
     }
 
-    /// Unconditionally jumps to a certain block.
-    fn jmp(&mut self, block: Block) {
-        let b = self.create_value(ValueInfo::Block(block));
-        self.create_inst(InstructionInfo {
-            opcode: Opcode::Jmp,
-            arguments: vec![b]
-        });
+    /// Creates a branch target, passing values to the parameters of the block.
+    fn block_target(&mut self, target: BlockCall) -> Value {
+        if target.1.is_empty() {
+            self.create_value(ValueInfo::Block(target.0), Type::Void)
+        } else {
+            self.create_value(ValueInfo::BlockCall(target), Type::Void)
+        }
     }
 
-    /// Uses a named reference as a value.
-    fn iuse(&mut self, named: Named) -> Value {
-        self.create_value(ValueInfo::Named(named))
+    /// Unconditionally jumps to a certain block, which may be given arguments for its
+    /// parameters with a `BlockCall`.  This is a terminator, so it must be the last
+    /// instruction of the block.
+    fn jmp(&mut self, target: impl Into<BlockCall>) -> Result<(), BuilderError> {
+        self.check_terminator()?;
+        let b = self.block_target(target.into());
+        self.build_inst(Opcode::Jmp, vec![b])
+    }
+
+    /// Branches to `then` if `cond` is true, or to `otherwise` if it is false.  Both blocks
+    /// are blocks of the function, and this is a terminator like `jmp`.
+    fn brif(&mut self, cond: Value, then: impl Into<BlockCall>, otherwise: impl Into<BlockCall>) -> Result<(), BuilderError> {
+        self.check_terminator()?;
+
+        // The condition is checked first, so that no block values are left behind if it
+        // isn't a `bool`.
+        let ty = self.value_types(&[cond])?.remove(0);
+        Opcode::Brif.result_type(&[ty, Type::Void, Type::Void])?;
+
+        let t = self.block_target(then.into());
+        let e = self.block_target(otherwise.into());
+        self.build_inst(Opcode::Brif, vec![cond, t, e])
+    }
+
+    /// Branches to the block of the case whose constant equals `v`, or to `default` if none
+    /// of them do.  The constants are created with the type of `v`, which must be an integer
+    /// or a character.  This is a terminator like `jmp`.
+    fn switch<B: Into<BlockCall>>(&mut self, v: Value, cases: Vec<(u64, B)>, default: impl Into<BlockCall>) -> Result<(), BuilderError> {
+        self.check_terminator()?;
+
+        let ty = self.value_types(&[v])?.remove(0);
+        Opcode::Switch.result_type(&[ty.clone(), Type::Void])?;
+
+        let mut keys = vec![];
+
+        for (value, _) in &cases {
+            let key = ty.integer_value(*value).ok_or_else(|| BuilderError::CaseOutOfRange {
+                value: *value,
+                ty: ty.clone(),
+            })?;
+
+            if keys.contains(&key) {
+                return Err(BuilderError::DuplicateCase(*value));
+            }
+
+            keys.push(key);
+        }
+
+        let mut args = vec![v, self.block_target(default.into())];
+
+        for (value, target) in cases {
+            args.push(self.iconst_int(value, ty.clone()));
+            args.push(self.block_target(target.into()));
+        }
+
+        self.build_inst(Opcode::Switch, args)
+    }
+
+    /// Enters one of the blocks nested in this InstBuilder, running it at this point.
+    fn enter(&mut self, block: Block) -> Result<(), BuilderError> {
+        self.check_terminator()?;
+        let b = self.create_value(ValueInfo::Block(block), Type::Void);
+        self.build_inst(Opcode::Enter, vec![b])
+    }
+
+    /// Creates a nested block of the given type and enters it at this point.
+    fn enter_block(&mut self, block_type: BlockType) -> Result<Block, BuilderError> {
+        self.check_terminator()?;
+        let block = self.create_block(InstBlock::new(block_type));
+        self.enter(block)?;
+
+        Ok(block)
+    }
+
+    /// Creates a nested block that only runs if `cond` is true, and enters it at this point.
+    /// More branches can be added to the block with `elif_block` and `else_block`.
+    fn if_block(&mut self, cond: Value) -> Result<Block, BuilderError> {
+        self.enter_block(BlockType::If(cond))
+    }
+
+    /// Creates a nested loop block that runs while `cond` is true, and enters it at this
+    /// point.  The condition is tested before each iteration.
+    fn while_block(&mut self, cond: Value) -> Result<Block, BuilderError> {
+        self.enter_block(BlockType::While(cond))
+    }
+
+    /// Creates a nested loop block that runs while `cond` is true, and enters it at this
+    /// point.  The condition is tested after each iteration, so the block runs at least once.
+    fn do_while_block(&mut self, cond: Value) -> Result<Block, BuilderError> {
+        self.enter_block(BlockType::DoWhile(cond))
+    }
+
+    /// Creates a nested counted loop block and enters it at this point.  `init` runs once
+    /// before the loop and `step` runs after each iteration; both are usually created with
+    /// `iset`.
+    fn for_block(&mut self, init: Option<Value>, cond: Value, step: Option<Value>) -> Result<Block, BuilderError> {
+        self.enter_block(BlockType::For { init, cond, step })
+    }
+
+    /// Exits the innermost loop that this InstBuilder is nested in.
+    fn break_(&mut self) -> Result<(), BuilderError> {
+        self.build_inst(Opcode::Break, vec![])
+    }
+
+    /// Skips to the next iteration of the innermost loop that this InstBuilder is nested in.
+    fn continue_(&mut self) -> Result<(), BuilderError> {
+        self.build_inst(Opcode::Continue, vec![])
+    }
+
+    /// Uses a named reference to a value of the given type as a value.
+    fn iuse(&mut self, named: Named, ty: Type) -> Value {
+        self.create_value(ValueInfo::Named(named), ty)
     }
 
     /// Sets a value, equivalent to the `=` assignment operator in most programming languages.
-    fn set(&mut self, k: Value, v: Value) {
-        self.create_inst(InstructionInfo {
-            opcode: Opcode::Set,
-            arguments: vec![k, v]
-        });
+    /// Both values must have the same type.
+    fn set(&mut self, k: Value, v: Value) -> Result<(), BuilderError> {
+        self.build_inst(Opcode::Set, vec![k, v])
+    }
+
+    /// Creates an assignment as a value, such as the `init` and `step` of a `for_block`.
+    fn iset(&mut self, k: Value, v: Value) -> Result<Value, BuilderError> {
+        self.build_value(Opcode::Set, vec![k, v])
     }
 
-    /// Makes a function call with the specified function name and arguments.
-    fn call(&mut self, k: Value, args: Vec<Value>) {
+    /// Makes a function call with the specified function name and arguments.  The arguments
+    /// must match the parameter types of the function.
+    fn call(&mut self, k: Value, args: Vec<Value>) -> Result<(), BuilderError> {
         let mut v = vec![k];
         v.append(&mut args.clone());
-        self.create_inst(InstructionInfo {
-            opcode: Opcode::Call,
-            arguments: v
-        });
+        self.build_inst(Opcode::Call, v)
     }
 
-    /// Makes a function call and returns the value that the function call returns.
-    fn icall(&mut self, k: Value, args: Vec<Value>) -> Value {
+    /// Makes a function call and returns the value that the function call returns, which has
+    /// the return type of the function.
+    fn icall(&mut self, k: Value, args: Vec<Value>) -> Result<Value, BuilderError> {
         let mut v = vec![k];
         v.append(&mut args.clone());
-        self.create_value(ValueInfo::Instruction(InstructionInfo {
-            opcode: Opcode::Call,
-            arguments: v
-        }))
+        self.build_value(Opcode::Call, v)
     }
 
-    /// Returns a value from the function that this InstBuilder resides in.
-    fn return_(&mut self, v: Value) {
-        self.create_inst(InstructionInfo {
-            opcode: Opcode::Ret,
-            arguments: vec![v]
-        });
+    /// Returns a value from the function that this InstBuilder resides in.  This is a
+    /// terminator, so it must be the last instruction of the block.
+    fn return_(&mut self, v: Value) -> Result<(), BuilderError> {
+        self.build_inst(Opcode::Ret, vec![v])
     }
 
     /// Returns and exits the function, without returning a value.  The function should have a
     /// return type of `void`.
-    fn return_none(&mut self) {
-        self.create_inst(InstructionInfo {
-            opcode: Opcode::Ret,
-            arguments: vec![]
-        });
+    fn return_none(&mut self) -> Result<(), BuilderError> {
+        self.build_inst(Opcode::Ret, vec![])
+    }
+
+    /// Marks the end of the block as unreachable, for example after a call to a function
+    /// that never returns.  This is a terminator, so it must be the last instruction of the
+    /// block.
+    fn unreachable(&mut self) -> Result<(), BuilderError> {
+        self.build_inst(Opcode::Unreachable, vec![])
     }
 
 }
\ No newline at end of file
@@ -1,9 +1,13 @@
 //! Information about possible Cardinal instructions.
 
-use crate::entities::{Block, Value, ValueInfo};
-use crate::instbuilder::InstBuilder;
+use crate::entities::{Block, BlockPath, BlockStep, Type, Value, ValueInfo};
+use crate::error::CodegenError;
+use crate::function::ValueTable;
+use crate::instbuilder::{BuilderError, InstBuilder};
+use std::ops::{Deref, DerefMut};
 
-#[derive(Clone, Copy)]
+/// The operation performed by an instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Opcode {
 
     Add,
@@ -27,14 +31,248 @@ pub enum Opcode {
     Or,
     And,
     Jmp,
+
+    /// Branches to the first block if the condition is true, or the second block if it is
+    /// false.  Both blocks are blocks of the function, like the target of a `Jmp`.
+    Brif,
+
+    /// Branches on an integer.  The arguments are the integer, the default block, and then a
+    /// constant and a block for each case, which runs if the integer equals the constant.
+    Switch,
+
     Set,
     Call,
     Ret,
 
+    /// Exits the innermost loop that the instruction is nested in.
+    Break,
+
+    /// Skips to the next iteration of the innermost loop that the instruction is nested in.
+    Continue,
+
+    /// Enters a nested block, running it at this point in the instruction list.  The argument
+    /// is a block reference to one of the nested blocks of the current block.
+    Enter,
+
+    /// Marks the end of a block as unreachable, such as after a call to a function that never
+    /// returns.
+    Unreachable,
+
+}
+
+impl Opcode {
+
+    /// A list of every opcode, in declaration order.
+    pub const ALL: [Opcode; 30] = [
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::Mul,
+        Opcode::Div,
+        Opcode::Mod,
+        Opcode::BitAnd,
+        Opcode::BitOr,
+        Opcode::BitXor,
+        Opcode::BitLeft,
+        Opcode::BitRight,
+        Opcode::BitNot,
+        Opcode::TestEq,
+        Opcode::TestNeq,
+        Opcode::TestGt,
+        Opcode::TestGtEq,
+        Opcode::TestLt,
+        Opcode::TestLtEq,
+        Opcode::Not,
+        Opcode::Or,
+        Opcode::And,
+        Opcode::Jmp,
+        Opcode::Brif,
+        Opcode::Switch,
+        Opcode::Set,
+        Opcode::Call,
+        Opcode::Ret,
+        Opcode::Break,
+        Opcode::Continue,
+        Opcode::Enter,
+        Opcode::Unreachable,
+    ];
+
+    /// Returns the mnemonic of the opcode, as used by the textual IR format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Sub => "sub",
+            Opcode::Mul => "mul",
+            Opcode::Div => "div",
+            Opcode::Mod => "mod",
+            Opcode::BitAnd => "bit_and",
+            Opcode::BitOr => "bit_or",
+            Opcode::BitXor => "bit_xor",
+            Opcode::BitLeft => "bit_left",
+            Opcode::BitRight => "bit_right",
+            Opcode::BitNot => "bit_not",
+            Opcode::TestEq => "test_eq",
+            Opcode::TestNeq => "test_neq",
+            Opcode::TestGt => "test_gt",
+            Opcode::TestGtEq => "test_gt_eq",
+            Opcode::TestLt => "test_lt",
+            Opcode::TestLtEq => "test_lt_eq",
+            Opcode::Not => "not",
+            Opcode::Or => "or",
+            Opcode::And => "and",
+            Opcode::Jmp => "jmp",
+            Opcode::Brif => "brif",
+            Opcode::Switch => "switch",
+            Opcode::Set => "set",
+            Opcode::Call => "call",
+            Opcode::Ret => "ret",
+            Opcode::Break => "break",
+            Opcode::Continue => "continue",
+            Opcode::Enter => "enter",
+            Opcode::Unreachable => "unreachable",
+        }
+    }
+
+    /// Returns true if the opcode is a terminator, which ends a block by transferring control
+    /// somewhere else.  A terminator must be the last instruction of its block.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Opcode::Ret | Opcode::Jmp | Opcode::Brif | Opcode::Switch | Opcode::Unreachable)
+    }
+
+    /// Returns true if the opcode changes where control goes, which is the case for the
+    /// terminators, and for `Break`, `Continue` and `Enter`, which may appear anywhere in a
+    /// block.
+    pub fn is_control_flow(&self) -> bool {
+        self.is_terminator() || matches!(self, Opcode::Break | Opcode::Continue | Opcode::Enter)
+    }
+
+    /// Returns true if the opcode gives the same result when its two operands are swapped.
+    pub fn is_commutative(&self) -> bool {
+        matches!(self, Opcode::Add | Opcode::Mul | Opcode::BitAnd | Opcode::BitOr | Opcode::BitXor
+            | Opcode::TestEq | Opcode::TestNeq | Opcode::And | Opcode::Or)
+    }
+
+    /// Looks up an opcode from its mnemonic.
+    pub fn from_name(name: &str) -> Option<Opcode> {
+        Opcode::ALL.iter().copied().find(|op| op.name() == name)
+    }
+
+    /// Returns the type of the result of the opcode when it is used with arguments of the
+    /// given types, or an error if the types can't be used together.
+    ///
+    /// Arithmetic takes the type of its operands, which must match, except that an integer
+    /// can be added to or subtracted from a pointer.  Comparisons and logical operators
+    /// produce a `bool`, `set` produces the type it assigns to and a call produces the return
+    /// type of the function it calls.  Control flow opcodes produce `void`.
+    pub fn result_type(&self, args: &[Type]) -> Result<Type, BuilderError> {
+        let same = |l: &Type, r: &Type, valid: fn(&Type) -> bool| {
+            if l != r {
+                Err(BuilderError::MismatchedTypes { opcode: *self, left: l.clone(), right: r.clone() })
+            } else if !valid(l) {
+                Err(BuilderError::InvalidOperand { opcode: *self, ty: l.clone() })
+            } else {
+                Ok(l.clone())
+            }
+        };
+
+        let valid = |ty: &Type, valid: fn(&Type) -> bool| {
+            if valid(ty) {
+                Ok(ty.clone())
+            } else {
+                Err(BuilderError::InvalidOperand { opcode: *self, ty: ty.clone() })
+            }
+        };
+
+        match (self, args) {
+            (Opcode::Add, [ptr @ Type::Pointer(_), n]) | (Opcode::Add, [n, ptr @ Type::Pointer(_)])
+            | (Opcode::Sub, [ptr @ Type::Pointer(_), n]) if is_integral(n) => Ok(ptr.clone()),
+            (Opcode::Sub, [l @ Type::Pointer(_), r @ Type::Pointer(_)]) => {
+                same(l, r, |_| true).map(|_| Type::ISize)
+            },
+            (Opcode::Add, [l, r]) | (Opcode::Sub, [l, r]) | (Opcode::Mul, [l, r])
+            | (Opcode::Div, [l, r]) => same(l, r, is_numeric),
+            (Opcode::Mod, [l, r]) | (Opcode::BitAnd, [l, r]) | (Opcode::BitOr, [l, r])
+            | (Opcode::BitXor, [l, r]) => same(l, r, is_integral),
+            (Opcode::BitLeft, [l, r]) | (Opcode::BitRight, [l, r]) => {
+                valid(r, is_integral)?;
+                valid(l, is_integral)
+            },
+            (Opcode::BitNot, [v]) => valid(v, is_integral),
+            (Opcode::Not, [v]) => valid(v, |ty| *ty == Type::Bool),
+            (Opcode::And, [l, r]) | (Opcode::Or, [l, r]) => same(l, r, |ty| *ty == Type::Bool),
+            (Opcode::TestEq, [l, r]) | (Opcode::TestNeq, [l, r]) => {
+                same(l, r, |ty| is_numeric(ty) || matches!(ty, Type::Bool | Type::Pointer(_)))
+                    .map(|_| Type::Bool)
+            },
+            (Opcode::TestGt, [l, r]) | (Opcode::TestGtEq, [l, r]) | (Opcode::TestLt, [l, r])
+            | (Opcode::TestLtEq, [l, r]) => {
+                same(l, r, |ty| is_numeric(ty) || matches!(ty, Type::Pointer(_))).map(|_| Type::Bool)
+            },
+            (Opcode::Set, [k, v]) => same(k, v, |ty| *ty != Type::Void),
+            (Opcode::Call, [callee, args @ ..]) => {
+                let func = match callee {
+                    Type::Function(func) => func,
+                    Type::Pointer(to) => match &**to {
+                        Type::Function(func) => func,
+                        _ => return Err(BuilderError::NotAFunction(callee.clone())),
+                    },
+                    _ => return Err(BuilderError::NotAFunction(callee.clone())),
+                };
+
+                if args.len() < func.params.len() || (!func.variadic && args.len() > func.params.len()) {
+                    return Err(BuilderError::CallArgumentCount {
+                        expected: func.params.len(),
+                        found: args.len(),
+                    });
+                }
+
+                for (param, arg) in func.params.iter().zip(args) {
+                    // Arrays decay to pointers to their first element when they are passed to
+                    // a function, like they do in C.
+                    let decays = match (param, arg) {
+                        (Type::Pointer(to), Type::Array(of, _)) => to == of,
+                        _ => false,
+                    };
+
+                    if param != arg && !decays {
+                        return Err(BuilderError::MismatchedTypes {
+                            opcode: *self,
+                            left: param.clone(),
+                            right: arg.clone(),
+                        });
+                    }
+                }
+
+                Ok((*func.returns).clone())
+            },
+            (Opcode::Brif, [cond, _, _]) => valid(cond, |ty| *ty == Type::Bool).map(|_| Type::Void),
+            (Opcode::Switch, [v, _, cases @ ..]) if cases.len() % 2 == 0 => {
+                for case in cases.iter().step_by(2) {
+                    same(v, case, is_integral)?;
+                }
+
+                valid(v, is_integral).map(|_| Type::Void)
+            },
+            (Opcode::Jmp, _) | (Opcode::Ret, _) | (Opcode::Break, _) | (Opcode::Continue, _)
+            | (Opcode::Enter, _) | (Opcode::Unreachable, _) => Ok(Type::Void),
+            _ => Err(BuilderError::WrongArgumentCount { opcode: *self, found: args.len() }),
+        }
+    }
+
+}
+
+/// Returns true if the type can be used with integer arithmetic.  Characters are included,
+/// so that frontends can do arithmetic on text.
+fn is_integral(ty: &Type) -> bool {
+    ty.is_integer() || *ty == Type::Char
+}
+
+/// Returns true if the type can be used with arithmetic.
+fn is_numeric(ty: &Type) -> bool {
+    is_integral(ty) || ty.is_float()
 }
 
 /// Information about an instruction or operation.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct InstructionInfo {
 
     /// The opcode of the instruction.
@@ -46,19 +284,54 @@ pub struct InstructionInfo {
 }
 
 /// A block type for creating different kinds of blocks.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum BlockType {
 
-    /// A basic IF type that uses a value as an expression.
+    /// A basic IF type that uses a value as an expression.  The values of conditional and loop
+    /// blocks are taken from the block that they are nested in.
     If(Value),
 
+    /// A loop that runs while the value is true, testing it before each iteration.
+    While(Value),
+
+    /// A loop that runs while the value is true, testing it after each iteration.
+    DoWhile(Value),
+
+    /// A counted loop, equivalent to C's `for (init; cond; step)`.  `init` and `step` are
+    /// usually assignments made with `iset`.
+    For {
+        init: Option<Value>,
+        cond: Value,
+        step: Option<Value>,
+    },
+
     /// A basic block with no conditions.
     Basic,
 
 }
 
+impl BlockType {
+
+    /// Returns true if the block is a loop, which `Break` and `Continue` can target.
+    pub fn is_loop(&self) -> bool {
+        matches!(self, BlockType::While(_) | BlockType::DoWhile(_) | BlockType::For { .. })
+    }
+
+    /// Returns the values that the block type uses, which are taken from the enclosing block.
+    pub fn values(&self) -> Vec<Value> {
+        match *self {
+            BlockType::If(v) | BlockType::While(v) | BlockType::DoWhile(v) => vec![v],
+            BlockType::For { init, cond, step } => {
+                init.into_iter().chain(Some(cond)).chain(step).collect()
+            },
+            BlockType::Basic => vec![],
+        }
+    }
+
+}
+
 /// A block for instruction building.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct InstBlock {
 
     /// The type of the block.
@@ -70,8 +343,18 @@ pub struct InstBlock {
     /// An else_block for If blocks.
     pub else_block: Option<Box<InstBlock>>,
 
-    /// A list of values defined in the block.
-    pub values: Vec<ValueInfo>,
+    /// The types of the block's parameters, which are defined as `Param` values.  Only the
+    /// blocks of a function can have parameters, as they are passed by branches.
+    pub params: Vec<Type>,
+
+    /// A list of values defined in the block, in the order that they were defined.  The
+    /// values themselves are stored in the function's value table.
+    pub values: Vec<Value>,
+
+    /// The number of instructions in `insts` that were created before each value in
+    /// `values`, which places the value in the block's instruction order.  A value with side
+    /// effects, such as a call, happens at this point.
+    pub positions: Vec<usize>,
 
     /// A list of instructions in the block.
     pub insts: Vec<InstructionInfo>,
@@ -84,34 +367,170 @@ pub struct InstBlock {
 
 }
 
-impl InstBuilder for InstBlock {
+impl InstBlock {
+
+    /// Creates a new empty block of the given type.
+    pub fn new(block_type: BlockType) -> Self {
+        Self {
+            block_type,
+            blocks: vec![],
+            else_block: None,
+            elses: vec![],
+            imports: vec![],
+            insts: vec![],
+            params: vec![],
+            values: vec![],
+            positions: vec![],
+        }
+    }
+
+    /// Returns the position of a value defined in the block, which is the number of
+    /// instructions that were created before it.
+    pub fn position(&self, value: Value) -> Option<usize> {
+        let i = self.values.iter().position(|v| *v == value)?;
+        self.positions.get(i).copied()
+    }
+
+    /// Returns the opcode of the terminator that ends the block, if it has one.
+    pub fn terminator(&self) -> Option<Opcode> {
+        self.insts.last().map(|inst| inst.opcode).filter(Opcode::is_terminator)
+    }
+
+}
+
+/// Builds the instructions of a block, defining its values in the value table of the
+/// function that the block is in.  It dereferences to the block being built.
+#[derive(Debug)]
+pub struct BlockBuilder<'a> {
+
+    /// The value table of the function.
+    values: &'a mut ValueTable,
+
+    /// The block being built.
+    block: &'a mut InstBlock,
+
+    /// The location of the block in the function.
+    path: BlockPath,
+
+}
+
+impl<'a> BlockBuilder<'a> {
+
+    /// Creates a builder for a block at the given location, which defines its values in the
+    /// function's value table.
+    pub fn new(values: &'a mut ValueTable, block: &'a mut InstBlock, path: BlockPath) -> Self {
+        Self {
+            values,
+            block,
+            path,
+        }
+    }
+
+    /// Returns the location of the block in the function.
+    pub fn path(&self) -> &BlockPath {
+        &self.path
+    }
+
+    /// Returns information about a value of the function, if it exists.
+    pub fn value(&self, value: Value) -> Option<&ValueInfo> {
+        self.values.get(value)
+    }
+
+    /// Returns a builder for a block nested in this one, or for one of its branches.
+    fn nested(&mut self, block: Block, step: Option<BlockStep>) -> Result<BlockBuilder<'_>, CodegenError> {
+        let mut path = self.path.join(BlockStep::Nested(block));
+        let nested = self.block.blocks.get_mut(block.0 as usize)
+            .ok_or(CodegenError::MissingBlock { function: None, block })?;
+
+        let branch = match step {
+            Some(BlockStep::Elif(i)) => &mut nested.elses[i],
+            Some(BlockStep::Else) => nested.else_block.get_or_insert_with(|| Box::new(InstBlock::new(BlockType::Basic))),
+            _ => nested,
+        };
+
+        if let Some(step) = step {
+            path.steps.push(step);
+        }
+
+        Ok(BlockBuilder::new(self.values, branch, path))
+    }
+
+}
+
+impl<'a> Deref for BlockBuilder<'a> {
+
+    type Target = InstBlock;
+
+    fn deref(&self) -> &InstBlock {
+        self.block
+    }
+
+}
+
+impl<'a> DerefMut for BlockBuilder<'a> {
+
+    fn deref_mut(&mut self) -> &mut InstBlock {
+        self.block
+    }
+
+}
+
+impl<'a> InstBuilder for BlockBuilder<'a> {
 
     fn require_import(&mut self, name: String) {
-        if !self.imports.contains(&name) {
-            self.imports.push(name);
+        if !self.block.imports.contains(&name) {
+            self.block.imports.push(name);
         }
     }
 
-    fn create_value(&mut self, value: ValueInfo) -> Value {
-        let val = Value(self.values.len() as u32);
-        self.values.push(value);
+    fn create_value(&mut self, value: ValueInfo, ty: Type) -> Value {
+        let val = self.values.push(value, ty, self.path.clone());
+        self.block.values.push(val);
+        self.block.positions.push(self.block.insts.len());
 
         val
     }
 
+    fn value_type(&self, value: Value) -> Option<&Type> {
+        self.values.ty(value)
+    }
+
+    fn append_param(&mut self, ty: Type) -> Value {
+        let param = ValueInfo::Param(self.block.params.len() as u32);
+        self.block.params.push(ty.clone());
+
+        self.create_value(param, ty)
+    }
+
     fn create_block(&mut self, block: InstBlock) -> Block {
-        let val = Block(self.blocks.len() as u32);
-        self.blocks.push(block);
+        let val = Block(self.block.blocks.len() as u32);
+        self.block.blocks.push(block);
 
         val
     }
 
     fn create_inst(&mut self, inst: InstructionInfo) {
-        self.insts.push(inst);
+        self.block.insts.push(inst);
+    }
+
+    fn use_block(&mut self, block: Block) -> Result<BlockBuilder<'_>, CodegenError> {
+        self.nested(block, None)
+    }
+
+    fn elif_block(&mut self, block: Block, cond: Value) -> Result<BlockBuilder<'_>, CodegenError> {
+        let mut nested = self.use_block(block)?;
+        nested.elses.push(InstBlock::new(BlockType::If(cond)));
+        let i = nested.elses.len() - 1;
+
+        self.nested(block, Some(BlockStep::Elif(i)))
+    }
+
+    fn else_block(&mut self, block: Block) -> Result<BlockBuilder<'_>, CodegenError> {
+        self.nested(block, Some(BlockStep::Else))
     }
 
-    fn use_block(&mut self, block: Block) -> &mut InstBlock {
-        self.blocks.get_mut(block.0 as usize).unwrap()
+    fn terminator(&self) -> Option<Opcode> {
+        self.block.terminator()
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,1474 @@
+//! A reference interpreter, which runs the functions of a module directly.
+//!
+//! The interpreter gives Cardinal IR the same meaning as the C backend, apart from narrow
+//! integer arithmetic, so that frontends can be tested without a C compiler, and so that the
+//! output of a backend can be checked against it.  Instruction values are evaluated once, in
+//! instruction order, each time their block runs, while the values that the conditions of
+//! loops are built from are evaluated again each time that the loop uses them.  Constants,
+//! named references and block parameters are read when they are used.
+//!
+//! Integer arithmetic wraps around to the type of its result.  This differs from the C
+//! backend for the narrow types `i8`, `u8`, `i16`, `u16` and `char`, whose arithmetic C
+//! promotes to `int` when it is displayed inline, so `200 + 100` on two `u8` values is 44
+//! here, but compares equal to 300 in C until the result is stored.  Programs that are
+//! checked against the C backend should store narrow results before using them.
+//!
+//! Values are typed, and every global, variable and string lives in an object in the
+//! interpreter's `Memory`, which pointers refer to.  Calls to functions that aren't defined in
+//! the module, such as `printf`, go to host functions, which are provided by the embedder.
+
+use crate::data::Constant;
+use crate::entities::{Block, BlockPath, BlockStep, NamedProperty, StringEncoding, Type, Value, ValueInfo};
+use crate::function::Function;
+use crate::instruction::{BlockType, InstBlock, InstructionInfo, Opcode};
+use crate::module::Module;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// The deepest that calls can be nested by default before the interpreter gives up, which
+/// keeps runaway recursion from overflowing the stack of the thread that runs it.
+const DEFAULT_CALL_LIMIT: usize = 48;
+
+/// A value that the interpreter works with, which is typed like the Cardinal type that it
+/// stands for.
+#[derive(Clone, PartialEq, Debug)]
+pub enum DataValue {
+
+    /// The absence of a value, returned by functions that return `void`.
+    Void,
+
+    /// A boolean.
+    Bool(bool),
+
+    /// A character, which is signed like `char` is on most targets.
+    Char(i8),
+
+    /// A signed 8 bit integer.
+    I8(i8),
+
+    /// A signed 16 bit integer.
+    I16(i16),
+
+    /// A signed 32 bit integer.
+    I32(i32),
+
+    /// A signed 64 bit integer.
+    I64(i64),
+
+    /// A signed integer with the size of a pointer, which is 64 bits.
+    ISize(i64),
+
+    /// An unsigned 8 bit integer.
+    U8(u8),
+
+    /// An unsigned 16 bit integer.
+    U16(u16),
+
+    /// An unsigned 32 bit integer.
+    U32(u32),
+
+    /// An unsigned 64 bit integer.
+    U64(u64),
+
+    /// An unsigned integer with the size of a pointer, which is 64 bits.
+    USize(u64),
+
+    /// A 32 bit floating point number.
+    Float(f32),
+
+    /// A 64 bit floating point number.
+    Double(f64),
+
+    /// A pointer into the interpreter's memory, or a null pointer.
+    Pointer(Option<Address>),
+
+    /// A function, by name, which is either defined in the module or by the host.  Function
+    /// pointers are also functions.
+    Function(String),
+
+    /// An array, with a value for each element.
+    Array(Vec<DataValue>),
+
+    /// A struct, by name, with a value for each field in declaration order.
+    Struct(String, Vec<DataValue>),
+
+}
+
+impl DataValue {
+
+    /// Creates an integer of the given type from `n`, wrapping it if it doesn't fit.  Zero can
+    /// also be a null pointer, and integers can be converted to floating point numbers.
+    pub fn from_int(ty: &Type, n: i128) -> Option<Self> {
+        let value = match ty {
+            Type::Bool => DataValue::Bool(n != 0),
+            Type::Char => DataValue::Char(n as i8),
+            Type::I8 => DataValue::I8(n as i8),
+            Type::I16 => DataValue::I16(n as i16),
+            Type::I32 => DataValue::I32(n as i32),
+            Type::I64 => DataValue::I64(n as i64),
+            Type::ISize => DataValue::ISize(n as i64),
+            Type::U8 => DataValue::U8(n as u8),
+            Type::U16 => DataValue::U16(n as u16),
+            Type::U32 => DataValue::U32(n as u32),
+            Type::U64 => DataValue::U64(n as u64),
+            Type::USize => DataValue::USize(n as u64),
+            Type::Float => DataValue::Float(n as f32),
+            Type::Double => DataValue::Double(n as f64),
+            Type::Pointer(_) if n == 0 => DataValue::Pointer(None),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Creates a floating point number of the given type.
+    pub fn from_float(ty: &Type, n: f64) -> Option<Self> {
+        match ty {
+            Type::Float => Some(DataValue::Float(n as f32)),
+            Type::Double => Some(DataValue::Double(n)),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an integer or character, if this is one.
+    pub fn as_int(&self) -> Option<i128> {
+        let n = match *self {
+            DataValue::Char(n) | DataValue::I8(n) => n as i128,
+            DataValue::I16(n) => n as i128,
+            DataValue::I32(n) => n as i128,
+            DataValue::I64(n) | DataValue::ISize(n) => n as i128,
+            DataValue::U8(n) => n as i128,
+            DataValue::U16(n) => n as i128,
+            DataValue::U32(n) => n as i128,
+            DataValue::U64(n) | DataValue::USize(n) => n as i128,
+            _ => return None,
+        };
+
+        Some(n)
+    }
+
+    /// Returns the value of a floating point number, if this is one.
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            DataValue::Float(n) => Some(n as f64),
+            DataValue::Double(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the value has the given type.  Any pointer has a pointer type, as the
+    /// interpreter doesn't track what pointers point to, and functions have both function and
+    /// function pointer types.
+    pub fn has_type(&self, ty: &Type) -> bool {
+        match (self, ty) {
+            (DataValue::Void, Type::Void) | (DataValue::Bool(_), Type::Bool)
+            | (DataValue::Char(_), Type::Char) | (DataValue::I8(_), Type::I8)
+            | (DataValue::I16(_), Type::I16) | (DataValue::I32(_), Type::I32)
+            | (DataValue::I64(_), Type::I64) | (DataValue::ISize(_), Type::ISize)
+            | (DataValue::U8(_), Type::U8) | (DataValue::U16(_), Type::U16)
+            | (DataValue::U32(_), Type::U32) | (DataValue::U64(_), Type::U64)
+            | (DataValue::USize(_), Type::USize) | (DataValue::Float(_), Type::Float)
+            | (DataValue::Double(_), Type::Double) | (DataValue::Function(_), Type::Function(_)) => true,
+            (DataValue::Pointer(_), Type::Pointer(_)) => true,
+            (DataValue::Function(_), Type::Pointer(to)) => matches!(**to, Type::Function(_)),
+            (DataValue::Array(items), Type::Array(of, len)) => {
+                len.is_none_or(|len| len == items.len()) && items.iter().all(|item| item.has_type(of))
+            },
+            (DataValue::Struct(name, _), Type::Struct(ty)) => name == ty,
+            _ => false,
+        }
+    }
+
+    /// Returns the zero value of a type, which globals and variables start out with.
+    fn zero(ty: &Type, module: &Module) -> Result<Self, InterpreterErrorKind> {
+        let value = match ty {
+            Type::Void => DataValue::Void,
+            Type::Array(of, len) => {
+                let item = DataValue::zero(of, module)?;
+                DataValue::Array(vec![item; len.unwrap_or(0)])
+            },
+            Type::Struct(name) => {
+                let fields = module.get_struct(name)
+                    .and_then(|def| def.fields.as_ref())
+                    .ok_or_else(|| InterpreterErrorKind::UnsizedType(ty.clone()))?;
+
+                let fields = fields.iter()
+                    .map(|field| DataValue::zero(&field.1, module))
+                    .collect::<Result<Vec<DataValue>, InterpreterErrorKind>>()?;
+
+                DataValue::Struct(name.to_string(), fields)
+            },
+            Type::Function(_) => return Err(InterpreterErrorKind::UnsizedType(ty.clone())),
+            ty => DataValue::from_int(ty, 0).unwrap_or(DataValue::Pointer(None)),
+        };
+
+        Ok(value)
+    }
+
+}
+
+/// The location of a value in memory, which is an object along with the indices of the
+/// elements and fields that lead to the value inside of it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Address {
+
+    /// The index of the object.
+    pub object: usize,
+
+    /// The indices of the array elements and struct fields inside of the object.
+    pub path: Vec<usize>,
+
+}
+
+impl Address {
+
+    /// Moves an address to another element of the array that it points into.  Only the
+    /// elements of arrays can be reached with pointer arithmetic.
+    pub fn offset(&self, n: i128) -> Result<Address, InterpreterErrorKind> {
+        let mut address = self.clone();
+
+        match address.path.last_mut() {
+            Some(index) => {
+                *index = usize::try_from(*index as i128 + n).map_err(|_| InterpreterErrorKind::InvalidPointer)?;
+            },
+            None if n != 0 => return Err(InterpreterErrorKind::InvalidPointer),
+            None => {},
+        }
+
+        Ok(address)
+    }
+
+    /// Returns how many elements apart two addresses in the same array are.
+    fn distance(&self, other: &Address) -> Result<i128, InterpreterErrorKind> {
+        match (self.path.split_last(), other.path.split_last()) {
+            (Some((a, parent)), Some((b, other_parent))) if self.object == other.object && parent == other_parent => {
+                Ok(*a as i128 - *b as i128)
+            },
+            _ => Err(InterpreterErrorKind::InvalidPointer),
+        }
+    }
+
+}
+
+/// The memory of an interpreter, which holds the objects that pointers point into.  Objects
+/// are freed when the function that they belong to returns, after which pointers to them are
+/// invalid.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+
+    /// The objects, or `None` for the ones that have been freed.
+    objects: Vec<Option<DataValue>>,
+
+}
+
+impl Memory {
+
+    /// Creates an empty memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates a new object holding the value, returning its address.
+    pub fn allocate(&mut self, value: DataValue) -> Address {
+        self.objects.push(Some(value));
+
+        Address {
+            object: self.objects.len() - 1,
+            path: vec![],
+        }
+    }
+
+    /// Frees an object, so that it can no longer be read or written.
+    fn free(&mut self, object: usize) {
+        if let Some(slot) = self.objects.get_mut(object) {
+            *slot = None;
+        }
+    }
+
+    /// Reads the value at an address.
+    pub fn read(&self, address: &Address) -> Result<&DataValue, InterpreterErrorKind> {
+        let mut value = self.objects.get(address.object)
+            .and_then(Option::as_ref)
+            .ok_or(InterpreterErrorKind::InvalidPointer)?;
+
+        for index in &address.path {
+            value = match value {
+                DataValue::Array(items) | DataValue::Struct(_, items) => items.get(*index),
+                _ => None,
+            }.ok_or(InterpreterErrorKind::InvalidPointer)?;
+        }
+
+        Ok(value)
+    }
+
+    /// Writes a value to an address, replacing the value that was there.
+    pub fn write(&mut self, address: &Address, value: DataValue) -> Result<(), InterpreterErrorKind> {
+        let mut slot = self.objects.get_mut(address.object)
+            .and_then(Option::as_mut)
+            .ok_or(InterpreterErrorKind::InvalidPointer)?;
+
+        for index in &address.path {
+            slot = match slot {
+                DataValue::Array(items) | DataValue::Struct(_, items) => items.get_mut(*index),
+                _ => None,
+            }.ok_or(InterpreterErrorKind::InvalidPointer)?;
+        }
+
+        *slot = value;
+        Ok(())
+    }
+
+    /// Allocates a string of characters followed by a null terminator, returning a pointer to
+    /// its first character.
+    pub fn allocate_string(&mut self, bytes: &[u8]) -> DataValue {
+        let chars = bytes.iter().map(|b| DataValue::Char(*b as i8)).collect();
+        self.allocate_array(chars, DataValue::Char(0))
+    }
+
+    /// Reads the characters of a null terminated string, up to but not including the null
+    /// terminator.
+    pub fn read_string(&self, pointer: &DataValue) -> Result<Vec<u8>, InterpreterErrorKind> {
+        let mut address = match pointer {
+            DataValue::Pointer(Some(address)) => address.clone(),
+            DataValue::Pointer(None) => return Err(InterpreterErrorKind::NullPointer),
+            _ => return Err(InterpreterErrorKind::InvalidPointer),
+        };
+
+        let mut bytes = vec![];
+
+        loop {
+            match self.read(&address)? {
+                DataValue::Char(0) => return Ok(bytes),
+                DataValue::Char(c) => bytes.push(*c as u8),
+                _ => return Err(InterpreterErrorKind::InvalidPointer),
+            }
+
+            address = address.offset(1)?;
+        }
+    }
+
+    /// Allocates a string in the given encoding, followed by a null terminator, returning a
+    /// pointer to its first code unit.
+    fn allocate_encoded(&mut self, s: &str, encoding: StringEncoding) -> DataValue {
+        let zero = DataValue::from_int(&encoding.char_type(), 0).unwrap();
+        self.allocate_array(string_units(s, encoding), zero)
+    }
+
+    /// Allocates an array of items followed by a terminator, returning a pointer to its first
+    /// item.
+    fn allocate_array(&mut self, mut items: Vec<DataValue>, terminator: DataValue) -> DataValue {
+        items.push(terminator);
+
+        let mut address = self.allocate(DataValue::Array(items));
+        address.path.push(0);
+
+        DataValue::Pointer(Some(address))
+    }
+
+}
+
+/// A function provided by the host, which the interpreter calls for functions that aren't
+/// defined in the module, such as `printf`.  It is given the interpreter's memory, so that it
+/// can follow pointers, along with the arguments of the call.
+pub type HostFunction<'a> = Box<dyn FnMut(&mut Memory, &[DataValue]) -> Result<DataValue, InterpreterErrorKind> + 'a>;
+
+/// The different kinds of problems that stop the interpreter.
+#[derive(Clone, PartialEq, Debug)]
+pub enum InterpreterErrorKind {
+
+    /// A call to a function that is neither defined in the module nor by the host.
+    UnknownFunction(String),
+
+    /// A named reference to something that isn't a variable, argument, global or function.
+    UndeclaredName(String),
+
+    /// A call with a different number of arguments than the function takes.
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+
+    /// A call with an argument that doesn't have the type of its parameter.
+    ArgumentType {
+        index: usize,
+        expected: Type,
+    },
+
+    /// A function that returned a value without its return type.
+    ReturnType(Type),
+
+    /// A function that doesn't return `void` and ended without returning a value.
+    MissingReturn,
+
+    /// A value that is not defined in the function, or a parameter of a block that wasn't
+    /// passed by the branch to it.
+    InvalidValue(Value),
+
+    /// An instruction whose arguments can't be used with its opcode.
+    InvalidOperands(Opcode),
+
+    /// A value that is assigned to, or decays to a pointer, without being a named reference.
+    NotAPlace(Value),
+
+    /// A named reference with a property that the interpreter can't follow, such as a static
+    /// property.
+    UnsupportedProperty(String),
+
+    /// A field that the struct doesn't have.
+    NoSuchField {
+        name: String,
+        field: String,
+    },
+
+    /// A constant that can't be given its type.
+    InvalidConstant(Type),
+
+    /// A type that values can't be stored with, such as an opaque struct.
+    UnsizedType(Type),
+
+    /// An integer division or remainder by zero.
+    DivisionByZero,
+
+    /// A shift by a negative amount, or by at least the width of the shifted integer.
+    InvalidShift(i128),
+
+    /// A null pointer that was followed.
+    NullPointer,
+
+    /// A pointer outside of the object that it points into, or into an object that has been
+    /// freed.
+    InvalidPointer,
+
+    /// An `unreachable` instruction that was reached.
+    Unreachable,
+
+    /// A `break` or `continue` outside of any loop.
+    OutsideOfLoop(Opcode),
+
+    /// More steps than the step limit of the interpreter.
+    StepLimit,
+
+    /// Calls nested more deeply than the call limit of the interpreter.
+    CallDepth,
+
+    /// An error reported by a host function.
+    Host(String),
+
+}
+
+/// An error that stopped the interpreter, along with where it happened.
+#[derive(Clone, PartialEq, Debug)]
+pub struct InterpreterError {
+
+    /// The name of the function that was running.
+    pub function: String,
+
+    /// The path to the block that was running, such as `block0/block1/else`, or an empty
+    /// string if the function never started.
+    pub block: String,
+
+    /// The kind of error.
+    pub kind: InterpreterErrorKind,
+
+}
+
+impl fmt::Display for InterpreterErrorKind {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterpreterErrorKind::UnknownFunction(name) => write!(f, "`{}` is not a defined or host function", name),
+            InterpreterErrorKind::UndeclaredName(name) => write!(f, "`{}` is not declared", name),
+            InterpreterErrorKind::ArgumentCount { expected, found } => {
+                write!(f, "expected {} arguments, found {}", expected, found)
+            },
+            InterpreterErrorKind::ArgumentType { index, expected } => {
+                write!(f, "argument {} is not a `{}`", index, expected)
+            },
+            InterpreterErrorKind::ReturnType(ty) => write!(f, "the returned value is not a `{}`", ty),
+            InterpreterErrorKind::MissingReturn => write!(f, "a non-void function ended without returning a value"),
+            InterpreterErrorKind::InvalidValue(v) => write!(f, "value v{} has no value here", v.0),
+            InterpreterErrorKind::InvalidOperands(opcode) => write!(f, "`{}` can't be used with these values", opcode.name()),
+            InterpreterErrorKind::NotAPlace(v) => write!(f, "value v{} is not a named reference", v.0),
+            InterpreterErrorKind::UnsupportedProperty(property) => write!(f, "the property `{}` is not supported", property),
+            InterpreterErrorKind::NoSuchField { name, field } => write!(f, "struct `{}` has no field `{}`", name, field),
+            InterpreterErrorKind::InvalidConstant(ty) => write!(f, "a constant can't have the type `{}`", ty),
+            InterpreterErrorKind::UnsizedType(ty) => write!(f, "values of the type `{}` can't be stored", ty),
+            InterpreterErrorKind::DivisionByZero => write!(f, "division by zero"),
+            InterpreterErrorKind::InvalidShift(n) => write!(f, "can't shift by {}", n),
+            InterpreterErrorKind::NullPointer => write!(f, "a null pointer was followed"),
+            InterpreterErrorKind::InvalidPointer => write!(f, "a pointer is outside of its object"),
+            InterpreterErrorKind::Unreachable => write!(f, "an `unreachable` instruction was reached"),
+            InterpreterErrorKind::OutsideOfLoop(opcode) => write!(f, "`{}` is not inside of a loop", opcode.name()),
+            InterpreterErrorKind::StepLimit => write!(f, "the step limit was reached"),
+            InterpreterErrorKind::CallDepth => write!(f, "calls are nested too deeply"),
+            InterpreterErrorKind::Host(message) => write!(f, "{}", message),
+        }
+    }
+
+}
+
+impl fmt::Display for InterpreterError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.block.is_empty() {
+            write!(f, "in function `{}`: {}", self.function, self.kind)
+        } else {
+            write!(f, "in function `{}`, {}: {}", self.function, self.block, self.kind)
+        }
+    }
+
+}
+
+impl Error for InterpreterError {}
+
+/// How control leaves a block.
+enum Flow {
+
+    /// The block ran to its end, so control moves on to what follows it.
+    Next,
+
+    /// A branch to a block of the function.
+    Jump(Block),
+
+    /// A return from the function.
+    Return(DataValue),
+
+    /// A `break` out of the innermost loop.
+    Break,
+
+    /// A `continue` to the next iteration of the innermost loop.
+    Continue,
+
+}
+
+/// A call to a function of the module that is running.
+struct Frame<'a> {
+
+    /// The function being run.
+    func: &'a Function,
+
+    /// The objects holding the arguments and variables of the function.
+    locals: HashMap<&'a str, usize>,
+
+    /// The values that have been evaluated in the blocks that are running.
+    values: Vec<Option<DataValue>>,
+
+    /// Whether each value is evaluated each time that it is used, as the conditions of loops
+    /// are built from it.
+    inline: Vec<bool>,
+
+    /// The values passed to the parameters of each block of the function.
+    params: Vec<Vec<DataValue>>,
+
+    /// The block that is running.
+    location: BlockPath,
+
+}
+
+impl<'a> Frame<'a> {
+
+    /// Creates an error that happened in the block that is running.
+    fn error(&self, kind: InterpreterErrorKind) -> InterpreterError {
+        InterpreterError {
+            function: self.func.name.to_string(),
+            block: self.location.to_string(),
+            kind,
+        }
+    }
+
+}
+
+/// Runs the functions of a module.
+pub struct Interpreter<'a> {
+
+    /// The module being run.
+    module: &'a Module,
+
+    /// The memory that globals, variables and strings are stored in.
+    pub memory: Memory,
+
+    /// The most instructions that a call may run, including the loop tests, before it is
+    /// stopped.  There is no limit when it is `None`.
+    pub step_limit: Option<u64>,
+
+    /// The deepest that calls may be nested.  Each call uses the stack of the thread running
+    /// the interpreter, so a higher limit may need a thread with a larger stack.
+    pub call_limit: usize,
+
+    /// The number of steps run by the current call.
+    steps: u64,
+
+    /// The number of calls that are running.
+    depth: usize,
+
+    /// The objects holding the globals that have been used.
+    globals: HashMap<String, usize>,
+
+    /// The strings that have been allocated for string constants, by function and value.
+    strings: HashMap<(String, u32), DataValue>,
+
+    /// The functions provided by the host.
+    hosts: HashMap<String, HostFunction<'a>>,
+
+}
+
+impl<'a> Interpreter<'a> {
+
+    /// Creates an interpreter for the given module.  Globals are created with their
+    /// initializers the first time that they are used.
+    pub fn new(module: &'a Module) -> Self {
+        Self {
+            module,
+            memory: Memory::new(),
+            step_limit: None,
+            call_limit: DEFAULT_CALL_LIMIT,
+            steps: 0,
+            depth: 0,
+            globals: HashMap::new(),
+            strings: HashMap::new(),
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Provides a function from the host, which is called for calls to the name that aren't
+    /// to a function defined in the module.
+    pub fn define_host<F>(&mut self, name: &str, func: F)
+    where
+        F: FnMut(&mut Memory, &[DataValue]) -> Result<DataValue, InterpreterErrorKind> + 'a,
+    {
+        self.hosts.insert(name.to_string(), Box::new(func));
+    }
+
+    /// Calls a function of the module, or of the host, with the given arguments, returning
+    /// the value that it returns.
+    pub fn call(&mut self, name: &str, args: &[DataValue]) -> Result<DataValue, InterpreterError> {
+        self.steps = 0;
+        self.call_function(name, args.to_vec(), None)
+    }
+
+    /// Returns the value of a global, creating it if it hasn't been used yet.
+    pub fn global(&mut self, name: &str) -> Result<DataValue, InterpreterError> {
+        let error = |kind| InterpreterError {
+            function: String::new(),
+            block: String::new(),
+            kind,
+        };
+
+        let address = self.global_address(name).map_err(error)?
+            .ok_or_else(|| error(InterpreterErrorKind::UndeclaredName(name.to_string())))?;
+
+        self.memory.read(&address).cloned().map_err(error)
+    }
+
+    /// Calls a function, checking the value returned by a host function against the return
+    /// type of the call if it is known.
+    fn call_function(&mut self, name: &str, args: Vec<DataValue>, returns: Option<&Type>) -> Result<DataValue, InterpreterError> {
+        let error = |kind| InterpreterError {
+            function: name.to_string(),
+            block: String::new(),
+            kind,
+        };
+
+        if self.depth >= self.call_limit {
+            return Err(error(InterpreterErrorKind::CallDepth));
+        }
+
+        if let Some(func) = self.module.functions.get(name).filter(|func| !func.blocks.is_empty()) {
+            self.depth += 1;
+            let result = self.run_function(func, args);
+            self.depth -= 1;
+
+            return result;
+        }
+
+        let host = self.hosts.get_mut(name)
+            .ok_or_else(|| error(InterpreterErrorKind::UnknownFunction(name.to_string())))?;
+
+        let result = host(&mut self.memory, &args).map_err(error)?;
+
+        match returns {
+            Some(ty) if !result.has_type(ty) => Err(error(InterpreterErrorKind::ReturnType(ty.clone()))),
+            _ => Ok(result),
+        }
+    }
+
+    /// Runs a function of the module from its first block.
+    fn run_function(&mut self, func: &'a Function, args: Vec<DataValue>) -> Result<DataValue, InterpreterError> {
+        let mut frame = Frame {
+            func,
+            locals: HashMap::new(),
+            values: vec![None; func.values.len()],
+            inline: func.loop_values(),
+            params: vec![vec![]; func.blocks.len()],
+            location: BlockPath::new(Block(0)),
+        };
+
+        let error = |kind| InterpreterError {
+            function: func.name.to_string(),
+            block: String::new(),
+            kind,
+        };
+
+        let params = &func.signature.arguments;
+
+        if args.len() != params.len() {
+            return Err(error(InterpreterErrorKind::ArgumentCount {
+                expected: params.len(),
+                found: args.len(),
+            }));
+        }
+
+        for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+            if !arg.has_type(&param.1) {
+                return Err(error(InterpreterErrorKind::ArgumentType {
+                    index: i,
+                    expected: param.1.clone(),
+                }));
+            }
+
+            frame.locals.insert(&param.0, self.memory.allocate(arg).object);
+        }
+
+        // Variables shadow the arguments of the function.
+        for (name, ty) in &func.variables {
+            let value = DataValue::zero(ty, self.module).map_err(error)?;
+            frame.locals.insert(name, self.memory.allocate(value).object);
+        }
+
+        let result = self.run_blocks(&mut frame);
+
+        for object in frame.locals.values() {
+            self.memory.free(*object);
+        }
+
+        let value = result?;
+
+        if !value.has_type(&func.signature.returns) {
+            return Err(frame.error(InterpreterErrorKind::ReturnType(func.signature.returns.clone())));
+        }
+
+        Ok(value)
+    }
+
+    /// Runs the blocks of a function, following the branches between them, until the
+    /// function returns.  A block that doesn't end with a terminator falls through to the
+    /// block after it.
+    fn run_blocks(&mut self, frame: &mut Frame<'a>) -> Result<DataValue, InterpreterError> {
+        let func = frame.func;
+        let mut current = 0;
+
+        while let Some(block) = func.blocks.get(current) {
+            frame.location = BlockPath::new(Block(current as u32));
+
+            match self.run_block(frame, block)? {
+                Flow::Next => current += 1,
+                Flow::Jump(b) => current = b.0 as usize,
+                Flow::Return(value) => return Ok(value),
+                Flow::Break => return Err(frame.error(InterpreterErrorKind::OutsideOfLoop(Opcode::Break))),
+                Flow::Continue => return Err(frame.error(InterpreterErrorKind::OutsideOfLoop(Opcode::Continue))),
+            }
+        }
+
+        if func.signature.returns == Type::Void {
+            Ok(DataValue::Void)
+        } else {
+            Err(frame.error(InterpreterErrorKind::MissingReturn))
+        }
+    }
+
+    /// Runs the instructions of a block in order, evaluating its values at their positions.
+    /// Nested blocks run where they are entered, or after the rest of the block if they are
+    /// never entered.
+    fn run_block(&mut self, frame: &mut Frame<'a>, block: &'a InstBlock) -> Result<Flow, InterpreterError> {
+        for v in &block.values {
+            frame.values[v.0 as usize] = None;
+        }
+
+        let mut entered = vec![false; block.blocks.len()];
+        let mut next = 0;
+
+        for (n, inst) in block.insts.iter().enumerate() {
+            self.evaluate_until(frame, block, &mut next, n)?;
+            self.step(frame)?;
+
+            let flow = match inst.opcode {
+                Opcode::Enter => {
+                    let target = self.argument(frame, inst, 0)?;
+
+                    match frame.func.values.get(target) {
+                        Some(ValueInfo::Block(b)) if (b.0 as usize) < block.blocks.len() => {
+                            entered[b.0 as usize] = true;
+                            self.run_nested(frame, block, b.0 as usize)?
+                        },
+                        _ => return Err(frame.error(InterpreterErrorKind::InvalidOperands(Opcode::Enter))),
+                    }
+                },
+                _ => self.execute(frame, inst)?,
+            };
+
+            if let Flow::Next = flow {
+                continue;
+            }
+
+            return Ok(flow);
+        }
+
+        self.evaluate_until(frame, block, &mut next, usize::MAX)?;
+
+        for (i, entered) in entered.into_iter().enumerate() {
+            if !entered {
+                match self.run_nested(frame, block, i)? {
+                    Flow::Next => {},
+                    flow => return Ok(flow),
+                }
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /// Evaluates the instruction values of a block up to the instruction at `position`,
+    /// starting from the value at `next`.
+    fn evaluate_until(&mut self, frame: &mut Frame<'a>, block: &'a InstBlock, next: &mut usize, position: usize) -> Result<(), InterpreterError> {
+        while let (Some(v), Some(p)) = (block.values.get(*next), block.positions.get(*next)) {
+            if *p > position {
+                break;
+            }
+
+            *next += 1;
+
+            if let Some(ValueInfo::Instruction(_)) = frame.func.values.get(*v) {
+                if !frame.inline[v.0 as usize] {
+                    self.step(frame)?;
+                    self.evaluate(frame, *v)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs a nested block of `parent`, along with its `elif` and `else` branches or its loop.
+    /// Conditions are evaluated in the parent block.
+    fn run_nested(&mut self, frame: &mut Frame<'a>, parent: &'a InstBlock, index: usize) -> Result<Flow, InterpreterError> {
+        let nested = &parent.blocks[index];
+        let location = frame.location.clone();
+        let path = location.join(BlockStep::Nested(Block(index as u32)));
+
+        let flow = match nested.block_type {
+            BlockType::Basic => self.run_branch(frame, nested, path)?,
+            BlockType::If(cond) => {
+                let mut taken = None;
+
+                if self.condition(frame, cond)? {
+                    taken = Some((nested, path.clone()));
+                } else {
+                    for (i, elif) in nested.elses.iter().enumerate() {
+                        let cond = match elif.block_type {
+                            BlockType::If(cond) => self.condition(frame, cond)?,
+                            _ => true,
+                        };
+
+                        if cond {
+                            taken = Some((elif, path.join(BlockStep::Elif(i))));
+                            break;
+                        }
+                    }
+
+                    if taken.is_none() {
+                        taken = nested.else_block.as_deref().map(|b| (b, path.join(BlockStep::Else)));
+                    }
+                }
+
+                match taken {
+                    Some((branch, path)) => self.run_branch(frame, branch, path)?,
+                    None => Flow::Next,
+                }
+            },
+            BlockType::While(cond) => {
+                while self.condition(frame, cond)? {
+                    match self.run_branch(frame, nested, path.clone())? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {},
+                        flow => return Ok(flow),
+                    }
+                }
+
+                Flow::Next
+            },
+            BlockType::DoWhile(cond) => {
+                loop {
+                    match self.run_branch(frame, nested, path.clone())? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {},
+                        flow => return Ok(flow),
+                    }
+
+                    if !self.condition(frame, cond)? {
+                        break;
+                    }
+                }
+
+                Flow::Next
+            },
+            BlockType::For { init, cond, step } => {
+                if let Some(init) = init {
+                    self.evaluate(frame, init)?;
+                }
+
+                while self.condition(frame, cond)? {
+                    match self.run_branch(frame, nested, path.clone())? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {},
+                        flow => return Ok(flow),
+                    }
+
+                    if let Some(step) = step {
+                        self.evaluate(frame, step)?;
+                    }
+                }
+
+                Flow::Next
+            },
+        };
+
+        Ok(flow)
+    }
+
+    /// Runs a nested block or one of its branches at `path`, then returns to the location of
+    /// the parent block.
+    fn run_branch(&mut self, frame: &mut Frame<'a>, block: &'a InstBlock, path: BlockPath) -> Result<Flow, InterpreterError> {
+        let location = std::mem::replace(&mut frame.location, path);
+        let flow = self.run_block(frame, block);
+        frame.location = location;
+
+        flow
+    }
+
+    /// Evaluates the condition of a block, which counts as a step.
+    fn condition(&mut self, frame: &mut Frame<'a>, cond: Value) -> Result<bool, InterpreterError> {
+        self.step(frame)?;
+
+        match self.evaluate(frame, cond)? {
+            DataValue::Bool(b) => Ok(b),
+            _ => Err(frame.error(InterpreterErrorKind::InvalidValue(cond))),
+        }
+    }
+
+    /// Counts a step, stopping the call if it is over the step limit.
+    fn step(&mut self, frame: &Frame<'a>) -> Result<(), InterpreterError> {
+        self.steps += 1;
+
+        match self.step_limit {
+            Some(limit) if self.steps > limit => Err(frame.error(InterpreterErrorKind::StepLimit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs an instruction that isn't a value, returning where control goes next.
+    fn execute(&mut self, frame: &mut Frame<'a>, inst: &'a InstructionInfo) -> Result<Flow, InterpreterError> {
+        let flow = match inst.opcode {
+            Opcode::Ret => match inst.arguments.first() {
+                Some(v) => Flow::Return(self.evaluate(frame, *v)?),
+                None => Flow::Return(DataValue::Void),
+            },
+            Opcode::Jmp => self.jump(frame, self.argument(frame, inst, 0)?)?,
+            Opcode::Brif => {
+                let cond = self.argument(frame, inst, 0)?;
+
+                if self.condition(frame, cond)? {
+                    self.jump(frame, self.argument(frame, inst, 1)?)?
+                } else {
+                    self.jump(frame, self.argument(frame, inst, 2)?)?
+                }
+            },
+            Opcode::Switch => {
+                let v = self.argument(frame, inst, 0)?;
+                let n = self.evaluate(frame, v)?.as_int()
+                    .ok_or_else(|| frame.error(InterpreterErrorKind::InvalidOperands(Opcode::Switch)))?;
+
+                let mut target = self.argument(frame, inst, 1)?;
+
+                for case in inst.arguments[2..].chunks(2) {
+                    if let [value, block] = *case {
+                        if self.evaluate(frame, value)?.as_int() == Some(n) {
+                            target = block;
+                            break;
+                        }
+                    }
+                }
+
+                self.jump(frame, target)?
+            },
+            Opcode::Break => Flow::Break,
+            Opcode::Continue => Flow::Continue,
+            Opcode::Unreachable => return Err(frame.error(InterpreterErrorKind::Unreachable)),
+            _ => {
+                self.compute(frame, inst)?;
+                Flow::Next
+            },
+        };
+
+        Ok(flow)
+    }
+
+    /// Branches to a block of the function, passing the values of a block call to its
+    /// parameters.
+    fn jump(&mut self, frame: &mut Frame<'a>, target: Value) -> Result<Flow, InterpreterError> {
+        let (block, args) = frame.func.values.get(target)
+            .and_then(ValueInfo::branch_target)
+            .ok_or_else(|| frame.error(InterpreterErrorKind::InvalidValue(target)))?;
+
+        // Every argument is evaluated before any parameter is assigned, as a block may pass
+        // its own parameters to itself in a different order.
+        let mut values = vec![];
+
+        for arg in args {
+            values.push(self.evaluate(frame, *arg)?);
+        }
+
+        match frame.params.get_mut(block.0 as usize) {
+            Some(params) => *params = values,
+            None => return Err(frame.error(InterpreterErrorKind::InvalidValue(target))),
+        }
+
+        Ok(Flow::Jump(block))
+    }
+
+    /// Returns an argument of an instruction.
+    fn argument(&self, frame: &Frame<'a>, inst: &InstructionInfo, i: usize) -> Result<Value, InterpreterError> {
+        inst.arguments.get(i).copied()
+            .ok_or_else(|| frame.error(InterpreterErrorKind::InvalidOperands(inst.opcode)))
+    }
+
+    /// Evaluates a value of the function.  Instruction values are only evaluated once each
+    /// time that their block runs, unless the conditions of loops are built from them.
+    fn evaluate(&mut self, frame: &mut Frame<'a>, v: Value) -> Result<DataValue, InterpreterError> {
+        let func = frame.func;
+        let i = v.0 as usize;
+
+        let (info, ty) = match (func.values.get(v), func.values.ty(v)) {
+            (Some(info), Some(ty)) => (info, ty),
+            _ => return Err(frame.error(InterpreterErrorKind::InvalidValue(v))),
+        };
+
+        let invalid_constant = || frame.error(InterpreterErrorKind::InvalidConstant(ty.clone()));
+
+        let value = match info {
+            ValueInfo::IntegerConstant(n) => DataValue::from_int(ty, *n as i128).ok_or_else(invalid_constant)?,
+            ValueInfo::FloatConstant(n) => DataValue::Float(*n as f32),
+            ValueInfo::DoubleConstant(n) => DataValue::Double(*n),
+            ValueInfo::BooleanConstant(b) => DataValue::Bool(*b),
+            ValueInfo::CharConstant(s) => DataValue::Char(s.bytes().last().unwrap_or(0) as i8),
+            ValueInfo::StringConstant(s, encoding) => {
+                let memory = &mut self.memory;
+
+                self.strings.entry((func.name.to_string(), v.0))
+                    .or_insert_with(|| memory.allocate_encoded(s, *encoding))
+                    .clone()
+            },
+            ValueInfo::BytesConstant(bytes) => {
+                let memory = &mut self.memory;
+
+                self.strings.entry((func.name.to_string(), v.0))
+                    .or_insert_with(|| memory.allocate_string(bytes))
+                    .clone()
+            },
+            ValueInfo::Named(named) => {
+                let local = frame.locals.contains_key(named.name.as_str());
+                let global = !local && self.module.data.contains_key(&named.name);
+
+                if !local && !global && named.properties.is_empty() {
+                    DataValue::Function(named.name.to_string())
+                } else {
+                    let address = self.place(frame, v)?;
+                    self.memory.read(&address).cloned().map_err(|kind| frame.error(kind))?
+                }
+            },
+            ValueInfo::Param(n) => {
+                let block = func.values.block(v).map_or(0, |path| path.block.0 as usize);
+
+                frame.params.get(block)
+                    .and_then(|params| params.get(*n as usize))
+                    .cloned()
+                    .ok_or_else(|| frame.error(InterpreterErrorKind::InvalidValue(v)))?
+            },
+            ValueInfo::Block(_) | ValueInfo::BlockCall(_) => DataValue::Void,
+            ValueInfo::Instruction(inst) => {
+                if let Some(value) = &frame.values[i] {
+                    return Ok(value.clone());
+                }
+
+                let value = self.compute(frame, inst)?;
+
+                if !frame.inline[i] {
+                    frame.values[i] = Some(value.clone());
+                }
+
+                value
+            },
+        };
+
+        Ok(value)
+    }
+
+    /// Computes the result of an instruction.
+    fn compute(&mut self, frame: &mut Frame<'a>, inst: &'a InstructionInfo) -> Result<DataValue, InterpreterError> {
+        let func = frame.func;
+
+        match inst.opcode {
+            Opcode::Set => {
+                let target = self.argument(frame, inst, 0)?;
+                let address = self.place(frame, target)?;
+                let value = self.evaluate(frame, self.argument(frame, inst, 1)?)?;
+
+                self.memory.write(&address, value.clone()).map_err(|kind| frame.error(kind))?;
+                Ok(value)
+            },
+            Opcode::Call => {
+                let callee = self.argument(frame, inst, 0)?;
+
+                let returns = match func.values.ty(callee) {
+                    Some(Type::Function(ty)) => Some(&*ty.returns),
+                    Some(Type::Pointer(to)) => match &**to {
+                        Type::Function(ty) => Some(&*ty.returns),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+
+                let name = match self.evaluate(frame, callee)? {
+                    DataValue::Function(name) => name,
+                    DataValue::Pointer(None) => return Err(frame.error(InterpreterErrorKind::NullPointer)),
+                    _ => return Err(frame.error(InterpreterErrorKind::InvalidOperands(Opcode::Call))),
+                };
+
+                let mut args = vec![];
+
+                for arg in &inst.arguments[1..] {
+                    // Arrays decay to pointers to their first element when they are passed to a
+                    // function.
+                    if let Some(Type::Array(..)) = func.values.ty(*arg) {
+                        let mut address = self.place(frame, *arg)?;
+                        address.path.push(0);
+                        args.push(DataValue::Pointer(Some(address)));
+                    } else {
+                        args.push(self.evaluate(frame, *arg)?);
+                    }
+                }
+
+                self.call_function(&name, args, returns)
+            },
+            Opcode::Ret | Opcode::Jmp | Opcode::Brif | Opcode::Switch | Opcode::Break
+            | Opcode::Continue | Opcode::Enter | Opcode::Unreachable => {
+                Err(frame.error(InterpreterErrorKind::InvalidOperands(inst.opcode)))
+            },
+            opcode => {
+                let mut args = vec![];
+                let mut types = vec![];
+
+                for arg in &inst.arguments {
+                    args.push(self.evaluate(frame, *arg)?);
+                    types.push(func.values.ty(*arg).cloned().unwrap_or(Type::Void));
+                }
+
+                let ty = opcode.result_type(&types)
+                    .map_err(|_| frame.error(InterpreterErrorKind::InvalidOperands(opcode)))?;
+
+                operate(opcode, &args, &ty).map_err(|kind| frame.error(kind))
+            },
+        }
+    }
+
+    /// Returns the address that a named reference refers to, following its properties.
+    fn place(&mut self, frame: &mut Frame<'a>, v: Value) -> Result<Address, InterpreterError> {
+        let named = match frame.func.values.get(v) {
+            Some(ValueInfo::Named(named)) => named,
+            _ => return Err(frame.error(InterpreterErrorKind::NotAPlace(v))),
+        };
+
+        let mut address = match frame.locals.get(named.name.as_str()) {
+            Some(object) => Address { object: *object, path: vec![] },
+            None => self.global_address(&named.name)
+                .map_err(|kind| frame.error(kind))?
+                .ok_or_else(|| frame.error(InterpreterErrorKind::UndeclaredName(named.name.to_string())))?,
+        };
+
+        for prop in &named.properties {
+            match prop {
+                NamedProperty::Basic(field) => {
+                    let index = self.field(&address, field).map_err(|kind| frame.error(kind))?;
+                    address.path.push(index);
+                },
+                NamedProperty::Pointer(field) => {
+                    address = self.follow(&address, 0).map_err(|kind| frame.error(kind))?;
+                    let index = self.field(&address, field).map_err(|kind| frame.error(kind))?;
+                    address.path.push(index);
+                },
+                NamedProperty::Index(index) => {
+                    let n = self.evaluate(frame, *index)?.as_int()
+                        .ok_or_else(|| frame.error(InterpreterErrorKind::InvalidValue(*index)))?;
+
+                    address = match self.memory.read(&address).map_err(|kind| frame.error(kind))? {
+                        DataValue::Array(_) => {
+                            let n = usize::try_from(n).map_err(|_| frame.error(InterpreterErrorKind::InvalidPointer))?;
+                            address.path.push(n);
+                            address
+                        },
+                        _ => self.follow(&address, n).map_err(|kind| frame.error(kind))?,
+                    };
+                },
+                NamedProperty::Static(field) => {
+                    return Err(frame.error(InterpreterErrorKind::UnsupportedProperty(format!("::{}", field))));
+                },
+            }
+        }
+
+        Ok(address)
+    }
+
+    /// Follows the pointer at an address, moving it `n` elements along first.
+    fn follow(&self, address: &Address, n: i128) -> Result<Address, InterpreterErrorKind> {
+        match self.memory.read(address)? {
+            DataValue::Pointer(Some(to)) => to.offset(n),
+            DataValue::Pointer(None) => Err(InterpreterErrorKind::NullPointer),
+            _ => Err(InterpreterErrorKind::InvalidPointer),
+        }
+    }
+
+    /// Returns the index of a field of the struct at an address.
+    fn field(&self, address: &Address, field: &str) -> Result<usize, InterpreterErrorKind> {
+        let name = match self.memory.read(address)? {
+            DataValue::Struct(name, _) => name,
+            _ => return Err(InterpreterErrorKind::InvalidPointer),
+        };
+
+        self.module.get_struct(name)
+            .and_then(|def| def.fields.as_ref())
+            .and_then(|fields| fields.iter().position(|f| f.0 == field))
+            .ok_or_else(|| InterpreterErrorKind::NoSuchField {
+                name: name.to_string(),
+                field: field.to_string(),
+            })
+    }
+
+    /// Returns the address of a global, creating it from its initializer the first time that
+    /// it is used, or `None` if there is no global with the name.
+    fn global_address(&mut self, name: &str) -> Result<Option<Address>, InterpreterErrorKind> {
+        if let Some(object) = self.globals.get(name) {
+            return Ok(Some(Address { object: *object, path: vec![] }));
+        }
+
+        let global = match self.module.data.get(name) {
+            Some(global) => global,
+            None => return Ok(None),
+        };
+
+        let value = match &global.init {
+            Some(init) => self.constant(init, &global.ty)?,
+            None => DataValue::zero(&global.ty, self.module)?,
+        };
+
+        let address = self.memory.allocate(value);
+        self.globals.insert(name.to_string(), address.object);
+
+        Ok(Some(address))
+    }
+
+    /// Creates the value of a constant initializer with the given type.  Strings initialize
+    /// pointers by allocating the string, or arrays of characters by copying it.
+    fn constant(&mut self, constant: &Constant, ty: &Type) -> Result<DataValue, InterpreterErrorKind> {
+        let invalid = || InterpreterErrorKind::InvalidConstant(ty.clone());
+
+        let value = match (constant, ty) {
+            (Constant::Integer(n), ty) => DataValue::from_int(ty, *n as i128).ok_or_else(invalid)?,
+            (Constant::Float(n), ty) | (Constant::Double(n), ty) => DataValue::from_float(ty, *n).ok_or_else(invalid)?,
+            (Constant::Boolean(b), Type::Bool) => DataValue::Bool(*b),
+            (Constant::Char(s), Type::Char) => DataValue::Char(s.bytes().last().unwrap_or(0) as i8),
+            (Constant::String(s, encoding), Type::Pointer(_)) => self.memory.allocate_encoded(s, *encoding),
+            (Constant::Bytes(bytes), Type::Pointer(_)) => self.memory.allocate_string(bytes),
+            (Constant::String(s, encoding), Type::Array(of, len)) => {
+                let mut units = string_units(s, *encoding);
+                units.push(DataValue::zero(of, self.module)?);
+
+                fit(units, of, *len, self.module)?
+            },
+            (Constant::Bytes(bytes), Type::Array(of, len)) => {
+                let mut units: Vec<DataValue> = bytes.iter().map(|b| DataValue::Char(*b as i8)).collect();
+                units.push(DataValue::zero(of, self.module)?);
+
+                fit(units, of, *len, self.module)?
+            },
+            (Constant::Array(items), Type::Array(of, len)) => {
+                let items = items.iter()
+                    .map(|item| self.constant(item, of))
+                    .collect::<Result<Vec<DataValue>, InterpreterErrorKind>>()?;
+
+                fit(items, of, *len, self.module)?
+            },
+            (Constant::Struct(fields), Type::Struct(name)) => {
+                let mut value = DataValue::zero(ty, self.module)?;
+                let defs = self.module.get_struct(name).and_then(|def| def.fields.as_ref()).ok_or_else(invalid)?;
+
+                for (field, constant) in fields {
+                    let index = defs.iter().position(|f| f.0 == *field).ok_or_else(|| InterpreterErrorKind::NoSuchField {
+                        name: name.to_string(),
+                        field: field.to_string(),
+                    })?;
+
+                    let item = self.constant(constant, &defs[index].1)?;
+
+                    if let DataValue::Struct(_, items) = &mut value {
+                        items[index] = item;
+                    }
+                }
+
+                value
+            },
+            _ => return Err(invalid()),
+        };
+
+        Ok(value)
+    }
+
+}
+
+/// Returns the code units of a string in the given encoding, without a null terminator.
+fn string_units(s: &str, encoding: StringEncoding) -> Vec<DataValue> {
+    match encoding {
+        StringEncoding::Narrow | StringEncoding::Utf8 => s.bytes().map(|b| DataValue::Char(b as i8)).collect(),
+        StringEncoding::Utf16 => s.encode_utf16().map(DataValue::U16).collect(),
+        StringEncoding::Utf32 => s.chars().map(|c| DataValue::U32(c as u32)).collect(),
+        StringEncoding::Wide => s.chars().map(|c| DataValue::I32(c as i32)).collect(),
+    }
+}
+
+/// Fits the items of an array initializer to the length of the array, filling the rest of
+/// it with zeroes.
+fn fit(mut items: Vec<DataValue>, of: &Type, len: Option<usize>, module: &Module) -> Result<DataValue, InterpreterErrorKind> {
+    if let Some(len) = len {
+        if items.len() > len {
+            return Err(InterpreterErrorKind::InvalidConstant(Type::array(of.clone(), Some(len))));
+        }
+
+        items.resize(len, DataValue::zero(of, module)?);
+    }
+
+    Ok(DataValue::Array(items))
+}
+
+/// Applies an arithmetic, bitwise, comparison or logical opcode to its arguments, giving a
+/// result of the type `ty`.  Integers wrap around when they overflow.
+pub(crate) fn operate(opcode: Opcode, args: &[DataValue], ty: &Type) -> Result<DataValue, InterpreterErrorKind> {
+    let invalid = || InterpreterErrorKind::InvalidOperands(opcode);
+
+    // Pointer arithmetic moves pointers along the arrays that they point into.
+    match (opcode, args) {
+        (Opcode::Add, [DataValue::Pointer(p), n]) | (Opcode::Add, [n, DataValue::Pointer(p)]) if n.as_int().is_some() => {
+            let p = p.as_ref().ok_or(InterpreterErrorKind::NullPointer)?;
+            return Ok(DataValue::Pointer(Some(p.offset(n.as_int().unwrap())?)));
+        },
+        (Opcode::Sub, [DataValue::Pointer(p), n]) if n.as_int().is_some() => {
+            let p = p.as_ref().ok_or(InterpreterErrorKind::NullPointer)?;
+            return Ok(DataValue::Pointer(Some(p.offset(-n.as_int().unwrap())?)));
+        },
+        (Opcode::Sub, [DataValue::Pointer(Some(a)), DataValue::Pointer(Some(b))]) => {
+            return Ok(DataValue::ISize(a.distance(b)? as i64));
+        },
+        (Opcode::TestEq, [DataValue::Pointer(a), DataValue::Pointer(b)]) => return Ok(DataValue::Bool(a == b)),
+        (Opcode::TestNeq, [DataValue::Pointer(a), DataValue::Pointer(b)]) => return Ok(DataValue::Bool(a != b)),
+        (_, [DataValue::Pointer(Some(a)), DataValue::Pointer(Some(b))]) => {
+            return compare(opcode, a.distance(b)?.cmp(&0)).map(DataValue::Bool).ok_or_else(invalid);
+        },
+        (Opcode::TestEq, [DataValue::Bool(a), DataValue::Bool(b)]) => return Ok(DataValue::Bool(a == b)),
+        (Opcode::TestNeq, [DataValue::Bool(a), DataValue::Bool(b)]) => return Ok(DataValue::Bool(a != b)),
+        (Opcode::Not, [DataValue::Bool(b)]) => return Ok(DataValue::Bool(!b)),
+        (Opcode::And, [DataValue::Bool(a), DataValue::Bool(b)]) => return Ok(DataValue::Bool(*a && *b)),
+        (Opcode::Or, [DataValue::Bool(a), DataValue::Bool(b)]) => return Ok(DataValue::Bool(*a || *b)),
+        _ => {},
+    }
+
+    if let [l, r] = args {
+        if let (Some(l), Some(r)) = (l.as_float(), r.as_float()) {
+            let n = match opcode {
+                Opcode::Add => l + r,
+                Opcode::Sub => l - r,
+                Opcode::Mul => l * r,
+                Opcode::Div => l / r,
+                _ => {
+                    let ordering = l.partial_cmp(&r);
+
+                    return match ordering {
+                        Some(ordering) => compare(opcode, ordering),
+                        None => match opcode {
+                            Opcode::TestNeq => Some(true),
+                            _ => compare(opcode, std::cmp::Ordering::Less).map(|_| false),
+                        },
+                    }.map(DataValue::Bool).ok_or_else(invalid);
+                },
+            };
+
+            return DataValue::from_float(ty, n).ok_or_else(invalid);
+        }
+    }
+
+    let ints: Option<Vec<i128>> = args.iter().map(DataValue::as_int).collect();
+
+    let n = match (opcode, ints.as_deref().ok_or_else(invalid)?) {
+        (Opcode::Add, [l, r]) => l + r,
+        (Opcode::Sub, [l, r]) => l - r,
+        (Opcode::Mul, [l, r]) => l.wrapping_mul(*r),
+        (Opcode::Div, [_, 0]) | (Opcode::Mod, [_, 0]) => return Err(InterpreterErrorKind::DivisionByZero),
+        (Opcode::Div, [l, r]) => l / r,
+        (Opcode::Mod, [l, r]) => l % r,
+        (Opcode::BitAnd, [l, r]) => l & r,
+        (Opcode::BitOr, [l, r]) => l | r,
+        (Opcode::BitXor, [l, r]) => l ^ r,
+        (Opcode::BitLeft, [l, r]) | (Opcode::BitRight, [l, r]) => {
+            if *r < 0 || *r >= bits(ty) {
+                return Err(InterpreterErrorKind::InvalidShift(*r));
+            }
+
+            if opcode == Opcode::BitLeft {
+                l << r
+            } else {
+                l >> r
+            }
+        },
+        (Opcode::BitNot, [n]) => !n,
+        (_, [l, r]) => return compare(opcode, l.cmp(r)).map(DataValue::Bool).ok_or_else(invalid),
+        _ => return Err(invalid()),
+    };
+
+    DataValue::from_int(ty, n).ok_or_else(invalid)
+}
+
+/// Returns the result of a comparison opcode, given how its left operand compares to its
+/// right operand.
+fn compare(opcode: Opcode, ordering: std::cmp::Ordering) -> Option<bool> {
+    use std::cmp::Ordering::*;
+
+    let result = match opcode {
+        Opcode::TestEq => ordering == Equal,
+        Opcode::TestNeq => ordering != Equal,
+        Opcode::TestGt => ordering == Greater,
+        Opcode::TestGtEq => ordering != Less,
+        Opcode::TestLt => ordering == Less,
+        Opcode::TestLtEq => ordering != Greater,
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+/// Returns the number of bits in an integer type.
+fn bits(ty: &Type) -> i128 {
+    match ty {
+        Type::Char | Type::I8 | Type::U8 => 8,
+        Type::I16 | Type::U16 => 16,
+        Type::I32 | Type::U32 => 32,
+        _ => 64,
+    }
+}
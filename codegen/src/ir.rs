@@ -0,0 +1,1750 @@
+//! A textual format for Cardinal IR, with a printer and a parser.
+//!
+//! Every `Module` and `Function` can be written out with `Display`, as can a single block
+//! with `display_block`, and `parse_module` reads the text back into a `Module`.  The format
+//! is line based: each declaration, value or instruction sits on its own line, and `;` starts
+//! a comment that runs to the end of the line.
+//!
+//! ```text
+//! struct point {
+//!     x: i32
+//!     y: i32
+//! }
+//!
+//! global internal counter: i32 = int 0
+//!
+//! function main(argc: i32, argv: char*[]) -> i32 {
+//!     var my_var: i32
+//!
+//!     block0 {
+//!         import "stdio.h"
+//!         v0 = i32 21
+//!         v1 = i32 21
+//!         v2 = add v0, v1
+//!         v3 = named my_var: i32
+//!         v4 = named printf: fn(char*, ...) -> i32
+//!         v5 = str "%d"
+//!         set v3, v2
+//!         call v4, v5, v3
+//!     }
+//! }
+//! ```
+//!
+//! # Declarations
+//!
+//! - `struct <name> { ... }` defines a struct, with one `<field>: <type>` per line.  A struct
+//!   without a body is opaque, and is only forward declared.
+//! - `global <name>: <type>` declares a global variable in the module.  The name may be
+//!   preceded by `internal` or `import` to change its linkage from the default of exported,
+//!   and by `const` to make it read-only.  `= <constant>` gives it an initializer.
+//! - `function <name>(<arg>: <type>, ...) -> <type>` declares a function.  The return type
+//!   defaults to `void` when `-> <type>` is left out.  Like globals, the name may be preceded
+//!   by `internal` or `import`, and then by the `pure` and `const` attributes of the function.
+//!   A function without a `{ ... }` body has no blocks, and is only declared, so it is
+//!   imported.
+//! - `var <name>: <type>` declares a variable at the start of a function.
+//!
+//! # Types
+//!
+//! The primitive types are `void`, `bool`, `char`, `i8`, `i16`, `i32`, `i64`, `isize`, `u8`,
+//! `u16`, `u32`, `u64`, `usize`, `float` and `double`.  Any other name refers to a struct, and
+//! is quoted if it is not a plain identifier or clashes with a primitive type.  Function types
+//! are written as `fn(<type>, ...) -> <type>`, with a trailing `...` for variadic functions.
+//!
+//! Types are read from left to right: a `*` suffix makes a pointer, `[n]` makes an array of
+//! `n` elements and `[]` makes an implicitly sized array.  `i32*[4]` is an array of four
+//! pointers, while `i32[4]*` points to an array.  Parentheses group a function type before a
+//! suffix, as in `(fn(i32) -> i32)*`.
+//!
+//! # Blocks
+//!
+//! A block is written as `blockN { ... }`, where `N` is the index of the block in its parent.
+//! Inside a block, `import "<header>"` lines come first, then the values and instructions of
+//! the block in the order that they run, and finally its nested blocks.  A conditional block
+//! is written as `blockN if vK { ... }`, and may be followed by any number of `elif vK { ... }`
+//! blocks and a single `else { ... }` block.  Loops are written as `blockN while vK { ... }`,
+//! `blockN do_while vK { ... }` or `blockN for vI, vC, vS { ... }`, where `_` stands in for
+//! a missing `for` initializer or step.  A nested block runs where its parent `enter`s it, or
+//! after the parent's instructions if it is never entered.
+//!
+//! # Values
+//!
+//! Values are defined as `vN = <value>`, where `N` is the index of the value in the function's
+//! value table.  Values are numbered across the whole function, and can be used in any block
+//! that their definition dominates.  They may be written in any order, but every number from
+//! `v0` up to the largest one must be defined exactly once.  Every value has a type.  A value
+//! is one of:
+//!
+//! - Integer constants, written as an integer type followed by a `u64`, such as `i32 21`.
+//! - `float <f64>`, `double <f64>` or `bool <true|false>` constants.
+//! - `str "<text>"` and `char "<text>"` constants, using `\\`, `\"`, `\n`, `\r`, `\t`, `\0`
+//!   and `\u{...}` escapes.  Strings have the type `char*`.  A string may be given an
+//!   encoding with `str u8 "<text>"`, `str u16 "<text>"`, `str u32 "<text>"` or
+//!   `str wide "<text>"`, which makes it a pointer to the character type of the encoding.
+//! - `bytes "<text>"` strings of raw bytes, where each character stands for a byte up to
+//!   `\u{ff}`.  Byte strings have the type `char*`.
+//! - `named <name>: <type>` references, where the name may be followed by any number of
+//!   `.field`, `->field`, `::field` or `[vK]` properties.  The type is the type of the value
+//!   being referred to.
+//! - `block blockN` references, which have the type `void`.  A branch target may pass values
+//!   to the parameters of the block, as in `block blockN(vA, vB)`.
+//! - `param N: <type>` parameters of the block, which take the values passed by branches to
+//!   it.  They are numbered from 0, in the order that they are defined.
+//! - An instruction such as `add v0, v1`, whose type is inferred from its arguments.
+//!
+//! Global initializers take their types from the global, so integers are written as
+//! `int <u64>`.  The other constants are the same as above, along with `[<constant>, ...]`
+//! arrays and `{ <field> = <constant>, ... }` structs.
+//!
+//! # Instructions
+//!
+//! An instruction is its opcode mnemonic followed by a comma separated list of values, for
+//! example `set v3, v2` or `ret`.  The mnemonics are listed by `Opcode::name`.
+
+use crate::data::{Constant, GlobalData};
+use crate::entities::{AbiParam, Block, BlockCall, BlockPath, BlockStep, FunctionAttribute, FunctionType, Linkage, Named, NamedProperty, StringEncoding, Type, Value, ValueInfo};
+use crate::function::{Function, FunctionSignature, ValueTable};
+use crate::instruction::{BlockType, InstBlock, InstructionInfo, Opcode};
+use crate::module::Module;
+use crate::structs::{StructDef, StructField};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// An error produced while parsing textual IR, pointing at the offending line and column.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+
+    /// The line of the error, starting from 1.
+    pub line: usize,
+
+    /// The column of the error, starting from 1.
+    pub column: usize,
+
+    /// A description of the error.
+    pub message: String,
+
+}
+
+impl fmt::Display for ParseError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+
+}
+
+impl Error for ParseError {}
+
+/// Parses a module from its textual IR.
+pub fn parse_module(src: &str) -> Result<Module, ParseError> {
+    let tokens = Lexer::new(src).tokenize()?;
+    Parser { tokens, pos: 0, defined: vec![] }.parse_module()
+}
+
+/// Writes textual IR with indentation.
+struct Printer<'a, 'b> {
+
+    /// The formatter to write into.
+    f: &'a mut fmt::Formatter<'b>,
+
+    /// The current indentation level.
+    indent: usize,
+
+}
+
+impl<'a, 'b> Printer<'a, 'b> {
+
+    /// Writes a single indented line.
+    fn line(&mut self, text: &str) -> fmt::Result {
+        if text.is_empty() {
+            return writeln!(self.f);
+        }
+
+        writeln!(self.f, "{:indent$}{}", "", text, indent = self.indent * 4)
+    }
+
+    fn module(&mut self, module: &Module) -> fmt::Result {
+        for def in &module.structs {
+            self.struct_def(def)?;
+        }
+
+        if !module.structs.is_empty() && !module.data.is_empty() {
+            self.line("")?;
+        }
+
+        for (name, global) in &module.data {
+            self.line(&display_global(name, global))?;
+        }
+
+        for (i, func) in module.functions.values().enumerate() {
+            if i > 0 || !module.data.is_empty() || !module.structs.is_empty() {
+                self.line("")?;
+            }
+
+            self.function(func)?;
+        }
+
+        Ok(())
+    }
+
+    fn struct_def(&mut self, def: &StructDef) -> fmt::Result {
+        let header = format!("struct {}", display_name(&def.name));
+
+        let fields = match &def.fields {
+            Some(fields) => fields,
+            None => return self.line(&header),
+        };
+
+        self.line(&(header + " {"))?;
+        self.indent += 1;
+
+        for field in fields {
+            self.line(&format!("{}: {}", display_name(&field.0), display_type(&field.1)))?;
+        }
+
+        self.indent -= 1;
+        self.line("}")
+    }
+
+    fn function(&mut self, func: &Function) -> fmt::Result {
+        let args: Vec<String> = func.signature.arguments.iter()
+            .map(|arg| format!("{}: {}", display_name(&arg.0), display_type(&arg.1)))
+            .collect();
+
+        let declared = func.blocks.is_empty() && func.variables.is_empty();
+
+        // A function without a body is imported, so only other linkages are written out.
+        let linkage = match func.linkage {
+            Linkage::Internal => "internal ",
+            Linkage::Import if !declared => "import ",
+            _ => "",
+        };
+
+        let attributes: String = func.attributes.iter()
+            .map(|attr| match attr {
+                FunctionAttribute::Pure => "pure ",
+                FunctionAttribute::Const => "const ",
+            })
+            .collect();
+
+        let header = format!(
+            "function {}{}{}({}) -> {}",
+            linkage,
+            attributes,
+            display_name(&func.name),
+            args.join(", "),
+            display_type(&func.signature.returns)
+        );
+
+        if declared {
+            return self.line(&header);
+        }
+
+        self.line(&(header + " {"))?;
+        self.indent += 1;
+
+        for (name, ty) in &func.variables {
+            self.line(&format!("var {}: {}", display_name(name), display_type(ty)))?;
+        }
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            if i > 0 || !func.variables.is_empty() {
+                self.line("")?;
+            }
+
+            self.block(&format!("block{}", i), block, &func.values)?;
+        }
+
+        self.indent -= 1;
+        self.line("}")
+    }
+
+    /// Writes a block, its nested blocks and its `elif`/`else` chain.
+    fn block(&mut self, label: &str, block: &InstBlock, values: &ValueTable) -> fmt::Result {
+        self.line(&(label.to_string() + &display_block_type(&block.block_type) + " {"))?;
+        self.block_body(block, values)?;
+
+        for elif in &block.elses {
+            let cond = match elif.block_type {
+                BlockType::If(v) => format!(" {}", display_value_ref(v)),
+                _ => String::new(),
+            };
+
+            self.line(&format!("}} elif{} {{", cond))?;
+            self.block_body(elif, values)?;
+        }
+
+        if let Some(else_block) = &block.else_block {
+            self.line("} else {")?;
+            self.block_body(else_block, values)?;
+        }
+
+        self.line("}")
+    }
+
+    fn block_body(&mut self, block: &InstBlock, values: &ValueTable) -> fmt::Result {
+        self.indent += 1;
+
+        for import in &block.imports {
+            self.line(&format!("import {}", display_string(import)))?;
+        }
+
+        // Values are written in between the instructions, at the position that they were
+        // defined at.
+        let mut defined = block.values.iter().enumerate().peekable();
+
+        for position in 0..=block.insts.len() {
+            let before = |(i, _): &(usize, _)| block.positions.get(*i).is_none_or(|p| *p <= position);
+
+            while let Some((_, v)) = defined.next_if(before) {
+                self.value(*v, values)?;
+            }
+
+            if let Some(inst) = block.insts.get(position) {
+                self.line(&display_inst(inst))?;
+            }
+        }
+
+        for (_, v) in defined {
+            self.value(*v, values)?;
+        }
+
+        for (i, nested) in block.blocks.iter().enumerate() {
+            self.block(&format!("block{}", i), nested, values)?;
+        }
+
+        self.indent -= 1;
+        Ok(())
+    }
+
+    /// Writes the definition of a value from the function's value table.  A value that is
+    /// missing from the table is written as a comment.
+    fn value(&mut self, v: Value, values: &ValueTable) -> fmt::Result {
+        match (values.get(v), values.ty(v)) {
+            (Some(value), Some(ty)) => self.line(&format!("v{} = {}", v.0, display_value_info(value, ty))),
+            _ => self.line(&format!("; v{} is not defined", v.0)),
+        }
+    }
+
+}
+
+impl fmt::Display for Type {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", display_type(self))
+    }
+
+}
+
+impl fmt::Display for Module {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer { f, indent: 0 }.module(self)
+    }
+
+}
+
+impl fmt::Display for Function {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer { f, indent: 0 }.function(self)
+    }
+
+}
+
+/// Infers the types of the instruction values in a parsed function from the types of their
+/// arguments, in the order that the values are numbered.  Types that can't be inferred are
+/// left as `void`, so that the verifier can report the problem.
+fn infer_types(values: &mut ValueTable) {
+    for i in 0..values.len() {
+        if let ValueInfo::Instruction(inst) = &values.info[i] {
+            let args: Option<Vec<Type>> = inst.arguments.iter()
+                .map(|v| values.ty(*v).cloned())
+                .collect();
+
+            values.types[i] = args.and_then(|args| inst.opcode.result_type(&args).ok())
+                .unwrap_or(Type::Void);
+        }
+    }
+}
+
+/// A block of a function that can be written out with `Display`, along with the values that
+/// it defines and the blocks nested in it.  It is created by `display_block`.
+pub struct DisplayBlock<'a> {
+
+    /// The label of the block, such as `block2`.
+    label: String,
+
+    /// The block to write out.
+    block: &'a InstBlock,
+
+    /// The value table of the function that the block belongs to.
+    values: &'a ValueTable,
+
+}
+
+impl fmt::Display for DisplayBlock<'_> {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer { f, indent: 0 }.block(&self.label, self.block, self.values)
+    }
+
+}
+
+/// Returns a block of a function in a form that can be written out with `Display`.  Blocks
+/// don't own their values, so they are looked up in the function.  The block is labelled
+/// with its index in the function or in the block that it is nested in, and an `elif` or
+/// `else` branch is labelled like the block that it belongs to.
+pub fn display_block<'a>(func: &'a Function, block: &'a InstBlock) -> DisplayBlock<'a> {
+    DisplayBlock {
+        label: format!("block{}", block_index(&func.blocks, block).unwrap_or(0)),
+        block,
+        values: &func.values,
+    }
+}
+
+/// Finds the index of a block among `blocks`, or among the blocks nested in them.
+fn block_index(blocks: &[InstBlock], block: &InstBlock) -> Option<usize> {
+    for (i, b) in blocks.iter().enumerate() {
+        let branches = b.elses.iter().chain(b.else_block.as_deref());
+
+        if std::ptr::eq(b, block) || branches.clone().any(|branch| std::ptr::eq(branch, block)) {
+            return Some(i);
+        }
+
+        let nested = Some(b).into_iter().chain(branches)
+            .find_map(|branch| block_index(&branch.blocks, block));
+
+        if nested.is_some() {
+            return nested;
+        }
+    }
+
+    None
+}
+
+/// Returns true if the name can be written without quotes.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn display_name(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        display_string(name)
+    }
+}
+
+fn display_string(text: &str) -> String {
+    let mut out = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Writes a string of bytes, escaping the bytes that aren't printable ASCII.
+fn display_bytes(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+
+    for b in bytes {
+        match *b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b'\0' => out.push_str("\\0"),
+            b @ 0x20..=0x7e => out.push(b as char),
+            b => out.push_str(&format!("\\u{{{:x}}}", b)),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Writes the keyword of a string encoding, followed by a space, or nothing for narrow
+/// strings.
+fn display_encoding(encoding: StringEncoding) -> &'static str {
+    match encoding {
+        StringEncoding::Narrow => "",
+        StringEncoding::Utf8 => "u8 ",
+        StringEncoding::Utf16 => "u16 ",
+        StringEncoding::Utf32 => "u32 ",
+        StringEncoding::Wide => "wide ",
+    }
+}
+
+/// The keywords of the primitive types, and the types they stand for.
+const PRIMITIVE_TYPES: [(&str, Type); 15] = [
+    ("void", Type::Void),
+    ("bool", Type::Bool),
+    ("char", Type::Char),
+    ("i8", Type::I8),
+    ("i16", Type::I16),
+    ("i32", Type::I32),
+    ("i64", Type::I64),
+    ("isize", Type::ISize),
+    ("u8", Type::U8),
+    ("u16", Type::U16),
+    ("u32", Type::U32),
+    ("u64", Type::U64),
+    ("usize", Type::USize),
+    ("float", Type::Float),
+    ("double", Type::Double),
+];
+
+fn primitive_type(name: &str) -> Option<Type> {
+    PRIMITIVE_TYPES.iter().find(|(n, _)| *n == name).map(|(_, ty)| ty.clone())
+}
+
+fn display_type(ty: &Type) -> String {
+    // Function types take every postfix after them as part of their return type, so they
+    // are wrapped in parentheses when they are pointed to or stored in an array.
+    let inner = |ty: &Type| match ty {
+        Type::Function(_) => format!("({})", display_type(ty)),
+        _ => display_type(ty),
+    };
+
+    match ty {
+        Type::Pointer(to) => inner(to) + "*",
+        Type::Array(of, Some(len)) => format!("{}[{}]", inner(of), len),
+        Type::Array(of, None) => inner(of) + "[]",
+        Type::Function(func) => {
+            let mut params: Vec<String> = func.params.iter().map(display_type).collect();
+
+            if func.variadic {
+                params.push("...".into());
+            }
+
+            format!("fn({}) -> {}", params.join(", "), display_type(&func.returns))
+        },
+        Type::Struct(name) => {
+            if name == "fn" || primitive_type(name).is_some() {
+                display_string(name)
+            } else {
+                display_name(name)
+            }
+        },
+        _ => PRIMITIVE_TYPES.iter().find(|(_, t)| t == ty).unwrap().0.to_string(),
+    }
+}
+
+fn display_global(name: &str, global: &GlobalData) -> String {
+    let mut out = String::from("global ");
+
+    match global.linkage {
+        Linkage::Internal => out.push_str("internal "),
+        Linkage::Import => out.push_str("import "),
+        Linkage::Export => {},
+    }
+
+    if global.constant {
+        out.push_str("const ");
+    }
+
+    out.push_str(&format!("{}: {}", display_name(name), display_type(&global.ty)));
+
+    if let Some(init) = &global.init {
+        out.push_str(" = ");
+        out.push_str(&display_constant(init));
+    }
+
+    out
+}
+
+fn display_constant(constant: &Constant) -> String {
+    match constant {
+        Constant::Integer(n) => format!("int {}", n),
+        Constant::Float(n) => format!("float {}", n),
+        Constant::Double(n) => format!("double {}", n),
+        Constant::Boolean(b) => format!("bool {}", b),
+        Constant::String(s, encoding) => format!("str {}{}", display_encoding(*encoding), display_string(s)),
+        Constant::Bytes(b) => format!("bytes {}", display_bytes(b)),
+        Constant::Char(s) => format!("char {}", display_string(s)),
+        Constant::Array(items) => {
+            let items: Vec<String> = items.iter().map(display_constant).collect();
+            format!("[{}]", items.join(", "))
+        },
+        Constant::Struct(fields) => {
+            let fields: Vec<String> = fields.iter()
+                .map(|(name, c)| format!("{} = {}", display_name(name), display_constant(c)))
+                .collect();
+
+            format!("{{ {} }}", fields.join(", "))
+        },
+    }
+}
+
+fn display_block_type(block_type: &BlockType) -> String {
+    match *block_type {
+        BlockType::If(v) => format!(" if {}", display_value_ref(v)),
+        BlockType::While(v) => format!(" while {}", display_value_ref(v)),
+        BlockType::DoWhile(v) => format!(" do_while {}", display_value_ref(v)),
+        BlockType::For { init, cond, step } => {
+            let optional = |v: Option<Value>| v.map(display_value_ref).unwrap_or_else(|| "_".into());
+            format!(" for {}, {}, {}", optional(init), display_value_ref(cond), optional(step))
+        },
+        BlockType::Basic => String::new(),
+    }
+}
+
+fn display_value_ref(v: Value) -> String {
+    format!("v{}", v.0)
+}
+
+fn display_named(named: &Named) -> String {
+    let mut out = display_name(&named.name);
+
+    for prop in &named.properties {
+        match prop {
+            NamedProperty::Basic(n) => {
+                out.push('.');
+                out.push_str(&display_name(n));
+            },
+            NamedProperty::Static(n) => {
+                out.push_str("::");
+                out.push_str(&display_name(n));
+            },
+            NamedProperty::Pointer(n) => {
+                out.push_str("->");
+                out.push_str(&display_name(n));
+            },
+            NamedProperty::Index(v) => {
+                out.push('[');
+                out.push_str(&display_value_ref(*v));
+                out.push(']');
+            },
+        }
+    }
+
+    out
+}
+
+fn display_inst(inst: &InstructionInfo) -> String {
+    let args: Vec<String> = inst.arguments.iter().map(|v| display_value_ref(*v)).collect();
+
+    if args.is_empty() {
+        inst.opcode.name().to_string()
+    } else {
+        format!("{} {}", inst.opcode.name(), args.join(", "))
+    }
+}
+
+fn display_value_info(value: &ValueInfo, ty: &Type) -> String {
+    match value {
+        ValueInfo::IntegerConstant(n) => format!("{} {}", display_type(ty), n),
+        ValueInfo::FloatConstant(n) => format!("float {}", n),
+        ValueInfo::DoubleConstant(n) => format!("double {}", n),
+        ValueInfo::BooleanConstant(b) => format!("bool {}", b),
+        ValueInfo::StringConstant(s, encoding) => format!("str {}{}", display_encoding(*encoding), display_string(s)),
+        ValueInfo::BytesConstant(b) => format!("bytes {}", display_bytes(b)),
+        ValueInfo::CharConstant(s) => format!("char {}", display_string(s)),
+        ValueInfo::Named(named) => format!("named {}: {}", display_named(named), display_type(ty)),
+        ValueInfo::Block(b) => format!("block block{}", b.0),
+        ValueInfo::BlockCall(call) => {
+            let args: Vec<String> = call.1.iter().map(|v| display_value_ref(*v)).collect();
+            format!("block block{}({})", (call.0).0, args.join(", "))
+        },
+        ValueInfo::Param(n) => format!("param {}: {}", n, display_type(ty)),
+        ValueInfo::Instruction(inst) => display_inst(inst),
+    }
+}
+
+/// The kinds of tokens in textual IR.
+#[derive(Clone, PartialEq, Debug)]
+enum TokenKind {
+    Ident(String),
+    Str(String),
+    Number(String),
+    Punct(&'static str),
+    Newline,
+    Eof,
+}
+
+/// A token along with the position it started at.
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+/// Splits textual IR into tokens.
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+
+    fn new(src: &'a str) -> Self {
+        Self {
+            chars: src.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Returns true if the remaining input starts with the given text.
+    fn rest_starts_with(&self, text: &str) -> bool {
+        self.chars.clone().take(text.len()).eq(text.chars())
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn error(&self, line: usize, column: usize, message: String) -> ParseError {
+        ParseError { line, column, message }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = vec![];
+
+        loop {
+            let (line, column) = (self.line, self.column);
+
+            let c = match self.chars.peek() {
+                Some(c) => *c,
+                None => {
+                    tokens.push(Token { kind: TokenKind::Eof, line, column });
+                    return Ok(tokens);
+                }
+            };
+
+            let kind = match c {
+                '\n' => {
+                    self.bump();
+                    TokenKind::Newline
+                },
+                ';' => {
+                    while let Some(c) = self.chars.peek() {
+                        if *c == '\n' {
+                            break;
+                        }
+
+                        self.bump();
+                    }
+
+                    continue;
+                },
+                c if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                },
+                c if c.is_ascii_alphabetic() || c == '_' => {
+                    let mut ident = String::new();
+
+                    while let Some(c) = self.chars.peek() {
+                        if !(c.is_ascii_alphanumeric() || *c == '_') {
+                            break;
+                        }
+
+                        ident.push(*c);
+                        self.bump();
+                    }
+
+                    TokenKind::Ident(ident)
+                },
+                c if c.is_ascii_digit() || c == '-' => {
+                    let mut number = String::new();
+                    number.push(c);
+                    self.bump();
+
+                    if c == '-' && self.chars.peek() == Some(&'>') {
+                        self.bump();
+                        tokens.push(Token { kind: TokenKind::Punct("->"), line, column });
+                        continue;
+                    }
+
+                    while let Some(c) = self.chars.peek() {
+                        let exponent_sign = (*c == '-' || *c == '+')
+                            && number.ends_with(['e', 'E']);
+
+                        if !(c.is_ascii_alphanumeric() || *c == '.' || exponent_sign) {
+                            break;
+                        }
+
+                        number.push(*c);
+                        self.bump();
+                    }
+
+                    TokenKind::Number(number)
+                },
+                '"' => {
+                    self.bump();
+                    TokenKind::Str(self.string(line, column)?)
+                },
+                ':' => {
+                    self.bump();
+
+                    if self.chars.peek() == Some(&':') {
+                        self.bump();
+                        TokenKind::Punct("::")
+                    } else {
+                        TokenKind::Punct(":")
+                    }
+                },
+                _ => {
+                    let punct = match c {
+                        '{' => "{",
+                        '}' => "}",
+                        '(' => "(",
+                        ')' => ")",
+                        '[' => "[",
+                        ']' => "]",
+                        ',' => ",",
+                        '=' => "=",
+                        '.' if self.rest_starts_with("...") => {
+                            self.bump();
+                            self.bump();
+                            "..."
+                        },
+                        '.' => ".",
+                        '*' => "*",
+                        _ => return Err(self.error(line, column, format!("unexpected character `{}`", c))),
+                    };
+
+                    self.bump();
+                    TokenKind::Punct(punct)
+                },
+            };
+
+            tokens.push(Token { kind, line, column });
+        }
+    }
+
+    /// Reads the rest of a string literal, after the opening quote.
+    fn string(&mut self, line: usize, column: usize) -> Result<String, ParseError> {
+        let mut out = String::new();
+
+        loop {
+            let (esc_line, esc_column) = (self.line, self.column);
+
+            match self.bump() {
+                None | Some('\n') => return Err(self.error(line, column, "unterminated string".into())),
+                Some('"') => return Ok(out),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('u') => self.unicode_escape(esc_line, esc_column)?,
+                        _ => return Err(self.error(esc_line, esc_column, "invalid escape sequence".into())),
+                    };
+
+                    out.push(c);
+                },
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    /// Reads the `{XXXX}` part of a `\u{XXXX}` escape.
+    fn unicode_escape(&mut self, line: usize, column: usize) -> Result<char, ParseError> {
+        if self.bump() != Some('{') {
+            return Err(self.error(line, column, "expected `{` in unicode escape".into()));
+        }
+
+        let mut hex = String::new();
+
+        loop {
+            match self.bump() {
+                Some('}') => break,
+                Some(c) if c.is_ascii_hexdigit() => hex.push(c),
+                _ => return Err(self.error(line, column, "invalid unicode escape".into())),
+            }
+        }
+
+        u32::from_str_radix(&hex, 16).ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| self.error(line, column, "invalid unicode escape".into()))
+    }
+
+}
+
+/// Builds a `Module` from a list of tokens.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+
+    /// Whether each value of the function being parsed has been defined yet, as values may
+    /// be written in any order.
+    defined: Vec<bool>,
+}
+
+impl Parser {
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+
+        token
+    }
+
+    fn error_at(&self, token: &Token, message: String) -> ParseError {
+        ParseError {
+            line: token.line,
+            column: token.column,
+            message,
+        }
+    }
+
+    fn unexpected(&self, token: &Token, expected: &str) -> ParseError {
+        let found = match &token.kind {
+            TokenKind::Ident(s) => format!("`{}`", s),
+            TokenKind::Str(_) => "a string".to_string(),
+            TokenKind::Number(n) => format!("`{}`", n),
+            TokenKind::Punct(p) => format!("`{}`", p),
+            TokenKind::Newline => "end of line".to_string(),
+            TokenKind::Eof => "end of input".to_string(),
+        };
+
+        self.error_at(token, format!("expected {}, found {}", expected, found))
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek().kind, TokenKind::Punct(p) if p == punct)
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Ident(s) if s == ident)
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), ParseError> {
+        let token = self.next();
+
+        if matches!(token.kind, TokenKind::Punct(p) if p == punct) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&token, &format!("`{}`", punct)))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        let token = self.next();
+
+        match &token.kind {
+            TokenKind::Ident(s) if s == keyword => Ok(()),
+            _ => Err(self.unexpected(&token, &format!("`{}`", keyword))),
+        }
+    }
+
+    fn expect_newline(&mut self) -> Result<(), ParseError> {
+        let token = self.next();
+
+        match token.kind {
+            TokenKind::Newline | TokenKind::Eof => Ok(()),
+            _ => Err(self.unexpected(&token, "end of line")),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek().kind == TokenKind::Newline {
+            self.pos += 1;
+        }
+    }
+
+    /// Parses an identifier or a quoted name.
+    fn name(&mut self) -> Result<String, ParseError> {
+        let token = self.next();
+
+        match token.kind {
+            TokenKind::Ident(s) | TokenKind::Str(s) => Ok(s),
+            _ => Err(self.unexpected(&token, "a name")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let token = self.next();
+
+        match token.kind {
+            TokenKind::Str(s) => Ok(s),
+            _ => Err(self.unexpected(&token, "a string")),
+        }
+    }
+
+    /// Parses the optional encoding of a string constant, which defaults to narrow.
+    fn encoding(&mut self) -> StringEncoding {
+        let encoding = match &self.peek().kind {
+            TokenKind::Ident(s) if s == "u8" => StringEncoding::Utf8,
+            TokenKind::Ident(s) if s == "u16" => StringEncoding::Utf16,
+            TokenKind::Ident(s) if s == "u32" => StringEncoding::Utf32,
+            TokenKind::Ident(s) if s == "wide" => StringEncoding::Wide,
+            _ => return StringEncoding::Narrow,
+        };
+
+        self.next();
+        encoding
+    }
+
+    /// Parses a string of bytes, where each character stands for a byte.
+    fn bytes(&mut self) -> Result<Vec<u8>, ParseError> {
+        let token = self.peek().clone();
+
+        self.string()?.chars()
+            .map(|c| u8::try_from(c as u32).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| self.error_at(&token, "byte strings can only hold characters up to `\\u{ff}`".into()))
+    }
+
+    /// Parses an identifier such as `v3` or `block2`, returning its index.
+    fn indexed(&mut self, prefix: &str) -> Result<u32, ParseError> {
+        let token = self.next();
+
+        if let TokenKind::Ident(s) = &token.kind {
+            if let Some(n) = s.strip_prefix(prefix).and_then(|n| n.parse().ok()) {
+                return Ok(n);
+            }
+        }
+
+        Err(self.unexpected(&token, &format!("`{}N`", prefix)))
+    }
+
+    fn value_ref(&mut self) -> Result<Value, ParseError> {
+        Ok(Value(self.indexed("v")?))
+    }
+
+    /// Parses a value reference, or `_` for no value.
+    fn optional_value_ref(&mut self) -> Result<Option<Value>, ParseError> {
+        if self.is_ident("_") {
+            self.next();
+            return Ok(None);
+        }
+
+        Ok(Some(self.value_ref()?))
+    }
+
+    fn parse_module(mut self) -> Result<Module, ParseError> {
+        let mut module = Module::new();
+
+        loop {
+            self.skip_newlines();
+            let token = self.peek().clone();
+
+            match &token.kind {
+                TokenKind::Eof => return Ok(module),
+                TokenKind::Ident(s) if s == "global" => {
+                    let (name, global) = self.global()?;
+
+                    if module.data.contains_key(&name) {
+                        return Err(self.error_at(&token, format!("global `{}` is already declared", name)));
+                    }
+
+                    module.define_variable(name, global);
+                },
+                TokenKind::Ident(s) if s == "struct" => {
+                    let def = self.struct_def()?;
+
+                    if module.get_struct(&def.name).is_some() {
+                        return Err(self.error_at(&token, format!("struct `{}` is already declared", def.name)));
+                    }
+
+                    module.structs.push(def);
+                },
+                TokenKind::Ident(s) if s == "function" => {
+                    let func = self.function()?;
+
+                    if module.functions.contains_key(&func.name) {
+                        return Err(self.error_at(&token, format!("function `{}` is already declared", func.name)));
+                    }
+
+                    module.define_function(func);
+                },
+                _ => return Err(self.unexpected(&token, "`struct`, `global` or `function`")),
+            }
+        }
+    }
+
+    /// Returns true if the next token is the given keyword, rather than a name followed by
+    /// the `follow` punctuation.
+    fn is_modifier(&self, keyword: &str, follow: &'static str) -> bool {
+        let next = &self.tokens[(self.pos + 1).min(self.tokens.len() - 1)];
+        self.is_ident(keyword) && next.kind != TokenKind::Punct(follow)
+    }
+
+    /// Parses an optional `internal` or `import` linkage, which defaults to exported.
+    fn linkage(&mut self, follow: &'static str) -> Linkage {
+        if self.is_modifier("internal", follow) {
+            self.next();
+            Linkage::Internal
+        } else if self.is_modifier("import", follow) {
+            self.next();
+            Linkage::Import
+        } else {
+            Linkage::Export
+        }
+    }
+
+    fn global(&mut self) -> Result<(String, GlobalData), ParseError> {
+        self.expect_keyword("global")?;
+
+        let linkage = self.linkage(":");
+        let constant = self.is_modifier("const", ":");
+
+        if constant {
+            self.next();
+        }
+
+        let name = self.name()?;
+        self.expect_punct(":")?;
+
+        let mut global = GlobalData::new(self.ty()?);
+        global.linkage = linkage;
+        global.constant = constant;
+
+        if self.is_punct("=") {
+            self.next();
+            global.init = Some(self.constant()?);
+        }
+
+        self.expect_newline()?;
+        Ok((name, global))
+    }
+
+    fn constant(&mut self) -> Result<Constant, ParseError> {
+        let token = self.next();
+
+        let constant = match &token.kind {
+            TokenKind::Punct("[") => {
+                let mut items = vec![];
+
+                while !self.is_punct("]") {
+                    if !items.is_empty() {
+                        self.expect_punct(",")?;
+                    }
+
+                    items.push(self.constant()?);
+                }
+
+                self.next();
+                Constant::Array(items)
+            },
+            TokenKind::Punct("{") => {
+                let mut fields = vec![];
+
+                while !self.is_punct("}") {
+                    if !fields.is_empty() {
+                        self.expect_punct(",")?;
+                    }
+
+                    let name = self.name()?;
+                    self.expect_punct("=")?;
+                    fields.push((name, self.constant()?));
+                }
+
+                self.next();
+                Constant::Struct(fields)
+            },
+            TokenKind::Ident(s) if s == "int" => {
+                let token = self.next();
+
+                match &token.kind {
+                    TokenKind::Number(n) => Constant::Integer(n.parse()
+                        .map_err(|_| self.unexpected(&token, "an unsigned integer"))?),
+                    _ => return Err(self.unexpected(&token, "an unsigned integer")),
+                }
+            },
+            TokenKind::Ident(s) if s == "float" => Constant::Float(self.float()?),
+            TokenKind::Ident(s) if s == "double" => Constant::Double(self.float()?),
+            TokenKind::Ident(s) if s == "bool" => Constant::Boolean(self.boolean()?),
+            TokenKind::Ident(s) if s == "str" => {
+                let encoding = self.encoding();
+                Constant::String(self.string()?, encoding)
+            },
+            TokenKind::Ident(s) if s == "bytes" => Constant::Bytes(self.bytes()?),
+            TokenKind::Ident(s) if s == "char" => Constant::Char(self.string()?),
+            _ => return Err(self.unexpected(&token, "a constant")),
+        };
+
+        Ok(constant)
+    }
+
+    fn boolean(&mut self) -> Result<bool, ParseError> {
+        let token = self.next();
+
+        match &token.kind {
+            TokenKind::Ident(s) if s == "true" => Ok(true),
+            TokenKind::Ident(s) if s == "false" => Ok(false),
+            _ => Err(self.unexpected(&token, "`true` or `false`")),
+        }
+    }
+
+    fn struct_def(&mut self) -> Result<StructDef, ParseError> {
+        self.expect_keyword("struct")?;
+        let name = self.name()?;
+
+        if !self.is_punct("{") {
+            self.expect_newline()?;
+            return Ok(StructDef::opaque(name));
+        }
+
+        self.next();
+        self.expect_newline()?;
+
+        let mut fields: Vec<StructField> = vec![];
+
+        loop {
+            self.skip_newlines();
+            let token = self.peek().clone();
+
+            if let TokenKind::Punct("}") = token.kind {
+                self.next();
+                self.expect_newline()?;
+                return Ok(StructDef::new(name, fields));
+            }
+
+            let field = self.name()?;
+            self.expect_punct(":")?;
+            let ty = self.ty()?;
+            self.expect_newline()?;
+
+            if fields.iter().any(|f| f.0 == field) {
+                return Err(self.error_at(&token, format!("field `{}` is already declared", field)));
+            }
+
+            fields.push(StructField(field, ty));
+        }
+    }
+
+    /// Parses a type, such as `i32`, `char*`, `point[4]` or `(fn(i32, ...) -> i32)*`.
+    fn ty(&mut self) -> Result<Type, ParseError> {
+        let token = self.next();
+
+        let mut ty = match &token.kind {
+            TokenKind::Punct("(") => {
+                let ty = self.ty()?;
+                self.expect_punct(")")?;
+                ty
+            },
+            TokenKind::Ident(s) if s == "fn" => {
+                let mut func = FunctionType {
+                    params: vec![],
+                    returns: Box::new(Type::Void),
+                    variadic: false,
+                };
+
+                self.expect_punct("(")?;
+
+                while !self.is_punct(")") {
+                    if !func.params.is_empty() || func.variadic {
+                        self.expect_punct(",")?;
+                    }
+
+                    if self.is_punct("...") {
+                        self.next();
+                        func.variadic = true;
+                        break;
+                    }
+
+                    func.params.push(self.ty()?);
+                }
+
+                self.expect_punct(")")?;
+
+                if self.is_punct("->") {
+                    self.next();
+                    func.returns = Box::new(self.ty()?);
+                }
+
+                Type::Function(func)
+            },
+            TokenKind::Ident(s) => primitive_type(s).unwrap_or_else(|| Type::Struct(s.to_string())),
+            TokenKind::Str(s) => Type::Struct(s.to_string()),
+            _ => return Err(self.unexpected(&token, "a type")),
+        };
+
+        loop {
+            if self.is_punct("*") {
+                self.next();
+                ty = Type::pointer(ty);
+            } else if self.is_punct("[") {
+                self.next();
+
+                if self.is_punct("]") {
+                    self.next();
+                    ty = Type::array(ty, None);
+                    continue;
+                }
+
+                let token = self.next();
+                let len = match &token.kind {
+                    TokenKind::Number(n) => n.parse::<usize>().ok(),
+                    _ => None,
+                };
+
+                let len = len.ok_or_else(|| self.unexpected(&token, "an array length"))?;
+                self.expect_punct("]")?;
+
+                ty = Type::array(ty, Some(len));
+            } else {
+                return Ok(ty);
+            }
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        self.expect_keyword("function")?;
+        let linkage = self.linkage("(");
+        let mut attributes = vec![];
+
+        loop {
+            if self.is_modifier("pure", "(") {
+                attributes.push(FunctionAttribute::Pure);
+            } else if self.is_modifier("const", "(") {
+                attributes.push(FunctionAttribute::Const);
+            } else {
+                break;
+            }
+
+            self.next();
+        }
+
+        let name = self.name()?;
+
+        let mut sig = FunctionSignature::new();
+        self.expect_punct("(")?;
+
+        while !self.is_punct(")") {
+            if !sig.arguments.is_empty() {
+                self.expect_punct(",")?;
+            }
+
+            let arg = self.name()?;
+            self.expect_punct(":")?;
+            sig.arguments.push(AbiParam(arg, self.ty()?));
+        }
+
+        self.expect_punct(")")?;
+
+        if self.is_punct("->") {
+            self.next();
+            sig.returns = self.ty()?;
+        }
+
+        let mut func = Function::new(name, sig);
+        func.linkage = linkage;
+        func.attributes = attributes;
+
+        if !self.is_punct("{") {
+            self.expect_newline()?;
+
+            if linkage == Linkage::Export {
+                func.linkage = Linkage::Import;
+            }
+
+            return Ok(func);
+        }
+
+        self.next();
+        self.expect_newline()?;
+        self.defined.clear();
+
+        loop {
+            self.skip_newlines();
+            let token = self.peek().clone();
+
+            match &token.kind {
+                TokenKind::Punct("}") => {
+                    if let Some(v) = self.defined.iter().position(|defined| !defined) {
+                        return Err(self.error_at(&token, format!("value v{} is not defined", v)));
+                    }
+
+                    self.next();
+                    self.expect_newline()?;
+
+                    infer_types(&mut func.values);
+                    return Ok(func);
+                },
+                TokenKind::Ident(s) if s == "var" => {
+                    self.next();
+                    let var = self.name()?;
+                    self.expect_punct(":")?;
+                    let ty = self.ty()?;
+                    self.expect_newline()?;
+
+                    if func.variables.contains_key(&var) {
+                        return Err(self.error_at(&token, format!("variable `{}` is already declared", var)));
+                    }
+
+                    func.declare_var(var, ty);
+                },
+                TokenKind::Ident(s) if s.starts_with("block") => {
+                    let path = BlockPath::new(Block(func.blocks.len() as u32));
+                    let block = self.block(func.blocks.len(), &mut func.values, path)?;
+                    func.blocks.push(block);
+                },
+                _ => return Err(self.unexpected(&token, "`var`, a block or `}`")),
+            }
+        }
+    }
+
+    /// Parses the `blockN` label of a block, checking that it matches the block's position.
+    fn block_label(&mut self, index: usize) -> Result<(), ParseError> {
+        let token = self.next();
+
+        if let TokenKind::Ident(s) = &token.kind {
+            if s == "block" {
+                return Ok(());
+            }
+
+            if let Some(n) = s.strip_prefix("block").and_then(|n| n.parse::<usize>().ok()) {
+                if n == index {
+                    return Ok(());
+                }
+
+                return Err(self.error_at(&token, format!("expected `block{}`, found `{}`", index, s)));
+            }
+        }
+
+        Err(self.unexpected(&token, &format!("`block{}`", index)))
+    }
+
+    /// Parses a labelled block at the given location, along with its `elif` and `else`
+    /// chain.  Its values are defined in the function's value table.
+    fn block(&mut self, index: usize, values: &mut ValueTable, path: BlockPath) -> Result<InstBlock, ParseError> {
+        self.block_label(index)?;
+
+        let block_type = self.block_type()?;
+
+        self.expect_punct("{")?;
+        self.expect_newline()?;
+
+        let mut block = self.block_body(block_type, values, &path)?;
+
+        loop {
+            // Each body ends on its closing `}`, which may be followed by `elif` or `else`.
+            if self.is_ident("elif") {
+                self.next();
+
+                let block_type = if self.is_punct("{") {
+                    BlockType::Basic
+                } else {
+                    BlockType::If(self.value_ref()?)
+                };
+
+                self.expect_punct("{")?;
+                self.expect_newline()?;
+
+                let elif = self.block_body(block_type, values, &path.join(BlockStep::Elif(block.elses.len())))?;
+                block.elses.push(elif);
+            } else if self.is_ident("else") {
+                self.next();
+                self.expect_punct("{")?;
+                self.expect_newline()?;
+
+                let else_block = self.block_body(BlockType::Basic, values, &path.join(BlockStep::Else))?;
+                block.else_block = Some(Box::new(else_block));
+
+                self.expect_newline()?;
+                return Ok(block);
+            } else {
+                self.expect_newline()?;
+                return Ok(block);
+            }
+        }
+    }
+
+    /// Parses the optional type of a block, such as `if v0` or `for v0, v1, _`.
+    fn block_type(&mut self) -> Result<BlockType, ParseError> {
+        let block_type = if self.is_ident("if") {
+            self.next();
+            BlockType::If(self.value_ref()?)
+        } else if self.is_ident("while") {
+            self.next();
+            BlockType::While(self.value_ref()?)
+        } else if self.is_ident("do_while") {
+            self.next();
+            BlockType::DoWhile(self.value_ref()?)
+        } else if self.is_ident("for") {
+            self.next();
+            let init = self.optional_value_ref()?;
+            self.expect_punct(",")?;
+            let cond = self.value_ref()?;
+            self.expect_punct(",")?;
+            let step = self.optional_value_ref()?;
+
+            BlockType::For { init, cond, step }
+        } else {
+            BlockType::Basic
+        };
+
+        Ok(block_type)
+    }
+
+    /// Parses the contents of a block up to and including its closing `}`.
+    fn block_body(&mut self, block_type: BlockType, values: &mut ValueTable, path: &BlockPath) -> Result<InstBlock, ParseError> {
+        let mut block = InstBlock::new(block_type);
+
+        loop {
+            self.skip_newlines();
+            let token = self.peek().clone();
+
+            match &token.kind {
+                TokenKind::Punct("}") => {
+                    self.next();
+                    return Ok(block);
+                },
+                TokenKind::Ident(s) if s == "import" => {
+                    self.next();
+                    let import = self.string()?;
+                    self.expect_newline()?;
+
+                    if !block.imports.contains(&import) {
+                        block.imports.push(import);
+                    }
+                },
+                TokenKind::Ident(s) if s.starts_with("block") => {
+                    let nested_path = path.join(BlockStep::Nested(Block(block.blocks.len() as u32)));
+                    let nested = self.block(block.blocks.len(), values, nested_path)?;
+                    block.blocks.push(nested);
+                },
+                TokenKind::Ident(s) if s.starts_with('v') && s[1..].parse::<u32>().is_ok() => {
+                    let v = self.value_ref()?;
+                    let i = v.0 as usize;
+
+                    if self.defined.get(i) == Some(&true) {
+                        return Err(self.error_at(&token, format!("value v{} is already defined", i)));
+                    }
+
+                    self.expect_punct("=")?;
+                    let start = self.peek().clone();
+                    let (value, ty) = self.value_info()?;
+                    self.expect_newline()?;
+
+                    // Parameters are numbered in the order that they are defined in.
+                    if let ValueInfo::Param(n) = value {
+                        if n as usize != block.params.len() {
+                            return Err(self.error_at(&start, format!("expected `param {}`, found `param {}`", block.params.len(), n)));
+                        }
+
+                        block.params.push(ty.clone());
+                    }
+
+                    // Values that are written before the values numbered below them leave gaps in
+                    // the table, which are filled in when those values are defined.
+                    while values.len() <= i {
+                        values.push(ValueInfo::IntegerConstant(0), Type::Void, path.clone());
+                        self.defined.push(false);
+                    }
+
+                    values.info[i] = value;
+                    values.types[i] = ty;
+                    values.blocks[i] = path.clone();
+                    self.defined[i] = true;
+
+                    block.values.push(v);
+                    block.positions.push(block.insts.len());
+                },
+                TokenKind::Ident(_) => {
+                    let inst = self.inst()?;
+                    self.expect_newline()?;
+
+                    block.insts.push(inst);
+                },
+                _ => return Err(self.unexpected(&token, "a value, an instruction, a block or `}`")),
+            }
+        }
+    }
+
+    fn inst(&mut self) -> Result<InstructionInfo, ParseError> {
+        let token = self.next();
+
+        let opcode = match &token.kind {
+            TokenKind::Ident(s) => Opcode::from_name(s)
+                .ok_or_else(|| self.error_at(&token, format!("unknown opcode `{}`", s)))?,
+            _ => return Err(self.unexpected(&token, "an opcode")),
+        };
+
+        let mut arguments = vec![];
+
+        if !matches!(self.peek().kind, TokenKind::Newline | TokenKind::Eof) {
+            arguments.push(self.value_ref()?);
+
+            while self.is_punct(",") {
+                self.next();
+                arguments.push(self.value_ref()?);
+            }
+        }
+
+        Ok(InstructionInfo { opcode, arguments })
+    }
+
+    fn float(&mut self) -> Result<f64, ParseError> {
+        let token = self.next();
+
+        let text = match &token.kind {
+            TokenKind::Number(n) => n.to_string(),
+            TokenKind::Ident(s) if s == "inf" || s == "NaN" => s.to_string(),
+            _ => String::new(),
+        };
+
+        text.parse().map_err(|_| self.unexpected(&token, "a floating point number"))
+    }
+
+    /// Parses a value along with its type.  Instructions are given the type `void`, until
+    /// their types are inferred once the whole function has been parsed.
+    fn value_info(&mut self) -> Result<(ValueInfo, Type), ParseError> {
+        let token = self.peek().clone();
+
+        let kind = match &token.kind {
+            TokenKind::Ident(s) => s.to_string(),
+            _ => return Err(self.unexpected(&token, "a value")),
+        };
+
+        let value = match kind.as_str() {
+            "float" => {
+                self.next();
+                (ValueInfo::FloatConstant(self.float()?), Type::Float)
+            },
+            "double" => {
+                self.next();
+                (ValueInfo::DoubleConstant(self.float()?), Type::Double)
+            },
+            "bool" => {
+                self.next();
+                (ValueInfo::BooleanConstant(self.boolean()?), Type::Bool)
+            },
+            "str" => {
+                self.next();
+                let encoding = self.encoding();
+
+                (ValueInfo::StringConstant(self.string()?, encoding), Type::pointer(encoding.char_type()))
+            },
+            "bytes" => {
+                self.next();
+                (ValueInfo::BytesConstant(self.bytes()?), Type::pointer(Type::Char))
+            },
+            "char" => {
+                self.next();
+                (ValueInfo::CharConstant(self.string()?), Type::Char)
+            },
+            "named" => {
+                self.next();
+                let named = self.named()?;
+                self.expect_punct(":")?;
+
+                (ValueInfo::Named(named), self.ty()?)
+            },
+            "block" => {
+                self.next();
+                let block = Block(self.indexed("block")?);
+
+                if !self.is_punct("(") {
+                    return Ok((ValueInfo::Block(block), Type::Void));
+                }
+
+                self.next();
+                let mut args = vec![];
+
+                while !self.is_punct(")") {
+                    if !args.is_empty() {
+                        self.expect_punct(",")?;
+                    }
+
+                    args.push(self.value_ref()?);
+                }
+
+                self.next();
+                (ValueInfo::BlockCall(BlockCall(block, args)), Type::Void)
+            },
+            "param" => {
+                self.next();
+                let token = self.next();
+
+                let n = match &token.kind {
+                    TokenKind::Number(n) => n.parse().map_err(|_| self.unexpected(&token, "a parameter index"))?,
+                    _ => return Err(self.unexpected(&token, "a parameter index")),
+                };
+
+                self.expect_punct(":")?;
+                (ValueInfo::Param(n), self.ty()?)
+            },
+            _ => match primitive_type(&kind) {
+                Some(ty) if ty.is_integer() => {
+                    self.next();
+                    let token = self.next();
+
+                    match &token.kind {
+                        TokenKind::Number(n) => (ValueInfo::IntegerConstant(n.parse()
+                            .map_err(|_| self.unexpected(&token, "an unsigned integer"))?), ty),
+                        _ => return Err(self.unexpected(&token, "an unsigned integer")),
+                    }
+                },
+                _ => (ValueInfo::Instruction(self.inst()?), Type::Void),
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn named(&mut self) -> Result<Named, ParseError> {
+        let mut named = Named::new(self.name()?);
+
+        loop {
+            let prop = if self.is_punct(".") {
+                self.next();
+                NamedProperty::Basic(self.name()?)
+            } else if self.is_punct("::") {
+                self.next();
+                NamedProperty::Static(self.name()?)
+            } else if self.is_punct("->") {
+                self.next();
+                NamedProperty::Pointer(self.name()?)
+            } else if self.is_punct("[") {
+                self.next();
+                let v = self.value_ref()?;
+                self.expect_punct("]")?;
+                NamedProperty::Index(v)
+            } else {
+                return Ok(named);
+            };
+
+            named.properties.push(prop);
+        }
+    }
+
+}
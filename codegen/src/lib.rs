@@ -1,12 +1,34 @@
 //! The top-level `lib.rs` for the Cardinal code generator.
 
+pub mod data;
+pub mod dce;
+pub mod dominance;
 pub mod entities;
+pub mod error;
+pub mod fold;
 pub mod function;
+pub mod gvn;
 pub mod instbuilder;
 pub mod instruction;
+pub mod interpreter;
 pub mod ir;
+pub mod map;
 pub mod module;
+pub mod pass;
+pub mod structs;
+pub mod verifier;
 
-pub use entities::{AbiType, Block, GlobalVariable, Named, NamedProperty, Type, Value, Variable};
-pub use function::{Function, FunctionSignature};
-pub use module::Module;
\ No newline at end of file
+pub use data::{Constant, GlobalData};
+pub use dce::{DeadCodeElimination, Removed};
+pub use entities::{AbiParam, Block, BlockCall, BlockPath, FunctionAttribute, FunctionType, GlobalVariable, Linkage, Named, NamedProperty, StringEncoding, Type, Value, Variable};
+pub use error::CodegenError;
+pub use fold::ConstantFolding;
+pub use function::{Function, FunctionSignature, ValueTable};
+pub use gvn::GlobalValueNumbering;
+pub use instbuilder::{BuilderError, InstBuilder};
+pub use interpreter::{DataValue, Interpreter, InterpreterError, InterpreterErrorKind};
+pub use map::OrderedMap;
+pub use module::Module;
+pub use pass::{Analyses, Analysis, Pass, PassError, PassManager, PassTiming};
+pub use structs::{StructDef, StructField};
+pub use verifier::{verify, VerifierError};
\ No newline at end of file
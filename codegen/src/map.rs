@@ -0,0 +1,138 @@
+//! A map that keeps its entries in insertion order, so that code generated from it is the
+//! same on every run.
+
+use std::collections::HashMap;
+use std::ops::Index;
+use std::slice;
+
+/// A map from names to values, which iterates over its entries in the order that their names
+/// were first inserted.  Replacing the value of a name keeps its place in the order.
+#[derive(Clone, Debug)]
+pub struct OrderedMap<V> {
+
+    /// The entries of the map, in insertion order.
+    entries: Vec<(String, V)>,
+
+    /// The index of each name in `entries`.
+    indices: HashMap<String, usize>,
+
+}
+
+impl<V> OrderedMap<V> {
+
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self {
+            entries: vec![],
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Inserts a value with the given name, returning the value that it replaced.  A new name
+    /// is added to the end of the order.
+    pub fn insert(&mut self, name: String, value: V) -> Option<V> {
+        match self.indices.get(&name) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[*i].1, value)),
+            None => {
+                self.indices.insert(name.to_string(), self.entries.len());
+                self.entries.push((name, value));
+
+                None
+            },
+        }
+    }
+
+    /// Removes the value with the given name, keeping the order of the other entries.
+    pub fn remove(&mut self, name: &str) -> Option<V> {
+        let i = self.indices.remove(name)?;
+        let (_, value) = self.entries.remove(i);
+
+        for index in self.indices.values_mut() {
+            if *index > i {
+                *index -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    /// Returns the value with the given name, if there is one.
+    pub fn get(&self, name: &str) -> Option<&V> {
+        self.indices.get(name).map(|i| &self.entries[*i].1)
+    }
+
+    /// Returns a mutable reference to the value with the given name, if there is one.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut V> {
+        let i = *self.indices.get(name)?;
+        Some(&mut self.entries[i].1)
+    }
+
+    /// Returns true if the map has a value with the given name.
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.indices.contains_key(name)
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the names and values of the map, in insertion order.
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+
+    /// Iterates over the names of the map, in insertion order.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(name, _)| name)
+    }
+
+    /// Iterates over the values of the map, in insertion order.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    /// Iterates over mutable references to the values of the map, in insertion order.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries.iter_mut().map(|(_, value)| value)
+    }
+
+}
+
+/// An iterator over the names and values of an `OrderedMap`, in insertion order.
+pub type Iter<'a, V> = std::iter::Map<slice::Iter<'a, (String, V)>, fn(&'a (String, V)) -> (&'a String, &'a V)>;
+
+impl<V> Default for OrderedMap<V> {
+
+    fn default() -> Self {
+        Self::new()
+    }
+
+}
+
+impl<V> Index<&str> for OrderedMap<V> {
+
+    type Output = V;
+
+    /// Returns the value with the given name, panicking if there isn't one.
+    fn index(&self, name: &str) -> &V {
+        self.get(name).unwrap_or_else(|| panic!("no entry named `{}`", name))
+    }
+
+}
+
+impl<'a, V> IntoIterator for &'a OrderedMap<V> {
+
+    type Item = (&'a String, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+
+}
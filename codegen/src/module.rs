@@ -2,18 +2,25 @@
 
 //! Exposes types for function declarations and definitions.
 
-use crate::entities::{AbiType, GlobalVariable};
+use crate::data::GlobalData;
+use crate::entities::{GlobalVariable, Linkage, Type};
 use crate::function::{Function, FunctionSignature};
-use std::collections::HashMap;
+use crate::map::OrderedMap;
+use crate::structs::{StructDef, StructField};
 
 // A module that contains Cardinal functions and global data.
+#[derive(Debug)]
 pub struct Module {
 
-    /// A list of functions defined in the module.
-    pub functions: HashMap<String, Function>,
+    /// A list of functions defined in the module, in the order that they were declared.
+    pub functions: OrderedMap<Function>,
 
-    /// A list of global data variables declared in the module.
-    pub data: HashMap<String, AbiType>,
+    /// A list of global data variables declared in the module, in the order that they were
+    /// declared.
+    pub data: OrderedMap<GlobalData>,
+
+    /// A list of structs declared in the module, in the order that they were declared.
+    pub structs: Vec<StructDef>,
 
 }
 
@@ -22,14 +29,16 @@ impl Module {
     /// Creates a new empty module.
     pub fn new() -> Self {
         Self {
-            functions: HashMap::new(),
-            data: HashMap::new(),
+            functions: OrderedMap::new(),
+            data: OrderedMap::new(),
+            structs: vec![],
         }
     }
 
-    /// Declares a function with the specified name.
-    pub fn declare_function(&mut self, name: String) {
-        let func = Function::new(name.to_string(), FunctionSignature::new());
+    /// Declares a function with the specified name and signature, which is defined elsewhere.
+    pub fn declare_function(&mut self, name: String, signature: FunctionSignature) {
+        let mut func = Function::new(name.to_string(), signature);
+        func.linkage = Linkage::Import;
         self.functions.insert(name, func);
     }
 
@@ -39,9 +48,45 @@ impl Module {
     }
 
     /// Declares a variable in the module.
-    pub fn declare_variable(&mut self, name: String, val_type: AbiType) -> GlobalVariable {
-        self.data.insert(name.to_string(), val_type);
+    pub fn declare_variable(&mut self, name: String, val_type: Type) -> GlobalVariable {
+        self.define_variable(name, GlobalData::new(val_type))
+    }
+
+    /// Defines a variable in the module, with the initializer, linkage and constness given by
+    /// `data`.
+    pub fn define_variable(&mut self, name: String, data: GlobalData) -> GlobalVariable {
+        self.data.insert(name.to_string(), data);
         GlobalVariable(name)
     }
 
+    /// Defines a struct with the given fields, replacing any struct with the same name.
+    pub fn define_struct(&mut self, name: String, fields: Vec<StructField>) {
+        self.insert_struct(StructDef::new(name, fields));
+    }
+
+    /// Declares an opaque struct, which has no fields and can only be used behind a pointer.
+    pub fn declare_struct(&mut self, name: String) {
+        self.insert_struct(StructDef::opaque(name));
+    }
+
+    /// Returns the struct with the given name, if it is declared.
+    pub fn get_struct(&self, name: &str) -> Option<&StructDef> {
+        self.structs.iter().find(|s| s.name == name)
+    }
+
+    fn insert_struct(&mut self, def: StructDef) {
+        match self.structs.iter_mut().find(|s| s.name == def.name) {
+            Some(existing) => *existing = def,
+            None => self.structs.push(def),
+        }
+    }
+
+}
+
+impl Default for Module {
+
+    fn default() -> Self {
+        Self::new()
+    }
+
 }
\ No newline at end of file
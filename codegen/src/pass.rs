@@ -0,0 +1,279 @@
+//! Passes that transform a module, and a manager that runs them in order.
+//!
+//! A pass can work on a whole module, on each function or on each block, by overriding the
+//! matching method of `Pass`.  The other methods fall through to it, so a block pass is run
+//! over every block of every function.  Analyses, such as the dominators of a function, are
+//! computed when a pass asks for them and kept until a pass that invalidates them changes the
+//! module.
+
+use crate::dominance::Dominators;
+use crate::function::{Function, ValueTable};
+use crate::instruction::InstBlock;
+use crate::module::Module;
+use crate::verifier::{verify, VerifierError};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// An analysis that passes can share, which the pass manager keeps until it is invalidated.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Analysis {
+
+    /// The dominators of the blocks of each function, which only change when the branches
+    /// between blocks do.
+    Dominators,
+
+}
+
+impl Analysis {
+
+    /// Every analysis, which passes invalidate by default.
+    pub const ALL: &'static [Analysis] = &[Analysis::Dominators];
+
+}
+
+/// The analyses that have been computed for the functions of a module.
+#[derive(Clone, Debug, Default)]
+pub struct Analyses {
+
+    /// The dominators of each function, by name.
+    dominators: HashMap<String, Dominators>,
+
+}
+
+impl Analyses {
+
+    /// Creates an empty set of analyses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the dominators of a function, finding them if they haven't been found since
+    /// they were last invalidated.
+    pub fn dominators(&mut self, func: &Function) -> &Dominators {
+        self.dominators.entry(func.name.to_string())
+            .or_insert_with(|| Dominators::new(func))
+    }
+
+    /// Forgets an analysis for every function, so that it is computed again the next time
+    /// that it is used.
+    pub fn invalidate(&mut self, analysis: Analysis) {
+        match analysis {
+            Analysis::Dominators => self.dominators.clear(),
+        }
+    }
+
+}
+
+/// A transformation of a module.  Each `run_` method returns true if it changed anything.
+///
+/// By default, `run_module` runs `run_function` on every function with blocks, and
+/// `run_function` runs `run_block` on every block of the function, including nested blocks
+/// and their `elif` and `else` branches, so a pass only needs to override the level that it
+/// works at.
+pub trait Pass {
+
+    /// The name of the pass, which is used in timings and errors.
+    fn name(&self) -> &str;
+
+    /// The analyses that the pass invalidates when it changes the module.  Every analysis is
+    /// invalidated by default.
+    fn invalidates(&self) -> &[Analysis] {
+        Analysis::ALL
+    }
+
+    /// Describes what the pass did the last time that it ran, such as what it removed.  The
+    /// pass manager keeps the summary with the timing of the pass.
+    fn summary(&self) -> Option<String> {
+        None
+    }
+
+    /// Runs the pass over a module.
+    fn run_module(&mut self, module: &mut Module, analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+
+        for func in module.functions.values_mut() {
+            if !func.blocks.is_empty() {
+                changed |= self.run_function(func, analyses);
+            }
+        }
+
+        changed
+    }
+
+    /// Runs the pass over a function that has blocks.
+    fn run_function(&mut self, func: &mut Function, _analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        let mut blocks: Vec<&mut InstBlock> = func.blocks.iter_mut().collect();
+
+        while let Some(block) = blocks.pop() {
+            changed |= self.run_block(block, &mut func.values);
+
+            blocks.extend(block.blocks.iter_mut());
+            blocks.extend(block.elses.iter_mut());
+            blocks.extend(block.else_block.as_deref_mut());
+        }
+
+        changed
+    }
+
+    /// Runs the pass over a block.  Nested blocks are run separately, after the blocks that
+    /// they are nested in.
+    fn run_block(&mut self, _block: &mut InstBlock, _values: &mut ValueTable) -> bool {
+        false
+    }
+
+}
+
+/// How long a pass took the last time that the pass manager ran it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PassTiming {
+
+    /// The name of the pass.
+    pub name: String,
+
+    /// The time spent running the pass.
+    pub duration: Duration,
+
+    /// The time spent verifying the module after the pass, which is zero if the verifier
+    /// wasn't run.
+    pub verify: Duration,
+
+    /// Whether the pass changed the module.
+    pub changed: bool,
+
+    /// What the pass reported doing, if anything.
+    pub summary: Option<String>,
+
+}
+
+/// An invalid module found by the verifier while running passes.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PassError {
+
+    /// The name of the pass that produced the invalid module, or `None` if the module was
+    /// already invalid before any pass ran.
+    pub pass: Option<String>,
+
+    /// The errors found by the verifier.
+    pub errors: Vec<VerifierError>,
+
+}
+
+impl fmt::Display for PassError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.pass {
+            Some(pass) => write!(f, "pass `{}` produced an invalid module", pass)?,
+            None => write!(f, "the module was invalid before any pass ran")?,
+        }
+
+        for err in &self.errors {
+            write!(f, "\n{}", err)?;
+        }
+
+        Ok(())
+    }
+
+}
+
+impl Error for PassError {}
+
+/// Runs passes over a module in the order that they were added.
+pub struct PassManager {
+
+    /// The passes to run, in order.
+    passes: Vec<Box<dyn Pass>>,
+
+    /// Whether to run the verifier before the first pass and after every pass that changes
+    /// the module.  It is on by default in debug builds.
+    pub verify: bool,
+
+    /// The timings of the passes from the last run.
+    timings: Vec<PassTiming>,
+
+}
+
+impl PassManager {
+
+    /// Creates a pass manager with no passes.
+    pub fn new() -> Self {
+        Self {
+            passes: vec![],
+            verify: cfg!(debug_assertions),
+            timings: vec![],
+        }
+    }
+
+    /// Adds a pass to run after the passes that have already been added.
+    pub fn add<P: Pass + 'static>(&mut self, pass: P) {
+        self.passes.push(Box::new(pass));
+    }
+
+    /// Returns the names of the passes, in the order that they run.
+    pub fn passes(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Returns how long each pass took during the last run.
+    pub fn timings(&self) -> &[PassTiming] {
+        &self.timings
+    }
+
+    /// Runs every pass over a module, returning true if any of them changed it.  Analyses
+    /// are shared between the passes, and are forgotten when a pass that invalidates them
+    /// changes the module.
+    pub fn run(&mut self, module: &mut Module) -> Result<bool, PassError> {
+        let mut analyses = Analyses::new();
+        let mut changed = false;
+
+        self.timings.clear();
+
+        if self.verify {
+            verify(module).map_err(|errors| PassError { pass: None, errors })?;
+        }
+
+        for pass in &mut self.passes {
+            let start = Instant::now();
+            let pass_changed = pass.run_module(module, &mut analyses);
+            let duration = start.elapsed();
+
+            let mut timing = PassTiming {
+                name: pass.name().to_string(),
+                duration,
+                verify: Duration::default(),
+                changed: pass_changed,
+                summary: pass.summary(),
+            };
+
+            if pass_changed {
+                for analysis in pass.invalidates() {
+                    analyses.invalidate(*analysis);
+                }
+
+                if self.verify {
+                    let start = Instant::now();
+                    let result = verify(module);
+                    timing.verify = start.elapsed();
+
+                    result.map_err(|errors| PassError { pass: Some(timing.name.to_string()), errors })?;
+                }
+            }
+
+            changed |= pass_changed;
+            self.timings.push(timing);
+        }
+
+        Ok(changed)
+    }
+
+}
+
+impl Default for PassManager {
+
+    fn default() -> Self {
+        Self::new()
+    }
+
+}
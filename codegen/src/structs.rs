@@ -0,0 +1,45 @@
+//! Exposes types for struct declarations and definitions.
+
+use crate::entities::Type;
+
+/// A named field of a struct.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StructField(pub String, pub Type);
+
+/// A struct type that can be used by name in the types of variables, arguments and fields.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StructDef {
+
+    /// The name of the struct.
+    pub name: String,
+
+    /// The fields of the struct, in order.  Opaque structs, which are only forward declared,
+    /// have no list of fields.
+    pub fields: Option<Vec<StructField>>,
+
+}
+
+impl StructDef {
+
+    /// Creates a struct definition with the given fields.
+    pub fn new(name: String, fields: Vec<StructField>) -> Self {
+        Self {
+            name,
+            fields: Some(fields),
+        }
+    }
+
+    /// Creates an opaque struct, which can only be used behind a pointer.
+    pub fn opaque(name: String) -> Self {
+        Self {
+            name,
+            fields: None,
+        }
+    }
+
+    /// Returns true if the struct is only forward declared.
+    pub fn is_opaque(&self) -> bool {
+        self.fields.is_none()
+    }
+
+}
@@ -0,0 +1,642 @@
+//! Checks that a module is well formed before it is handed to a backend.
+
+use crate::dominance::Dominators;
+use crate::entities::{Block, BlockPath, BlockStep, NamedProperty, Type, Value, ValueInfo};
+use crate::function::Function;
+use crate::instbuilder::BuilderError;
+use crate::instruction::{BlockType, InstBlock, InstructionInfo, Opcode};
+use crate::module::Module;
+use std::error::Error;
+use std::fmt;
+
+/// The different kinds of problems the verifier can find.
+#[derive(Clone, PartialEq, Debug)]
+pub enum VerifierErrorKind {
+
+    /// A value index that points outside of the function's value table.
+    InvalidValue(Value),
+
+    /// An instruction value that uses a value defined after it in the same block, or itself.
+    UseBeforeDefinition(Value, Value),
+
+    /// A value used in a block that its definition doesn't dominate, such as a value from
+    /// another branch, or from a nested block used outside of it.
+    NotDominated(Value),
+
+    /// An instruction with the wrong number of arguments for its opcode.
+    WrongArgumentCount {
+        opcode: Opcode,
+        expected: &'static str,
+        found: usize,
+    },
+
+    /// A `Jmp`, `Brif` or `Switch` to a block that does not exist in the function, or an
+    /// `Enter` to a block that is not nested in the current block.
+    InvalidBlock(Block),
+
+    /// A `Jmp`, `Brif`, `Switch` or `Enter` whose target is not a block reference.
+    NotABlock(Value),
+
+    /// A conditional or loop block at the top level of a function, which has no enclosing
+    /// block to evaluate its condition in.
+    ConditionalFunctionBlock,
+
+    /// `elif` or `else` branches on a block that is not an `If` block.
+    UnexpectedElse,
+
+    /// A `Break` or `Continue` that is not nested inside of a loop.
+    OutsideOfLoop(Opcode),
+
+    /// A call to a function in the module with a different number of arguments than its
+    /// signature declares.
+    CallArgumentCount {
+        callee: String,
+        expected: usize,
+        found: usize,
+    },
+
+    /// A `Ret` with a value inside of a function that returns `void`.
+    ReturnValueInVoid,
+
+    /// A `Ret` without a value inside of a function that does not return `void`.
+    MissingReturnValue,
+
+    /// A named reference to a variable that is not declared in the function or module.
+    UndeclaredVariable(String),
+
+    /// An instruction whose arguments have types that can't be used together, or a `Ret`
+    /// whose value doesn't have the return type of the function.
+    InvalidTypes(BuilderError),
+
+    /// A named reference whose type is different from the declaration it refers to.
+    NamedType {
+        name: String,
+        expected: Type,
+        found: Type,
+    },
+
+    /// A `Param` value whose index is not a parameter of the block.
+    InvalidParam(u32),
+
+    /// A nested block with parameters, which can't be passed as nested blocks are not branched
+    /// to.
+    NestedBlockParams,
+
+    /// A branch that passes a different number of values than the block has parameters.
+    BlockArgumentCount {
+        block: Block,
+        expected: usize,
+        found: usize,
+    },
+
+    /// A `Switch` case whose value is not an integer constant.
+    NonConstantCase(Value),
+
+    /// A `Switch` with two cases for the same constant.
+    DuplicateCase(u64),
+
+    /// An instruction that follows a terminator in the same block, and can never run.  The
+    /// terminator's opcode is given.
+    AfterTerminator(Opcode),
+
+}
+
+/// An error found by the verifier, along with where it was found.
+#[derive(Clone, PartialEq, Debug)]
+pub struct VerifierError {
+
+    /// The name of the function that the error was found in.
+    pub function: String,
+
+    /// The path to the block that the error was found in, such as `block0` or
+    /// `block0/block1/else`.
+    pub block: String,
+
+    /// The kind of error that was found.
+    pub kind: VerifierErrorKind,
+
+}
+
+impl fmt::Display for VerifierErrorKind {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifierErrorKind::InvalidValue(v) => write!(f, "value v{} is not defined", v.0),
+            VerifierErrorKind::UseBeforeDefinition(v, used) => {
+                write!(f, "value v{} uses v{} before it is defined", v.0, used.0)
+            },
+            VerifierErrorKind::NotDominated(v) => {
+                write!(f, "value v{} is used in a block that its definition doesn't dominate", v.0)
+            },
+            VerifierErrorKind::WrongArgumentCount { opcode, expected, found } => {
+                write!(f, "`{}` expects {} arguments, found {}", opcode.name(), expected, found)
+            },
+            VerifierErrorKind::InvalidBlock(b) => write!(f, "block{} does not exist", b.0),
+            VerifierErrorKind::NotABlock(v) => write!(f, "value v{} is not a block reference", v.0),
+            VerifierErrorKind::ConditionalFunctionBlock => {
+                write!(f, "conditional blocks must be nested inside of another block")
+            },
+            VerifierErrorKind::UnexpectedElse => write!(f, "only `if` blocks can have `else` branches"),
+            VerifierErrorKind::OutsideOfLoop(opcode) => write!(f, "`{}` is not inside of a loop", opcode.name()),
+            VerifierErrorKind::CallArgumentCount { callee, expected, found } => {
+                write!(f, "`{}` takes {} arguments, found {}", callee, expected, found)
+            },
+            VerifierErrorKind::ReturnValueInVoid => write!(f, "a void function returns a value"),
+            VerifierErrorKind::MissingReturnValue => write!(f, "a non-void function returns without a value"),
+            VerifierErrorKind::UndeclaredVariable(name) => write!(f, "`{}` is not declared", name),
+            VerifierErrorKind::InvalidTypes(err) => write!(f, "{}", err),
+            VerifierErrorKind::NamedType { name, expected, found } => {
+                write!(f, "`{}` is declared as `{}`, but used as `{}`", name, expected, found)
+            },
+            VerifierErrorKind::InvalidParam(n) => write!(f, "the block has no parameter {}", n),
+            VerifierErrorKind::NestedBlockParams => write!(f, "only the blocks of a function can have parameters"),
+            VerifierErrorKind::BlockArgumentCount { block, expected, found } => {
+                write!(f, "block{} takes {} arguments, found {}", block.0, expected, found)
+            },
+            VerifierErrorKind::NonConstantCase(v) => write!(f, "switch case v{} is not an integer constant", v.0),
+            VerifierErrorKind::DuplicateCase(n) => write!(f, "switch has more than one case for {}", n),
+            VerifierErrorKind::AfterTerminator(opcode) => {
+                write!(f, "an instruction follows `{}`, which ends the block", opcode.name())
+            },
+        }
+    }
+
+}
+
+impl fmt::Display for VerifierError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "in function `{}`, {}: {}", self.function, self.block, self.kind)
+    }
+
+}
+
+impl Error for VerifierError {}
+
+/// Verifies every function in a module, returning all of the errors that were found in the
+/// order that the functions were declared.
+pub fn verify(module: &Module) -> Result<(), Vec<VerifierError>> {
+    let mut errors = vec![];
+
+    for func in module.functions.values() {
+        let mut verifier = FunctionVerifier {
+            module,
+            func,
+            dominators: Dominators::new(func),
+            location: BlockPath::new(Block(0)),
+            loop_depth: 0,
+            errors: &mut errors,
+        };
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            verifier.location = BlockPath::new(Block(i as u32));
+
+            if !matches!(block.block_type, BlockType::Basic) {
+                verifier.error(VerifierErrorKind::ConditionalFunctionBlock);
+            }
+
+            verifier.verify_block(block);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Verifies the blocks of a single function.
+struct FunctionVerifier<'a> {
+
+    module: &'a Module,
+
+    func: &'a Function,
+
+    /// The dominators of the blocks of the function, which decide where values can be used.
+    dominators: Dominators,
+
+    /// The location of the block currently being verified.
+    location: BlockPath,
+
+    /// The number of loops that the current block is nested in.
+    loop_depth: usize,
+
+    errors: &'a mut Vec<VerifierError>,
+
+}
+
+impl<'a> FunctionVerifier<'a> {
+
+    fn error(&mut self, kind: VerifierErrorKind) {
+        self.errors.push(VerifierError {
+            function: self.func.name.to_string(),
+            block: self.location.to_string(),
+            kind,
+        });
+    }
+
+    /// Returns true if the name is a variable, argument, global or function.
+    fn is_declared(&self, name: &str) -> bool {
+        self.func.variables.contains_key(name)
+            || self.func.signature.arguments.iter().any(|arg| arg.0 == name)
+            || self.module.data.contains_key(name)
+            || self.module.functions.contains_key(name)
+    }
+
+    /// Returns the declared type of a variable, argument, global or function.  Variables and
+    /// arguments shadow the globals and functions of the module.
+    fn declared_type(&self, name: &str) -> Option<Type> {
+        if let Some(ty) = self.func.variables.get(name) {
+            return Some(ty.clone());
+        }
+
+        if let Some(arg) = self.func.signature.arguments.iter().find(|arg| arg.0 == name) {
+            return Some(arg.1.clone());
+        }
+
+        if let Some(global) = self.module.data.get(name) {
+            return Some(global.ty.clone());
+        }
+
+        self.module.functions.get(name).map(|func| func.signature.function_type())
+    }
+
+    /// Checks that a value exists in the function and can be used in the current block,
+    /// returning its information if it exists.
+    fn check_value(&mut self, v: Value) -> Option<&'a ValueInfo> {
+        let at = self.location.clone();
+        self.check_use(v, &at)
+    }
+
+    /// Checks that a value exists in the function and can be used in the block at `at`,
+    /// returning its information if it exists.
+    fn check_use(&mut self, v: Value, at: &BlockPath) -> Option<&'a ValueInfo> {
+        let func = self.func;
+
+        match (func.values.get(v), func.values.block(v)) {
+            (Some(info), Some(def)) => {
+                if !self.dominators.is_available(def, at) {
+                    self.error(VerifierErrorKind::NotDominated(v));
+                }
+
+                Some(info)
+            },
+            _ => {
+                self.error(VerifierErrorKind::InvalidValue(v));
+                None
+            },
+        }
+    }
+
+    /// Checks that a value used by the value `v` exists and is available, and that it isn't
+    /// defined after `v` in the same block.
+    fn check_operand(&mut self, v: Value, arg: Value) {
+        if arg.0 >= v.0 && self.func.values.block(arg) == Some(&self.location) {
+            self.error(VerifierErrorKind::UseBeforeDefinition(v, arg));
+        }
+
+        self.check_value(arg);
+    }
+
+    fn verify_block(&mut self, block: &InstBlock) {
+        for v in &block.values {
+            let v = *v;
+
+            let value = match self.func.values.get(v) {
+                Some(value) => value,
+                None => {
+                    self.error(VerifierErrorKind::InvalidValue(v));
+                    continue;
+                },
+            };
+
+            match value {
+                ValueInfo::Instruction(inst) => {
+                    let errors = self.errors.len();
+
+                    for arg in &inst.arguments {
+                        if arg.0 >= v.0 && self.func.values.block(*arg) == Some(&self.location) {
+                            self.error(VerifierErrorKind::UseBeforeDefinition(v, *arg));
+                        }
+                    }
+
+                    self.verify_inst(inst, block, true);
+
+                    // Types are only checked for instructions that are otherwise well formed.
+                    if self.errors.len() == errors {
+                        self.verify_types(inst, true);
+                    }
+                },
+                ValueInfo::BlockCall(call) => {
+                    for arg in &call.1 {
+                        self.check_operand(v, *arg);
+                    }
+                },
+                ValueInfo::Param(n) if *n as usize >= block.params.len() => {
+                    self.error(VerifierErrorKind::InvalidParam(*n));
+                },
+                ValueInfo::Named(named) => {
+                    for prop in &named.properties {
+                        if let NamedProperty::Index(index) = prop {
+                            self.check_operand(v, *index);
+                        }
+                    }
+
+                    if !self.is_declared(&named.name) && !self.is_callee(v) {
+                        self.error(VerifierErrorKind::UndeclaredVariable(named.name.to_string()));
+                    }
+
+                    if named.properties.is_empty() {
+                        let declared = self.declared_type(&named.name);
+
+                        if let (Some(expected), Some(found)) = (declared, self.func.values.ty(v)) {
+                            if expected != *found {
+                                self.error(VerifierErrorKind::NamedType {
+                                    name: named.name.to_string(),
+                                    expected,
+                                    found: found.clone(),
+                                });
+                            }
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let terminator = block.insts.iter().position(|inst| inst.opcode.is_terminator());
+
+        if let Some(i) = terminator.filter(|i| i + 1 < block.insts.len()) {
+            self.error(VerifierErrorKind::AfterTerminator(block.insts[i].opcode));
+        }
+
+        for inst in &block.insts {
+            let errors = self.errors.len();
+            self.verify_inst(inst, block, false);
+
+            if self.errors.len() == errors {
+                self.verify_types(inst, false);
+            }
+        }
+
+        let location = self.location.clone();
+
+        for (i, nested) in block.blocks.iter().enumerate() {
+            self.location = location.join(BlockStep::Nested(Block(i as u32)));
+            self.verify_nested(nested, &location);
+        }
+
+        self.location = location;
+    }
+
+    /// Verifies a nested block, along with its `elif` and `else` blocks.  Conditions are
+    /// evaluated in the enclosing block at `parent`.
+    fn verify_nested(&mut self, nested: &InstBlock, parent: &BlockPath) {
+        let location = self.location.clone();
+
+        for v in nested.block_type.values() {
+            self.check_use(v, parent);
+        }
+
+        let mut branches = nested.elses.iter().chain(Some(nested)).chain(nested.else_block.as_deref());
+
+        if branches.any(|b| !b.params.is_empty()) {
+            self.error(VerifierErrorKind::NestedBlockParams);
+        }
+
+        let is_if = matches!(nested.block_type, BlockType::If(_));
+
+        if !is_if && (!nested.elses.is_empty() || nested.else_block.is_some()) {
+            self.error(VerifierErrorKind::UnexpectedElse);
+        }
+
+        if nested.block_type.is_loop() {
+            self.loop_depth += 1;
+            self.verify_block(nested);
+            self.loop_depth -= 1;
+        } else {
+            self.verify_block(nested);
+        }
+
+        for (i, elif) in nested.elses.iter().enumerate() {
+            self.location = location.join(BlockStep::Elif(i));
+
+            if let BlockType::If(cond) = elif.block_type {
+                self.check_use(cond, parent);
+            }
+
+            self.verify_block(elif);
+        }
+
+        if let Some(else_block) = &nested.else_block {
+            self.location = location.join(BlockStep::Else);
+            self.verify_block(else_block);
+        }
+
+        self.location = location;
+    }
+
+    /// Returns true if the value is only ever used as the function in a call, by any of the
+    /// values or instructions of the function.
+    fn is_callee(&self, v: Value) -> bool {
+        let mut calls: Vec<&InstructionInfo> = self.func.values.info.iter()
+            .filter_map(|value| match value {
+                ValueInfo::Instruction(inst) => Some(inst),
+                _ => None,
+            })
+            .collect();
+
+        let mut blocks: Vec<&InstBlock> = self.func.blocks.iter().collect();
+
+        while let Some(block) = blocks.pop() {
+            calls.extend(&block.insts);
+            blocks.extend(block.blocks.iter().flat_map(|b| b.elses.iter().chain(Some(b)).chain(b.else_block.as_deref())));
+        }
+
+        let mut used = false;
+
+        for inst in calls {
+            for (i, arg) in inst.arguments.iter().enumerate() {
+                if *arg == v {
+                    if inst.opcode != Opcode::Call || i != 0 {
+                        return false;
+                    }
+
+                    used = true;
+                }
+            }
+        }
+
+        used
+    }
+
+    fn verify_inst(&mut self, inst: &InstructionInfo, block: &InstBlock, is_value: bool) {
+        let found = inst.arguments.len();
+
+        let expected = match inst.opcode {
+            Opcode::BitNot | Opcode::Not | Opcode::Jmp | Opcode::Enter => (1, 1, "1"),
+            Opcode::Brif => (3, 3, "3"),
+            Opcode::Switch => (2, usize::MAX, "at least 2"),
+            Opcode::Call => (1, usize::MAX, "at least 1"),
+            Opcode::Ret => (0, 1, "0 or 1"),
+            Opcode::Break | Opcode::Continue | Opcode::Unreachable => (0, 0, "0"),
+            _ => (2, 2, "2"),
+        };
+
+        if found < expected.0 || found > expected.1 {
+            self.error(VerifierErrorKind::WrongArgumentCount {
+                opcode: inst.opcode,
+                expected: expected.2,
+                found,
+            });
+        }
+
+        let mut infos = vec![];
+
+        for arg in &inst.arguments {
+            infos.push(self.check_value(*arg));
+        }
+
+        match inst.opcode {
+            Opcode::Jmp if found == 1 => self.verify_target(inst, 0, infos[0]),
+            Opcode::Brif if found == 3 => {
+                self.verify_target(inst, 1, infos[1]);
+                self.verify_target(inst, 2, infos[2]);
+            },
+            Opcode::Switch if found >= 2 => {
+                self.verify_target(inst, 1, infos[1]);
+
+                let mut seen = vec![];
+
+                for i in (2..found).step_by(2) {
+                    match infos[i] {
+                        Some(ValueInfo::IntegerConstant(n)) if seen.contains(n) => {
+                            self.error(VerifierErrorKind::DuplicateCase(*n));
+                        },
+                        Some(ValueInfo::IntegerConstant(n)) => seen.push(*n),
+                        Some(_) => self.error(VerifierErrorKind::NonConstantCase(inst.arguments[i])),
+                        None => {},
+                    }
+
+                    match infos.get(i + 1) {
+                        Some(info) => self.verify_target(inst, i + 1, *info),
+                        None => self.error(VerifierErrorKind::WrongArgumentCount {
+                            opcode: Opcode::Switch,
+                            expected: "a block for each case",
+                            found,
+                        }),
+                    }
+                }
+            },
+            Opcode::Enter if found == 1 => {
+                match infos[0] {
+                    Some(ValueInfo::Block(b)) if b.0 as usize >= block.blocks.len() => {
+                        self.error(VerifierErrorKind::InvalidBlock(*b));
+                    },
+                    Some(ValueInfo::Block(_)) | None => {},
+                    Some(_) => self.error(VerifierErrorKind::NotABlock(inst.arguments[0])),
+                }
+            },
+            Opcode::Call if found >= 1 => {
+                if let Some(ValueInfo::Named(named)) = infos[0] {
+                    let callee = if named.properties.is_empty() {
+                        self.module.functions.get(&named.name)
+                    } else {
+                        None
+                    };
+
+                    if let Some(callee) = callee {
+                        let expected = callee.signature.arguments.len();
+
+                        if expected != found - 1 {
+                            self.error(VerifierErrorKind::CallArgumentCount {
+                                callee: named.name.to_string(),
+                                expected,
+                                found: found - 1,
+                            });
+                        }
+                    }
+                }
+            },
+            Opcode::Break | Opcode::Continue if self.loop_depth == 0 => {
+                self.error(VerifierErrorKind::OutsideOfLoop(inst.opcode));
+            },
+            Opcode::Ret if !is_value => {
+                let is_void = self.func.signature.returns == Type::Void;
+
+                if is_void && found > 0 {
+                    self.error(VerifierErrorKind::ReturnValueInVoid);
+                } else if !is_void && found == 0 {
+                    self.error(VerifierErrorKind::MissingReturnValue);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Checks that the `i`th argument of a `Jmp`, `Brif` or `Switch` is a block of the
+    /// function, and that it passes a value of the right type to each of the block's
+    /// parameters.
+    fn verify_target(&mut self, inst: &InstructionInfo, i: usize, info: Option<&ValueInfo>) {
+        let (b, args) = match info.map(ValueInfo::branch_target) {
+            Some(Some(target)) => target,
+            Some(None) => return self.error(VerifierErrorKind::NotABlock(inst.arguments[i])),
+            None => return,
+        };
+
+        let params = match self.func.blocks.get(b.0 as usize) {
+            Some(target) => &target.params,
+            None => return self.error(VerifierErrorKind::InvalidBlock(b)),
+        };
+
+        if args.len() != params.len() {
+            return self.error(VerifierErrorKind::BlockArgumentCount {
+                block: b,
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+
+        for (param, arg) in params.iter().zip(args) {
+            match self.func.values.ty(*arg) {
+                Some(ty) if ty != param => {
+                    self.error(VerifierErrorKind::InvalidTypes(BuilderError::MismatchedTypes {
+                        opcode: inst.opcode,
+                        left: param.clone(),
+                        right: ty.clone(),
+                    }));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Checks that the types of an instruction's arguments can be used together, and that
+    /// a `Ret` returns a value of the function's return type.
+    fn verify_types(&mut self, inst: &InstructionInfo, is_value: bool) {
+        let types: Option<Vec<Type>> = inst.arguments.iter()
+            .map(|v| self.func.values.ty(*v).cloned())
+            .collect();
+
+        let types = match types {
+            Some(types) => types,
+            None => return,
+        };
+
+        if let Err(err) = inst.opcode.result_type(&types) {
+            self.error(VerifierErrorKind::InvalidTypes(err));
+        } else if inst.opcode == Opcode::Ret && !is_value && types.len() == 1 {
+            let returns = &self.func.signature.returns;
+
+            if *returns != Type::Void && *returns != types[0] {
+                self.error(VerifierErrorKind::InvalidTypes(BuilderError::MismatchedTypes {
+                    opcode: Opcode::Ret,
+                    left: returns.clone(),
+                    right: types[0].clone(),
+                }));
+            }
+        }
+    }
+
+}
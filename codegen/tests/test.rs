@@ -1,8 +1,16 @@
 extern crate cardinal_codegen;
 
-use cardinal_codegen::entities::{AbiType, Type};
+use cardinal_codegen::dominance::Dominators;
+use cardinal_codegen::entities::{AbiParam, Block, BlockCall, BlockPath, FunctionType, NamedProperty, StringEncoding, Type, Value, ValueInfo};
 use cardinal_codegen::function::{Function, FunctionSignature};
-use cardinal_codegen::instbuilder::InstBuilder;
+use cardinal_codegen::instbuilder::{BuilderError, InstBuilder};
+use cardinal_codegen::instruction::{InstBlock, InstructionInfo, Opcode};
+use cardinal_codegen::interpreter::Address;
+use cardinal_codegen::ir::{display_block, parse_module};
+use cardinal_codegen::verifier::VerifierErrorKind;
+use cardinal_codegen::{verify, Analyses, Analysis, Constant, ConstantFolding, DataValue, DeadCodeElimination, GlobalData, GlobalValueNumbering, Interpreter, InterpreterErrorKind, Linkage, Module, OrderedMap, Pass, PassManager, StructField, ValueTable};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[cfg(test)]
 mod tests {
@@ -14,22 +22,1486 @@ mod tests {
 
         let mut func = Function::new("main".into(), sig);
 
-        let v = func.declare_var("my_var".into(), AbiType("int".into(), Type::Plain));
+        let v = func.declare_var("my_var".into(), Type::I32);
         
-        let block0;
+        let mut block0;
         {
             let block = func.create_block();
-            block0 = func.use_block(block);
+            block0 = func.use_block(block).unwrap();
         }
 
         {
-            let tmp0 = block0.iconst_int(21);
-            let tmp1 = block0.iconst_int(21);
-            let tmp2 = block0.iadd(tmp0, tmp1);
+            let tmp0 = block0.iconst_int(21, Type::I32);
+            let tmp1 = block0.iconst_int(21, Type::I32);
+            let tmp2 = block0.iadd(tmp0, tmp1).unwrap();
 
-            let tmp3 = block0.iuse(v.named());
-            block0.set(tmp3, tmp2);
+            let tmp3 = block0.iuse(v.named(), Type::I32);
+            block0.set(tmp3, tmp2).unwrap();
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    pub fn test_value_types() {
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let tmp0 = block0.iconst_int(1, Type::I32);
+        let tmp1 = block0.iconst_named("x".into(), Type::I32);
+        let tmp2 = block0.imul(tmp0, tmp1).unwrap();
+
+        assert_eq!(block0.value_type(tmp2), Some(&Type::I32));
+
+        let ptr = block0.iconst_named("ptr".into(), Type::pointer(Type::I32));
+        let half = block0.iconst_float(0.5);
+        let cond = block0.itest_gt(tmp2, tmp2).unwrap();
+
+        assert_eq!(block0.value_type(cond), Some(&Type::Bool));
+        let offset = block0.iadd(ptr, tmp2).unwrap();
+        assert_eq!(block0.value_type(offset), Some(&Type::pointer(Type::I32)));
+        assert_eq!(block0.iadd(ptr, half), Err(BuilderError::MismatchedTypes {
+            opcode: Opcode::Add,
+            left: Type::pointer(Type::I32),
+            right: Type::Float,
+        }));
+        assert_eq!(block0.ibit_and(half, half), Err(BuilderError::InvalidOperand {
+            opcode: Opcode::BitAnd,
+            ty: Type::Float,
+        }));
+        assert_eq!(block0.inot(tmp2), Err(BuilderError::InvalidOperand { opcode: Opcode::Not, ty: Type::I32 }));
+        assert_eq!(block0.icall(tmp2, vec![]), Err(BuilderError::NotAFunction(Type::I32)));
+        assert_eq!(block0.iadd(tmp2, Value(100)), Err(BuilderError::InvalidValue(Value(100))));
+    }
+
+    #[test]
+    pub fn test_terminators() {
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let x = block0.iconst_named("x".into(), Type::I32);
+        block0.return_none().unwrap();
+
+        assert_eq!(block0.insts.len(), 1);
+        assert_eq!(block0.terminator(), Some(Opcode::Ret));
+        assert_eq!(block0.iadd(x, x), Err(BuilderError::AfterTerminator(Opcode::Ret)));
+        assert_eq!(block0.jmp(block), Err(BuilderError::AfterTerminator(Opcode::Ret)));
+        assert_eq!(block0.if_block(x), Err(BuilderError::AfterTerminator(Opcode::Ret)));
+        assert_eq!(block0.unreachable(), Err(BuilderError::AfterTerminator(Opcode::Ret)));
+        assert_eq!((block0.values.len(), block0.blocks.len(), block0.insts.len()), (1, 0, 1));
+
+        let block = func.create_block();
+        let mut block1 = func.use_block(block).unwrap();
+        block1.break_().unwrap();
+        assert_eq!(block1.terminator(), None);
+        block1.unreachable().unwrap();
+        assert_eq!(block1.terminator(), Some(Opcode::Unreachable));
+
+        let block = func.create_block();
+        let mut block2 = func.use_block(block).unwrap();
+        let x = block2.iconst_named("x".into(), Type::I32);
+        let cond = block2.itest_gt(x, x).unwrap();
+
+        assert_eq!(block2.brif(x, Block(0), Block(1)), Err(BuilderError::InvalidOperand {
+            opcode: Opcode::Brif,
+            ty: Type::I32,
+        }));
+        assert_eq!(block2.values.len(), 2);
+        block2.brif(cond, Block(0), Block(1)).unwrap();
+        assert_eq!(block2.terminator(), Some(Opcode::Brif));
+
+        let m = parse_module("
+            function f(x: i32) {
+                block0 {
+                    v0 = named x: i32
+                    v1 = block block1
+                    v2 = block block4
+                    brif v0, v1, v2
+                }
+                block1 {
+                    ret
+                }
+            }
+        ").unwrap();
+
+        let errors: Vec<_> = verify(&m).unwrap_err().into_iter().map(|e| e.kind).collect();
+
+        assert_eq!(errors, vec![
+            VerifierErrorKind::InvalidBlock(Block(4)),
+        ]);
+    }
+
+    #[test]
+    pub fn test_switch() {
+        let mut func = Function::new("main".into(), FunctionSignature::new());
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+
+        let x = block0.iconst_named("x".into(), Type::U8);
+        let half = block0.iconst_float(0.5);
+
+        assert_eq!(block0.switch(half, vec![(1, Block(1))], Block(2)), Err(BuilderError::InvalidOperand {
+            opcode: Opcode::Switch,
+            ty: Type::Float,
+        }));
+        assert_eq!(block0.switch(x, vec![(1, Block(1)), (1, Block(2))], Block(0)), Err(BuilderError::DuplicateCase(1)));
+        assert_eq!(block0.switch(x, vec![(256, Block(1))], Block(0)), Err(BuilderError::CaseOutOfRange {
+            value: 256,
+            ty: Type::U8,
+        }));
+        assert_eq!(block0.values.len(), 2);
+        block0.switch(x, vec![(1, Block(1)), (2, Block(2))], Block(0)).unwrap();
+
+        assert_eq!(block0.terminator(), Some(Opcode::Switch));
+        assert_eq!(block0.insts[0].arguments.len(), 6);
+        assert_eq!(block0.value_type(block0.values[3]), Some(&Type::U8));
+
+        let m = parse_module("
+            function f(x: i32) {
+                block0 {
+                    v0 = named x: i32
+                    v1 = block block0
+                    v2 = i32 1
+                    v3 = i32 1
+                    v4 = add v0, v2
+                    v5 = i32 5
+                    switch v0, v1, v2, v1, v3, v1, v4, v1, v5
+                }
+            }
+        ").unwrap();
+
+        let errors: Vec<_> = verify(&m).unwrap_err().into_iter().map(|e| e.kind).collect();
+
+        assert_eq!(errors, vec![
+            VerifierErrorKind::DuplicateCase(1),
+            VerifierErrorKind::NonConstantCase(Value(4)),
+            VerifierErrorKind::WrongArgumentCount {
+                opcode: Opcode::Switch,
+                expected: "a block for each case",
+                found: 9,
+            },
+        ]);
+    }
+
+    #[test]
+    pub fn test_block_params() {
+        let mut sig = FunctionSignature::new();
+        sig.returns = Type::I32;
+
+        let mut m = Module::new();
+        let mut func = Function::new("count".into(), sig);
+        let entry = func.create_block();
+        let exit = func.create_block();
+
+        let mut block1 = func.use_block(exit).unwrap();
+        let result = block1.append_param(Type::I32);
+        block1.return_(result).unwrap();
+
+        let mut block0 = func.use_block(entry).unwrap();
+        let zero = block0.iconst_int(0, Type::I32);
+        block0.jmp(BlockCall(exit, vec![zero])).unwrap();
+
+        assert!(matches!(block0.value(block0.values[1]), Some(ValueInfo::BlockCall(BlockCall(Block(1), args))) if *args == vec![zero]));
+        assert_eq!(func.blocks[1].params, vec![Type::I32]);
+
+        m.define_function(func);
+        assert_eq!(verify(&m), Ok(()));
+
+        let text = m.to_string();
+        assert!(text.contains("v2 = block block1(v1)\n"));
+        assert!(text.contains("v0 = param 0: i32\n"));
+        assert_eq!(parse_module(&text).unwrap().to_string(), text);
+
+        let err = parse_module("function f() {\n    block0 {\n        v0 = param 1: i32\n    }\n}\n").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 14));
+        assert_eq!(err.message, "expected `param 0`, found `param 1`");
+
+        let invalid = parse_module("
+            function f(x: i32) {
+                block0 {
+                    v0 = named x: i32
+                    v1 = bool true
+                    v2 = block block1(v0)
+                    v3 = block block1(v0, v0, v0)
+                    brif v1, v2, v3
+                    block0 {
+                        v4 = param 0: i32
+                    }
+                }
+
+                block1 {
+                    v5 = param 0: bool
+                    v6 = param 1: i32
+                    v7 = param 2: i32
+                }
+            }
+        ").unwrap();
+
+        let errors: Vec<_> = verify(&invalid).unwrap_err().into_iter().map(|e| (e.block, e.kind)).collect();
+
+        assert_eq!(errors, vec![
+            ("block0".to_string(), VerifierErrorKind::BlockArgumentCount { block: Block(1), expected: 3, found: 1 }),
+            ("block0".to_string(), VerifierErrorKind::InvalidTypes(BuilderError::MismatchedTypes {
+                opcode: Opcode::Brif,
+                left: Type::Bool,
+                right: Type::I32,
+            })),
+            ("block0/block0".to_string(), VerifierErrorKind::NestedBlockParams),
+        ]);
+    }
+
+    #[test]
+    pub fn test_value_numbering() {
+        let mut sig = FunctionSignature::new();
+        sig.arguments.push(AbiParam("x".into(), Type::I32));
+        sig.returns = Type::I32;
+
+        let mut m = Module::new();
+        let mut func = Function::new("f".into(), sig);
+        let entry = func.create_block();
+        let exit = func.create_block();
+
+        let mut block0 = func.use_block(entry).unwrap();
+        let x = block0.iconst_named("x".into(), Type::I32);
+        let one = block0.iconst_int(1, Type::I32);
+        let sum = block0.iadd(x, one).unwrap();
+        let cond = block0.itest_gt(sum, one).unwrap();
+        let inner = block0.if_block(cond).unwrap();
+
+        let hidden = {
+            let mut nested = block0.use_block(inner).unwrap();
+            assert_eq!(nested.path().to_string(), "block0/block0");
+
+            // The values of the enclosing block can be used in a nested block.
+            nested.iadd(sum, one).unwrap()
+        };
+
+        {
+            let mut else_block = block0.else_block(inner).unwrap();
+            assert_eq!(else_block.path().to_string(), "block0/block0/else");
+            else_block.return_(sum).unwrap();
+        }
+
+        block0.jmp(exit).unwrap();
+
+        let mut block1 = func.use_block(exit).unwrap();
+        let two = block1.iconst_int(2, Type::I32);
+        let product = block1.imul(sum, two).unwrap();
+        block1.return_(product).unwrap();
+
+        // Values are numbered across the function, and remember the block that defined them.
+        assert_eq!((hidden, two, product), (Value(5), Value(7), Value(8)));
+        assert_eq!(func.blocks[1].values, vec![two, product]);
+        assert_eq!(func.values.block(hidden).unwrap().to_string(), "block0/block0");
+        assert_eq!(func.values.block(product), Some(&BlockPath::new(exit)));
+
+        let dominators = Dominators::new(&func);
+        assert!(dominators.dominates(entry, exit));
+        assert!(!dominators.dominates(exit, entry));
+
+        m.define_function(func);
+        assert_eq!(verify(&m), Ok(()));
+
+        let text = m.to_string();
+        assert!(text.contains("    block1 {\n        v7 = i32 2\n        v8 = mul v2, v7\n"));
+        assert_eq!(parse_module(&text).unwrap().to_string(), text);
+
+        let invalid = parse_module("
+            function g(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = bool true
+                    v2 = block block0
+                    enter v2
+                    v3 = block block1
+                    v4 = block block2
+                    brif v1, v3, v4
+                    block0 if v1 {
+                        v5 = i32 1
+                    } else {
+                        ret v5
+                    }
+                }
+
+                block1 {
+                    v6 = add v0, v5
+                    ret v7
+                }
+
+                block2 {
+                    v7 = i32 2
+                    ret v0
+                }
+            }
+        ").unwrap();
+
+        let errors = verify(&invalid).unwrap_err();
+        let kinds: Vec<_> = errors.iter().map(|e| (e.block.as_str(), e.kind.clone())).collect();
+
+        assert_eq!(kinds, vec![
+            ("block0/block0/else", VerifierErrorKind::NotDominated(Value(5))),
+            ("block1", VerifierErrorKind::NotDominated(Value(5))),
+            ("block1", VerifierErrorKind::NotDominated(Value(7))),
+        ]);
+        assert_eq!(errors[2].to_string(), "in function `g`, block1: value v7 is used in a block that its definition doesn't dominate");
+    }
+
+    #[test]
+    pub fn test_ordered_map() {
+        let mut map = OrderedMap::new();
+        map.insert("zeta".to_string(), 1);
+        map.insert("alpha".to_string(), 2);
+        map.insert("mid".to_string(), 3);
+
+        // Replacing a value keeps its place in the order.
+        assert_eq!(map.insert("zeta".to_string(), 4), Some(1));
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["zeta", "alpha", "mid"]);
+        assert_eq!(map["zeta"], 4);
+
+        assert_eq!(map.remove("alpha"), Some(2));
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&"zeta".to_string(), &4), (&"mid".to_string(), &3)]);
+        assert_eq!(map.get("mid"), Some(&3));
+        assert!(!map.contains_key("alpha"));
+
+        // Functions, globals and variables are printed in the order that they were declared.
+        let mut m = Module::new();
+        m.declare_variable("zeta".into(), Type::I32);
+        m.declare_variable("alpha".into(), Type::I32);
+
+        let mut func = Function::new("zeta".into(), FunctionSignature::new());
+        func.declare_var("z".into(), Type::I32);
+        func.declare_var("a".into(), Type::I32);
+        m.define_function(func);
+        m.declare_function("alpha".into(), FunctionSignature::new());
+
+        assert_eq!(m.to_string(), [
+            "global zeta: i32",
+            "global alpha: i32",
+            "",
+            "function zeta() -> void {",
+            "    var z: i32",
+            "    var a: i32",
+            "}",
+            "",
+            "function alpha() -> void",
+            "",
+        ].join("\n"));
+        assert_eq!(parse_module(&m.to_string()).unwrap().to_string(), m.to_string());
+    }
+
+    #[test]
+    pub fn test_ir_round_trip() {
+        let mut m = Module::new();
+
+        let mut sig = FunctionSignature::new();
+        sig.returns = Type::U32;
+
+        let mut func = Function::new("main".into(), sig);
+        let v = func.declare_var("my_var".into(), Type::U32);
+
+        let block = func.create_block();
+        let mut block0 = func.use_block(block).unwrap();
+        block0.require_import("stdio.h".into());
+
+        let tmp0 = block0.iconst_int(21, Type::U32);
+        let tmp1 = block0.iconst_int(2, Type::U32);
+        let tmp2 = block0.iadd(tmp0, tmp1).unwrap();
+        let tmp3 = block0.iuse(v.named(), Type::U32);
+        block0.set(tmp3, tmp2).unwrap();
+
+        let printf = block0.iconst_named("printf".into(), Type::Function(FunctionType {
+            params: vec![Type::pointer(Type::Char)],
+            returns: Box::new(Type::I32),
+            variadic: true,
+        }));
+        let str = block0.iconst_str("%d \"quoted\"\n".into());
+        let index = block0.iconst_int(0, Type::I32);
+        let prop = block0.iconst_named_index(index);
+        let field = block0.iconst_named_props("args".into(), vec![
+            NamedProperty::Pointer("items".into()),
+            prop,
+        ], Type::Double);
+        block0.call(printf, vec![str, field]).unwrap();
+        block0.return_(tmp0).unwrap();
+
+        m.define_function(func);
+        m.declare_variable("counter".into(), Type::array(Type::I32, Some(4)));
+        m.declare_struct("handle".into());
+
+        let mut origin = GlobalData::new(Type::Struct("point".into()));
+        origin.linkage = Linkage::Internal;
+        origin.constant = true;
+        origin.init = Some(Constant::Struct(vec![
+            ("x".into(), Constant::Integer(0)),
+            ("owner".into(), Constant::Array(vec![Constant::Double(0.5), Constant::Boolean(true)])),
+        ]));
+        m.define_variable("origin".into(), origin);
+        m.define_struct("point".into(), vec![
+            StructField("x".into(), Type::I32),
+            StructField("owner".into(), Type::pointer(Type::Struct("handle".into()))),
+        ]);
+
+        // A function may be named after a linkage keyword.
+        let mut helper = Function::new("internal".into(), FunctionSignature::new());
+        helper.linkage = Linkage::Internal;
+        let block = helper.create_block();
+        helper.use_block(block).unwrap().return_none().unwrap();
+        m.define_function(helper);
+        m.declare_function("exit".into(), FunctionSignature::new());
+
+        let mut wide = GlobalData::new(Type::pointer(Type::I32));
+        wide.init = Some(Constant::String("\u{e9}".into(), StringEncoding::Wide));
+        m.define_variable("wide".into(), wide);
+
+        let mut raw = GlobalData::new(Type::pointer(Type::Char));
+        raw.init = Some(Constant::Bytes(vec![0, b'a', 0xff]));
+        m.define_variable("raw".into(), raw);
+
+        let text = m.to_string();
+        let parsed = parse_module(&text).unwrap();
+
+        assert_eq!(parsed.to_string(), text);
+        assert!(text.starts_with("struct handle\nstruct point {\n    x: i32\n    owner: handle*\n}\n"));
+        assert!(text.contains("global internal const origin: point = { x = int 0, owner = [double 0.5, bool true] }\n"));
+        assert!(text.contains("var my_var: u32"));
+        assert!(text.contains("v0 = u32 21\n"));
+        assert!(text.contains("v2 = add v0, v1"));
+        assert!(text.contains("v3 = named my_var: u32\n"));
+        assert!(text.contains("v4 = named printf: fn(char*, ...) -> i32\n"));
+        assert!(text.contains("v5 = str \"%d \\\"quoted\\\"\\n\""));
+        assert!(text.contains("v7 = named args->items[v6]: double\n"));
+        assert_eq!(parsed.functions["main"].values.types, m.functions["main"].values.types);
+        assert!(text.contains("call v4, v5, v7"));
+        assert!(text.contains("function internal internal() -> void {\n"));
+        assert!(text.contains("function exit() -> void\n"));
+        assert!(text.contains("global wide: i32* = str wide \"\u{e9}\"\n"));
+        assert!(text.contains("global raw: char* = bytes \"\\0a\\u{ff}\"\n"));
+        assert_eq!(parsed.functions["internal"].linkage, Linkage::Internal);
+        assert_eq!(parsed.functions["exit"].linkage, Linkage::Import);
+        assert_eq!(parsed.functions["main"].linkage, Linkage::Export);
+    }
+
+    #[test]
+    pub fn test_ir_parse_nested_blocks() {
+        let src = "
+            ; A hand written function.
+            function abs(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = i32 0
+                    v2 = test_lt v0, v1
+                    block0 if v2 {
+                        v3 = sub v1, v0
+                        ret v3
+                    } elif v2 {
+                    } else {
+                        ret v0
+                    }
+                }
+            }
+
+            function puts(s: char*) -> i32
+        ";
+
+        let m = parse_module(src).unwrap();
+        let func = &m.functions["abs"];
+        let nested = &func.blocks[0].blocks[0];
+
+        assert_eq!(nested.insts.len(), 1);
+        assert_eq!(nested.elses.len(), 1);
+        assert!(nested.else_block.is_some());
+        assert!(m.functions["puts"].blocks.is_empty());
+        assert_eq!(parse_module(&m.to_string()).unwrap().to_string(), m.to_string());
+    }
+
+    #[test]
+    pub fn test_ir_display_block() {
+        let src = "
+            function abs(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = i32 0
+                    v2 = test_lt v0, v1
+                    v3 = block block1
+                    brif v2, v3, v3
+                }
+
+                block1 {
+                    import \"stdlib.h\"
+                    v4 = bool true
+                    v5 = block block0
+                    enter v5
+                    ret v0
+                    block0 if v4 {
+                        v6 = sub v1, v0
+                        ret v6
+                    } else {
+                        unreachable
+                    }
+                }
+            }
+        ";
+
+        let m = parse_module(src).unwrap();
+        let func = &m.functions["abs"];
+        let nested = &func.blocks[1].blocks[0];
+
+        let text = display_block(func, &func.blocks[1]).to_string();
+        assert!(text.starts_with("block1 {\n    import \"stdlib.h\"\n    v4 = bool true\n"));
+        assert!(text.ends_with("    block0 if v4 {\n        v6 = sub v1, v0\n        ret v6\n    } else {\n        unreachable\n    }\n}\n"));
+        assert_eq!(display_block(func, nested.else_block.as_deref().unwrap()).to_string(), "block0 {\n    unreachable\n}\n");
+
+        // The blocks of a function, written out one by one, read back as the same function.
+        let blocks: Vec<String> = func.blocks.iter()
+            .map(|block| display_block(func, block).to_string().lines().map(|line| format!("    {}\n", line)).collect())
+            .collect();
+
+        let text = format!("function abs(x: i32) -> i32 {{\n{}}}\n", blocks.join("\n"));
+        assert_eq!(text, func.to_string());
+        assert_eq!(parse_module(&text).unwrap().to_string(), m.to_string());
+    }
+
+    #[test]
+    pub fn test_ir_parse_errors() {
+        let err = parse_module("function f() {\n    block0 {\n        v1 = i32 3\n    }\n}\n").unwrap_err();
+        assert_eq!((err.line, err.column), (5, 1));
+        assert_eq!(err.message, "value v0 is not defined");
+
+        let err = parse_module("function f() {\n    block0 {\n        v0 = i32 3\n        v0 = i32 4\n    }\n}\n").unwrap_err();
+        assert_eq!((err.line, err.column), (4, 9));
+        assert_eq!(err.message, "value v0 is already defined");
+
+        let err = parse_module("function f() {\n    block0 {\n        frob v0\n    }\n}\n").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 9));
+        assert_eq!(err.message, "unknown opcode `frob`");
+
+        let err = parse_module("global x int\n").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 10));
+
+        let err = parse_module("function f() {\n    block0 {\n        v0 = str \"abc\n").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 18));
+        assert_eq!(err.message, "unterminated string");
+
+        let err = parse_module("global x: char* = bytes \"\\u{100}\"\n").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 25));
+        assert_eq!(err.message, "byte strings can only hold characters up to `\\u{ff}`");
+    }
+
+    #[test]
+    pub fn test_verifier() {
+        let valid = parse_module("
+            function add(a: i32, b: i32) -> i32 {
+                block0 {
+                    v0 = named a: i32
+                    v1 = named b: i32
+                    v2 = add v0, v1
+                    ret v2
+                }
+            }
+
+            function main() {
+                var x: i32
+
+                block0 {
+                    v0 = named x: i32
+                    v1 = named add: fn(i32, i32) -> i32
+                    v2 = i32 1
+                    v3 = call v1, v2, v2
+                    v4 = named printf: fn(i32) -> void
+                    v5 = block block0
+                    set v0, v3
+                    call v4, v0
+                    jmp v5
+                }
+            }
+        ").unwrap();
+
+        assert_eq!(verify(&valid), Ok(()));
+
+        let invalid = parse_module("
+            function add(a: i32, b: i32) -> i32 {
+                block0 {
+                    v0 = named a: i32
+                    v1 = add v0, v2
+                    v2 = not v0, v1
+                    ret
+                }
+            }
+
+            function main() {
+                block0 {
+                    v0 = named add: fn(i32, i32) -> i32
+                    v1 = i32 1
+                    v2 = block block3
+                    v3 = named y: i32
+                    call v0, v1
+                    jmp v2
+                    jmp v1
+                    set v3, v9
+                    ret v1
+                }
+            }
+        ").unwrap();
+
+        let errors: Vec<_> = verify(&invalid).unwrap_err().into_iter()
+            .map(|e| (e.function, e.kind))
+            .collect();
+
+        assert_eq!(errors, vec![
+            ("add".to_string(), VerifierErrorKind::UseBeforeDefinition(Value(1), Value(2))),
+            ("add".to_string(), VerifierErrorKind::WrongArgumentCount {
+                opcode: Opcode::Not,
+                expected: "1",
+                found: 2,
+            }),
+            ("add".to_string(), VerifierErrorKind::MissingReturnValue),
+            ("main".to_string(), VerifierErrorKind::UndeclaredVariable("y".into())),
+            ("main".to_string(), VerifierErrorKind::AfterTerminator(Opcode::Jmp)),
+            ("main".to_string(), VerifierErrorKind::CallArgumentCount {
+                callee: "add".into(),
+                expected: 2,
+                found: 1,
+            }),
+            ("main".to_string(), VerifierErrorKind::InvalidBlock(Block(3))),
+            ("main".to_string(), VerifierErrorKind::NotABlock(Value(1))),
+            ("main".to_string(), VerifierErrorKind::InvalidValue(Value(9))),
+            ("main".to_string(), VerifierErrorKind::ReturnValueInVoid),
+        ]);
+
+        let mistyped = parse_module("
+            function f(a: i32) -> bool {
+                block0 {
+                    v0 = named a: u8
+                    v1 = named a: i32
+                    v2 = double 1.5
+                    v3 = add v1, v2
+                    ret v1
+                }
+            }
+        ").unwrap();
+
+        let errors: Vec<_> = verify(&mistyped).unwrap_err().into_iter().map(|e| e.kind).collect();
+
+        assert_eq!(errors, vec![
+            VerifierErrorKind::NamedType { name: "a".into(), expected: Type::I32, found: Type::U8 },
+            VerifierErrorKind::InvalidTypes(BuilderError::MismatchedTypes {
+                opcode: Opcode::Add,
+                left: Type::I32,
+                right: Type::Double,
+            }),
+            VerifierErrorKind::InvalidTypes(BuilderError::MismatchedTypes {
+                opcode: Opcode::Ret,
+                left: Type::Bool,
+                right: Type::I32,
+            }),
+        ]);
+        assert_eq!(errors[1].to_string(), "`add` expects matching types, found `i32` and `double`");
+    }
+
+    #[test]
+    pub fn test_loop_blocks() {
+        let src = "
+            function main() {
+                var i: i32
+
+                block0 {
+                    v0 = named i: i32
+                    v1 = i32 0
+                    v2 = set v0, v1
+                    v3 = test_lt v0, v1
+                    enter v4
+                    v4 = block block0
+                    break
+                    block0 for v2, v3, _ {
+                        v5 = bool true
+                        block0 do_while v5 {
+                            continue
+                        }
+                        break
+                    }
+                    block1 while v3 {
+                    } else {
+                    }
+                }
+            }
+        ";
+
+        let m = parse_module(src).unwrap();
+        let nested = &m.functions["main"].blocks[0].blocks[0];
+
+        assert_eq!(parse_module(&m.to_string()).unwrap().to_string(), m.to_string());
+        assert!(nested.block_type.is_loop());
+        assert_eq!(nested.block_type.values(), vec![Value(2), Value(3)]);
+
+        let errors: Vec<_> = verify(&m).unwrap_err().into_iter().map(|e| (e.block, e.kind)).collect();
+
+        assert_eq!(errors, vec![
+            ("block0".to_string(), VerifierErrorKind::OutsideOfLoop(Opcode::Break)),
+            ("block0/block1".to_string(), VerifierErrorKind::UnexpectedElse),
+        ]);
+    }
+
+    #[test]
+    pub fn test_interpreter() {
+        let m = parse_module("
+            struct point {
+                x: i32
+                y: i32
+            }
+
+            global origin: point = { x = int 3, y = int 4 }
+            global greeting: char* = str \"hi\"
+            global counter: i32
+
+            function fib(n: i32) -> i32 {
+                block0 {
+                    v0 = i32 0
+                    v1 = i32 1
+                    v2 = named n: i32
+                    v3 = block block1(v0, v1, v2)
+                    jmp v3
+                }
+
+                block1 {
+                    v4 = param 0: i32
+                    v5 = param 1: i32
+                    v6 = param 2: i32
+                    v7 = i32 0
+                    v8 = test_eq v6, v7
+                    v9 = block block2
+                    v10 = add v4, v5
+                    v11 = i32 1
+                    v12 = sub v6, v11
+                    v13 = block block1(v5, v10, v12)
+                    brif v8, v9, v13
+                }
+
+                block2 {
+                    ret v4
+                }
+            }
+
+            function sum(n: i32) -> i32 {
+                var total: i32
+                var i: i32
+
+                block0 {
+                    v0 = named total: i32
+                    v1 = named i: i32
+                    v2 = named n: i32
+                    v3 = i32 0
+                    v4 = i32 1
+                    set v0, v3
+                    v5 = set v1, v3
+                    v6 = test_lt v1, v2
+                    v7 = add v1, v4
+                    v8 = set v1, v7
+                    v9 = block block0
+                    enter v9
+                    ret v0
+                    block0 for v5, v6, v8 {
+                        v10 = add v0, v1
+                        set v0, v10
+                    }
+                }
+            }
+
+            function sign(x: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = i32 0
+                    v2 = test_lt v0, v1
+                    v3 = test_gt v0, v1
+                    block0 if v2 {
+                        v4 = i32 18446744073709551615
+                        ret v4
+                    } elif v3 {
+                        v5 = i32 1
+                        ret v5
+                    } else {
+                        ret v1
+                    }
+                }
+            }
+
+            function length(s: char*) -> isize {
+                var p: char*
+
+                block0 {
+                    v0 = named p: char*
+                    v1 = named s: char*
+                    set v0, v1
+                    v2 = i64 0
+                    v3 = named p[v2]: char
+                    v4 = char \"\\0\"
+                    v5 = test_neq v3, v4
+                    v6 = i64 1
+                    v7 = block block0
+                    enter v7
+                    v8 = sub v0, v1
+                    ret v8
+                    block0 while v5 {
+                        v9 = add v0, v6
+                        set v0, v9
+                    }
+                }
+            }
+
+            function greet(count: i32) -> i32 {
+                block0 {
+                    v0 = named printf: fn(char*, ...) -> i32
+                    v1 = str \"%s %d\\n\"
+                    v2 = named greeting: char*
+                    v3 = named count: i32
+                    v4 = call v0, v1, v2, v3
+                    v5 = named counter: i32
+                    v6 = named origin.y: i32
+                    v7 = add v3, v6
+                    set v5, v7
+                    ret v4
+                }
+            }
+        ").unwrap();
+
+        assert_eq!(verify(&m), Ok(()));
+
+        let mut output = String::new();
+
+        {
+            let mut interpreter = Interpreter::new(&m);
+
+            interpreter.define_host("printf", |memory, args| {
+                let format = String::from_utf8(memory.read_string(&args[0])?).unwrap();
+                let mut args = args[1..].iter();
+                let mut parts = format.split('%');
+                let mut text = parts.next().unwrap_or("").to_string();
+
+                for part in parts {
+                    match (part.chars().next(), args.next()) {
+                        (Some('s'), Some(arg)) => text += &String::from_utf8(memory.read_string(arg)?).unwrap(),
+                        (Some('d'), Some(arg)) => text += &arg.as_int().unwrap().to_string(),
+                        _ => return Err(InterpreterErrorKind::Host("bad format".into())),
+                    }
+
+                    text += &part[1..];
+                }
+
+                output += &text;
+                Ok(DataValue::I32(text.len() as i32))
+            });
+
+            assert_eq!(interpreter.call("fib", &[DataValue::I32(10)]), Ok(DataValue::I32(55)));
+            assert_eq!(interpreter.call("sum", &[DataValue::I32(5)]), Ok(DataValue::I32(10)));
+            assert_eq!(interpreter.call("sum", &[DataValue::I32(0)]), Ok(DataValue::I32(0)));
+            assert_eq!(interpreter.call("sign", &[DataValue::I32(-7)]), Ok(DataValue::I32(-1)));
+            assert_eq!(interpreter.call("sign", &[DataValue::I32(7)]), Ok(DataValue::I32(1)));
+            assert_eq!(interpreter.call("sign", &[DataValue::I32(0)]), Ok(DataValue::I32(0)));
+
+            let hello = interpreter.memory.allocate_string(b"hello");
+            assert_eq!(interpreter.call("length", &[hello]), Ok(DataValue::ISize(5)));
+
+            assert_eq!(interpreter.call("greet", &[DataValue::I32(2)]), Ok(DataValue::I32(5)));
+            assert_eq!(interpreter.global("counter"), Ok(DataValue::I32(6)));
+            assert_eq!(interpreter.global("origin"), Ok(DataValue::Struct("point".into(), vec![DataValue::I32(3), DataValue::I32(4)])));
+        }
+
+        assert_eq!(output, "hi 2\n");
+    }
+
+    #[test]
+    pub fn test_interpreter_errors() {
+        let m = parse_module("
+            function divide(a: i32, b: i32) -> i32 {
+                block0 {
+                    v0 = named a: i32
+                    v1 = named b: i32
+                    v2 = div v0, v1
+                    ret v2
+                }
+            }
+
+            function spin() {
+                block0 {
+                    v0 = bool true
+                    block0 while v0 {
+                    }
+                }
+            }
+
+            function missing() -> i32 {
+                block0 {
+                    v0 = bool false
+                    block0 if v0 {
+                        unreachable
+                    }
+                }
+            }
+
+            function recurse() {
+                block0 {
+                    v0 = named recurse: fn() -> void
+                    call v0
+                }
+            }
+
+            function shout() -> i32 {
+                block0 {
+                    v0 = named puts: fn(char*) -> i32
+                    v1 = bytes \"!\"
+                    v2 = call v0, v1
+                    ret v2
+                }
+            }
+        ").unwrap();
+
+        let mut interpreter = Interpreter::new(&m);
+
+        assert_eq!(interpreter.call("divide", &[DataValue::I32(7), DataValue::I32(2)]), Ok(DataValue::I32(3)));
+        assert_eq!(interpreter.call("divide", &[DataValue::I32(i32::MIN), DataValue::I32(-1)]), Ok(DataValue::I32(i32::MIN)));
+
+        let err = interpreter.call("divide", &[DataValue::I32(1), DataValue::I32(0)]).unwrap_err();
+        assert_eq!(err.kind, InterpreterErrorKind::DivisionByZero);
+        assert_eq!(err.to_string(), "in function `divide`, block0: division by zero");
+
+        let err = interpreter.call("divide", &[DataValue::I32(1), DataValue::I64(0)]).unwrap_err();
+        assert_eq!(err.kind, InterpreterErrorKind::ArgumentType { index: 1, expected: Type::I32 });
+        assert_eq!(err.to_string(), "in function `divide`: argument 1 is not a `i32`");
+
+        let err = interpreter.call("divide", &[]).unwrap_err();
+        assert_eq!(err.kind, InterpreterErrorKind::ArgumentCount { expected: 2, found: 0 });
+
+        interpreter.step_limit = Some(1000);
+        let err = interpreter.call("spin", &[]).unwrap_err();
+        assert_eq!((err.block.as_str(), err.kind), ("block0", InterpreterErrorKind::StepLimit));
+
+        assert_eq!(interpreter.call("missing", &[]).unwrap_err().kind, InterpreterErrorKind::MissingReturn);
+        assert_eq!(interpreter.call("recurse", &[]).unwrap_err().kind, InterpreterErrorKind::CallDepth);
+        assert_eq!(interpreter.call("shout", &[]).unwrap_err().kind, InterpreterErrorKind::UnknownFunction("puts".into()));
+
+        interpreter.define_host("puts", |_, _| Ok(DataValue::I64(0)));
+        let err = interpreter.call("shout", &[]).unwrap_err();
+        assert_eq!((err.function.as_str(), err.kind), ("puts", InterpreterErrorKind::ReturnType(Type::I32)));
+
+        interpreter.define_host("puts", |memory, args| {
+            assert_eq!(memory.read_string(&args[0])?, b"!");
+            Ok(DataValue::I32(2))
+        });
+        assert_eq!(interpreter.call("shout", &[]), Ok(DataValue::I32(2)));
+
+        let dangling = DataValue::Pointer(Some(Address { object: 1000, path: vec![0] }));
+        assert_eq!(interpreter.memory.read_string(&dangling), Err(InterpreterErrorKind::InvalidPointer));
+    }
+
+    /// Records whether block1 of each function dominates block2, using the shared analyses.
+    struct Probe(Rc<RefCell<Vec<bool>>>);
+
+    impl Pass for Probe {
+
+        fn name(&self) -> &str {
+            "probe"
+        }
+
+        fn invalidates(&self) -> &[Analysis] {
+            &[]
+        }
+
+        fn run_function(&mut self, func: &mut Function, analyses: &mut Analyses) -> bool {
+            self.0.borrow_mut().push(analyses.dominators(func).dominates(Block(1), Block(2)));
+            false
+        }
+
+    }
+
+    /// Replaces the `brif` of block0 with a jump to block1, claiming to preserve the given
+    /// analyses.
+    struct Rewire(&'static [Analysis]);
+
+    impl Pass for Rewire {
+
+        fn name(&self) -> &str {
+            "rewire"
+        }
+
+        fn invalidates(&self) -> &[Analysis] {
+            self.0
+        }
+
+        fn run_function(&mut self, func: &mut Function, _analyses: &mut Analyses) -> bool {
+            let inst = func.blocks[0].insts.last_mut().unwrap();
+            *inst = InstructionInfo { opcode: Opcode::Jmp, arguments: vec![inst.arguments[1]] };
+            true
+        }
+
+    }
+
+    /// Counts the blocks that it visits, and breaks the module if `invalid` is set.
+    struct Blocks(Rc<RefCell<usize>>, bool);
+
+    impl Pass for Blocks {
+
+        fn name(&self) -> &str {
+            "blocks"
+        }
+
+        fn run_block(&mut self, block: &mut InstBlock, _values: &mut ValueTable) -> bool {
+            *self.0.borrow_mut() += 1;
+
+            if self.1 {
+                block.insts.push(InstructionInfo { opcode: Opcode::Ret, arguments: vec![Value(99)] });
+            }
+
+            self.1
+        }
+
+    }
+
+    #[test]
+    pub fn test_pass_manager() {
+        let src = "
+            function f() {
+                block0 {
+                    v0 = bool true
+                    v1 = block block1
+                    v2 = block block2
+                    brif v0, v1, v2
+                }
+
+                block1 {
+                    v3 = block block2
+                    jmp v3
+                    block0 if v0 {
+                    } else {
+                    }
+                }
+
+                block2 {
+                    ret
+                }
+            }
+
+            function puts(s: char*) -> i32
+        ";
+
+        let probes = Rc::new(RefCell::new(vec![]));
+        let blocks = Rc::new(RefCell::new(0));
+
+        let mut manager = PassManager::new();
+        manager.verify = true;
+        manager.add(Probe(probes.clone()));
+        manager.add(Rewire(Analysis::ALL));
+        manager.add(Probe(probes.clone()));
+        manager.add(Blocks(blocks.clone(), false));
+
+        let mut m = parse_module(src).unwrap();
+        assert_eq!(manager.passes(), vec!["probe", "rewire", "probe", "blocks"]);
+        assert_eq!(manager.run(&mut m), Ok(true));
+        assert_eq!(*probes.borrow(), vec![false, true]);
+        assert_eq!(*blocks.borrow(), 5);
+        assert_eq!(m.functions["f"].blocks[0].insts[0].opcode, Opcode::Jmp);
+
+        let timings: Vec<_> = manager.timings().iter().map(|t| (t.name.as_str(), t.changed)).collect();
+        assert_eq!(timings, vec![("probe", false), ("rewire", true), ("probe", false), ("blocks", false)]);
+
+        // A pass that claims to preserve the dominators leaves them stale.
+        let probes = Rc::new(RefCell::new(vec![]));
+        let mut manager = PassManager::new();
+        manager.add(Probe(probes.clone()));
+        manager.add(Rewire(&[]));
+        manager.add(Probe(probes.clone()));
+
+        let mut m = parse_module(src).unwrap();
+        assert_eq!(manager.run(&mut m), Ok(true));
+        assert_eq!(*probes.borrow(), vec![false, false]);
+
+        let mut manager = PassManager::new();
+        manager.verify = true;
+        manager.add(Blocks(blocks.clone(), true));
+
+        let mut m = parse_module(src).unwrap();
+        let err = manager.run(&mut m).unwrap_err();
+        assert_eq!(err.pass.as_deref(), Some("blocks"));
+        assert_eq!(err.errors[0].to_string(), "in function `f`, block0: an instruction follows `brif`, which ends the block");
+        assert!(err.to_string().starts_with("pass `blocks` produced an invalid module\n"));
+
+        let err = manager.run(&mut m).unwrap_err();
+        assert_eq!(err.pass, None);
+        assert!(manager.timings().is_empty());
+    }
+
+    #[test]
+    pub fn test_constant_folding() {
+        let src = "
+            function f(x: i32) -> i32 {
+                block0 {
+                    v0 = i32 21
+                    v1 = add v0, v0
+                    v2 = i32 2147483647
+                    v3 = i32 1
+                    v4 = add v2, v3
+                    v5 = named x: i32
+                    v6 = mul v5, v1
+                    v7 = i32 0
+                    v8 = add v6, v7
+                    v9 = mul v5, v7
+                    v10 = bit_xor v6, v6
+                    v11 = bit_and v6, v6
+                    v12 = test_lt v5, v7
+                    v13 = not v12
+                    v14 = not v13
+                    v15 = bool true
+                    v16 = and v15, v14
+                    v17 = double 1.5
+                    v18 = double 2
+                    v19 = mul v17, v18
+                    v20 = test_gt v17, v18
+                    v21 = div v5, v3
+                    v22 = add v8, v11
+                    v23 = add v22, v21
+                    v24 = double 0
+                    v25 = div v18, v24
+                    v26 = div v24, v24
+                    block0 if v16 {
+                        ret v23
+                    } else {
+                        ret v4
+                    }
+                }
+            }
+
+            function g(x: i32) -> i32 {
+                var i: i32
+
+                block0 {
+                    v0 = named i: i32
+                    v1 = named x: i32
+                    v2 = i32 0
+                    v3 = i32 1
+                    v4 = i32 5
+                    v5 = div v1, v2
+                    set v0, v2
+                    v6 = mul v0, v3
+                    v7 = add v6, v2
+                    v8 = test_lt v7, v4
+                    v9 = add v0, v3
+                    v10 = set v0, v9
+                    v11 = block block0
+                    enter v11
+                    v12 = add v6, v2
+                    ret v12
+                    block0 for _, v8, v10 {
+                    }
+                }
+            }
+        ";
+
+        let original = parse_module(src).unwrap();
+        let mut m = parse_module(src).unwrap();
+
+        let mut manager = PassManager::new();
+        manager.verify = true;
+        manager.add(ConstantFolding);
+        assert_eq!(manager.run(&mut m), Ok(true));
+        assert_eq!(manager.run(&mut m), Ok(false));
+
+        let f = &m.functions["f"];
+        let info = |v: u32| f.values.get(Value(v)).unwrap().clone();
+
+        assert!(matches!(info(1), ValueInfo::IntegerConstant(42)));
+        assert!(matches!(info(4), ValueInfo::IntegerConstant(n) if n as i32 == i32::MIN));
+        assert!(matches!(info(6), ValueInfo::Instruction(_)));
+        assert!(matches!(info(9), ValueInfo::IntegerConstant(0)));
+        assert!(matches!(info(10), ValueInfo::IntegerConstant(0)));
+        assert!(matches!(info(19), ValueInfo::DoubleConstant(n) if n == 3.0));
+        assert!(matches!(info(20), ValueInfo::BooleanConstant(false)));
+        assert!(matches!(info(22), ValueInfo::Instruction(inst) if inst.arguments == vec![Value(6), Value(6)]));
+        assert!(matches!(info(23), ValueInfo::Instruction(inst) if inst.arguments == vec![Value(22), Value(21)]));
+        assert_eq!(f.blocks[0].blocks[0].block_type.values(), vec![Value(12)]);
+
+        // Infinite and NaN results have no constant, so they are left unfolded.
+        assert!(matches!(info(25), ValueInfo::Instruction(_)));
+        assert!(matches!(info(26), ValueInfo::Instruction(_)));
+
+        // Dividing by zero is left for the program to fail on, and loop values are only
+        // forwarded to other loop values.
+        let g = &m.functions["g"];
+        assert!(matches!(g.values.get(Value(5)), Some(ValueInfo::Instruction(_))));
+        assert!(matches!(g.values.get(Value(8)), Some(ValueInfo::Instruction(inst)) if inst.arguments == vec![Value(6), Value(4)]));
+        assert!(matches!(g.values.get(Value(12)), Some(ValueInfo::Instruction(inst)) if inst.arguments == vec![Value(6), Value(2)]));
+
+        assert_eq!(parse_module(&m.to_string()).unwrap().to_string(), m.to_string());
+
+        for x in &[-3, 0, 7] {
+            let expected = Interpreter::new(&original).call("f", &[DataValue::I32(*x)]);
+            assert_eq!(Interpreter::new(&m).call("f", &[DataValue::I32(*x)]), expected);
+        }
+    }
+
+    #[test]
+    pub fn test_dead_code_elimination() {
+        let src = "
+            function f(x: i32) -> i32 {
+                var scratch: i32
+                var total: i32
+
+                block0 {
+                    v0 = named x: i32
+                    v1 = named scratch: i32
+                    v2 = named total: i32
+                    v3 = i32 1
+                    v4 = add v0, v3
+                    set v1, v4
+                    v5 = mul v0, v0
+                    set v2, v0
+                    v6 = block block0
+                    enter v6
+                    v7 = block block3(v2)
+                    jmp v7
+                    block0 {
+                        v8 = named puts: fn(char*) -> i32
+                        v9 = str \"hi\"
+                        v10 = call v8, v9
+                    }
+                }
+
+                block1 {
+                    v11 = i32 5
+                    v12 = block block2
+                    jmp v12
+                }
+
+                block2 {
+                    v13 = named total: i32
+                    ret v13
+                }
+
+                block3 {
+                    v14 = param 0: i32
+                    v15 = i32 2
+                    v16 = add v14, v15
+                    ret v16
+                }
+            }
+
+            function g() {
+                block0 {
+                    ret
+                }
+            }
+        ";
+
+        let original = parse_module(src).unwrap();
+        let mut m = parse_module(src).unwrap();
+
+        let mut manager = PassManager::new();
+        manager.verify = true;
+        manager.add(DeadCodeElimination::new());
+        assert_eq!(manager.run(&mut m), Ok(true));
+
+        let summary = manager.timings()[0].summary.as_deref();
+        assert_eq!(summary, Some("removed 7 values, 1 instruction, 2 blocks and 1 variable"));
+
+        let f = &m.functions["f"];
+        assert_eq!(f.variables.keys().collect::<Vec<_>>(), vec!["total"]);
+        assert_eq!(f.blocks.len(), 2);
+        assert_eq!(f.values.len(), 10);
+
+        let text = m.to_string();
+        assert!(text.contains("        set v1, v0\n        v2 = block block0\n        enter v2\n        v3 = block block1(v1)\n        jmp v3\n"));
+        assert!(text.contains("    block1 {\n        v7 = param 0: i32\n        v8 = i32 2\n        v9 = add v7, v8\n        ret v9\n    }"));
+        assert_eq!(parse_module(&text).unwrap().to_string(), text);
+
+        assert_eq!(manager.run(&mut m), Ok(false));
+        assert_eq!(manager.timings()[0].summary.as_deref(), Some("removed 0 values, 0 instructions, 0 blocks and 0 variables"));
+
+        for module in &[&original, &m] {
+            let mut interpreter = Interpreter::new(module);
+            interpreter.define_host("puts", |_, _| Ok(DataValue::I32(0)));
+            assert_eq!(interpreter.call("f", &[DataValue::I32(40)]), Ok(DataValue::I32(42)));
+        }
+    }
+
+    #[test]
+    pub fn test_dead_code_elimination_keeps_control_flow() {
+        let src = "
+            function f() -> i32 {
+                var i: i32
+
+                block0 {
+                    v0 = named i: i32
+                    v1 = i32 0
+                    set v0, v1
+                    v2 = bool true
+                    v3 = block block0
+                    enter v3
+                    ret v0
+                    block0 while v2 {
+                        v4 = i32 1
+                        v5 = add v0, v4
+                        set v0, v5
+                        v6 = i32 3
+                        v7 = test_gt v0, v6
+                        v8 = test_lt v0, v6
+                        v9 = block block0
+                        enter v9
+                        v10 = block block1
+                        enter v10
+                        block0 if v7 {
+                            break
+                        }
+                        block1 if v8 {
+                            continue
+                        }
+                    }
+                }
+            }
+        ";
+
+        let original = parse_module(src).unwrap();
+        let mut m = parse_module(src).unwrap();
+
+        let mut manager = PassManager::new();
+        manager.verify = true;
+        manager.add(DeadCodeElimination::new());
+        assert_eq!(manager.run(&mut m), Ok(false));
+        assert_eq!(manager.timings()[0].summary.as_deref(), Some("removed 0 values, 0 instructions, 0 blocks and 0 variables"));
+        assert_eq!(m.to_string(), original.to_string());
+
+        for module in &[&original, &m] {
+            let mut interpreter = Interpreter::new(module);
+            interpreter.step_limit = Some(1000);
+            assert_eq!(interpreter.call("f", &[]), Ok(DataValue::I32(4)));
+        }
+    }
+
+    #[test]
+    pub fn test_global_value_numbering() {
+        let src = "
+            global counter: i32 = int 0
+
+            function const square(n: i32) -> i32 {
+                block0 {
+                    v0 = named n: i32
+                    v1 = mul v0, v0
+                    ret v1
+                }
+            }
+
+            function pure peek() -> i32 {
+                block0 {
+                    v0 = named counter: i32
+                    ret v0
+                }
+            }
+
+            function bump() -> i32 {
+                block0 {
+                    v0 = named counter: i32
+                    v1 = i32 1
+                    v2 = add v0, v1
+                    set v0, v2
+                    ret v2
+                }
+            }
+
+            function f(x: i32, y: i32) -> i32 {
+                block0 {
+                    v0 = named x: i32
+                    v1 = named y: i32
+                    v2 = add v0, v1
+                    v3 = add v1, v0
+                    v4 = named counter: i32
+                    set v4, v3
+                    v5 = add v0, v1
+                    v6 = block block1(v0, v5)
+                    jmp v6
+                }
+
+                block1 {
+                    v7 = param 0: i32
+                    v8 = param 1: i32
+                    v9 = mul v7, v8
+                    v10 = named square: fn(i32) -> i32
+                    v11 = call v10, v9
+                    v12 = mul v8, v7
+                    v13 = call v10, v12
+                    v14 = named peek: fn() -> i32
+                    v15 = call v14
+                    v16 = call v14
+                    v17 = named bump: fn() -> i32
+                    v18 = call v17
+                    v19 = call v17
+                    v20 = call v14
+                    v21 = test_gt v7, v8
+                    v29 = i32 1
+                    v22 = block block0
+                    enter v22
+                    v23 = add v11, v13
+                    v24 = add v15, v16
+                    v25 = add v18, v19
+                    v26 = add v23, v24
+                    v27 = add v26, v25
+                    v28 = add v27, v20
+                    v30 = add v7, v29
+                    v31 = add v28, v30
+                    ret v31
+                    block0 if v21 {
+                        v32 = mul v7, v8
+                        v33 = add v7, v29
+                        set v4, v32
+                        set v4, v33
+                    } else {
+                        v34 = add v29, v7
+                        set v4, v34
+                    }
+                }
+            }
+        ";
+
+        let original = parse_module(src).unwrap();
+        let mut m = parse_module(src).unwrap();
+
+        let mut manager = PassManager::new();
+        manager.verify = true;
+        manager.add(GlobalValueNumbering::new());
+        assert_eq!(manager.run(&mut m), Ok(true));
+
+        let text = m.functions["f"].to_string();
+
+        // Commutative operands are sorted, but reads of named references aren't reused after a
+        // `set` that may change them.
+        assert!(text.contains("        set v4, v2\n        v5 = add v0, v1\n        v6 = block block1(v0, v5)\n"));
+
+        // Calls to `const` and `pure` functions are merged, unless a call that may write to
+        // memory comes between `pure` ones.
+        assert!(text.contains("        v13 = call v10, v9\n"));
+        assert!(text.contains("        v23 = add v11, v11\n        v24 = add v15, v15\n        v25 = add v18, v19\n"));
+        assert!(text.contains("        v28 = add v27, v20\n        v30 = add v7, v29\n"));
+
+        // Values from the enclosing block are reused in nested blocks, but not between the
+        // branches of an `if` or after them.
+        assert!(text.contains("            v33 = add v7, v29\n            set v4, v9\n            set v4, v33\n"));
+        assert!(text.contains("            v34 = add v29, v7\n            set v4, v34\n"));
+
+        manager.add(DeadCodeElimination::new());
+        assert_eq!(manager.run(&mut m), Ok(true));
+        assert!(!manager.timings()[0].changed);
+        assert_eq!(manager.timings()[1].summary.as_deref(), Some("removed 5 values, 0 instructions, 0 blocks and 0 variables"));
+
+        let text = m.to_string();
+        assert!(text.contains("function const square(n: i32) -> i32 {"));
+        assert!(text.contains("function pure peek() -> i32 {"));
+        assert!(text.contains("        v10 = call v9, v8\n        v11 = named peek: fn() -> i32\n        v12 = call v11\n        v13 = named bump: fn() -> i32\n"));
+        assert_eq!(parse_module(&text).unwrap().to_string(), text);
+
+        for module in &[&original, &m] {
+            let mut interpreter = Interpreter::new(module);
+            assert_eq!(interpreter.call("f", &[DataValue::I32(2), DataValue::I32(3)]), Ok(DataValue::I32(233)));
+            assert_eq!(interpreter.global("counter"), Ok(DataValue::I32(3)));
+
+            let mut interpreter = Interpreter::new(module);
+            assert_eq!(interpreter.call("f", &[DataValue::I32(5), DataValue::I32(-4)]), Ok(DataValue::I32(66)));
+            assert_eq!(interpreter.global("counter"), Ok(DataValue::I32(6)));
+        }
+    }
+
+}